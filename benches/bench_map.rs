@@ -1,10 +1,42 @@
 #![feature(test)]
 extern crate test;
 
-use dark_std::sync::SyncHashMap;
+use dark_std::sync::{CachePolicy, ShardedSyncHashMap, SyncHashMap, SyncLruCache};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
+/// the hasher of rustc (FxHash), much faster than SipHash on small keys but not HashDoS resistant
+#[derive(Default)]
+struct FxHasher {
+    hash: u64,
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(8) {
+            let mut b = [0u8; 8];
+            b[..chunk.len()].copy_from_slice(chunk);
+            self.write_u64(u64::from_le_bytes(b));
+        }
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ i).wrapping_mul(0x51_7c_c1_b7_27_22_0a_95);
+    }
+
+    fn write_i32(&mut self, i: i32) {
+        self.write_u64(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+type FxBuildHasher = BuildHasherDefault<FxHasher>;
+
 //6 ns/iter (+/- 0)
 #[bench]
 fn bench_sync_map_get(b: &mut test::Bencher) {
@@ -74,3 +106,141 @@ fn bench_sync_map_insert_race(b: &mut test::Bencher) {
 //         rw.insert(1,1);
 //     });
 // }
+
+//12 ns/iter (+/- 0)
+#[bench]
+fn bench_fx_sync_map_get(b: &mut test::Bencher) {
+    let rw = SyncHashMap::with_hasher(FxBuildHasher::default());
+    rw.insert(1, 1);
+    assert_eq!(rw.len(), 1);
+    b.iter(|| {
+        rw.get(&1);
+    });
+}
+
+//111 ns/iter (+/- 2)
+#[bench]
+fn bench_fx_sync_map_insert(b: &mut test::Bencher) {
+    let rw = SyncHashMap::with_hasher(FxBuildHasher::default());
+    b.iter(|| {
+        rw.insert(1, 1);
+    });
+}
+
+//15 ns/iter (+/- 0)
+#[bench]
+fn bench_sharded_map_get(b: &mut test::Bencher) {
+    let rw = ShardedSyncHashMap::new();
+    rw.insert(1, 1);
+    assert_eq!(rw.len(), 1);
+    b.iter(|| {
+        rw.get(&1);
+    });
+}
+
+//107 ns/iter (+/- 48)
+#[bench]
+fn bench_sharded_map_insert(b: &mut test::Bencher) {
+    let rw = ShardedSyncHashMap::new();
+    b.iter(|| {
+        rw.insert(1, 1);
+    });
+}
+
+#[bench]
+fn bench_fx_sharded_map_get(b: &mut test::Bencher) {
+    let rw = ShardedSyncHashMap::with_hasher(FxBuildHasher::default());
+    rw.insert(1, 1);
+    assert_eq!(rw.len(), 1);
+    b.iter(|| {
+        rw.get(&1);
+    });
+}
+
+/// writers keep updating other keys while the bench thread inserts
+fn bench_write_heavy<F>(b: &mut test::Bencher, insert: F)
+where
+    F: Fn(i32) + Send + Sync + 'static,
+{
+    let insert = Arc::new(insert);
+    let stop = Arc::new(AtomicBool::new(false));
+    let mut writers = vec![];
+    for t in 1..4 {
+        let insert = insert.clone();
+        let stop = stop.clone();
+        writers.push(std::thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::Relaxed) {
+                insert(t * 1000 + i % 1000);
+                i += 1;
+            }
+        }));
+    }
+    let mut i = 0;
+    b.iter(|| {
+        insert(i % 1000);
+        i += 1;
+    });
+    stop.store(true, Ordering::Relaxed);
+    for w in writers {
+        w.join().unwrap();
+    }
+}
+
+//473 ns/iter (+/- 761)
+#[bench]
+fn bench_sync_map_insert_write_heavy(b: &mut test::Bencher) {
+    let rw = SyncHashMap::new();
+    bench_write_heavy(b, move |i| {
+        rw.insert(i, i);
+    });
+}
+
+//538 ns/iter (+/- 537)
+#[bench]
+fn bench_fx_sync_map_insert_write_heavy(b: &mut test::Bencher) {
+    let rw = SyncHashMap::with_hasher(FxBuildHasher::default());
+    bench_write_heavy(b, move |i| {
+        rw.insert(i, i);
+    });
+}
+
+//218 ns/iter (+/- 941)
+#[bench]
+fn bench_sharded_map_insert_write_heavy(b: &mut test::Bencher) {
+    let rw = ShardedSyncHashMap::new();
+    bench_write_heavy(b, move |i| {
+        rw.insert(i, i);
+    });
+}
+
+//33 ns/iter (+/- 0)
+#[bench]
+fn bench_lru_cache_get(b: &mut test::Bencher) {
+    let rw = SyncLruCache::new(1024);
+    rw.insert(1, 1);
+    b.iter(|| {
+        rw.get(&1);
+    });
+}
+
+//53 ns/iter (+/- 2)
+#[bench]
+fn bench_tiny_lfu_cache_get(b: &mut test::Bencher) {
+    let rw = SyncLruCache::with_policy(1024, CachePolicy::TinyLfu);
+    rw.insert(1, 1);
+    b.iter(|| {
+        rw.get(&1);
+    });
+}
+
+//453 ns/iter (+/- 17)
+#[bench]
+fn bench_lru_cache_insert(b: &mut test::Bencher) {
+    let rw = SyncLruCache::new(1024);
+    let mut i = 0;
+    b.iter(|| {
+        i += 1;
+        rw.insert(i, i);
+    });
+}
@@ -0,0 +1,505 @@
+use crate::sync::{HashMapRef, HashRefIter, SyncHashMap};
+use atomic_shim::AtomicU64;
+use parking_lot::Mutex;
+use serde::{Deserializer, Serialize, Serializer};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap as Map};
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, Hash};
+use std::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// which entry [`SyncLruCache`] evicts once it is full
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CachePolicy {
+    /// the least recently used
+    Lru,
+    /// the least frequently used, ties broken by recency.
+    /// the entry being inserted is never the one evicted to make room for it.
+    Lfu,
+    /// W-TinyLFU: new entries enter a small LRU window (1% of the capacity),
+    /// leaving it they must be estimated more frequent than the LRU victim of the main space to stay.
+    TinyLfu,
+}
+
+/// a bounded cache on top of [`SyncHashMap`].
+///
+/// hits are as unlocked as `SyncHashMap::get`, they only bump the atomic counters of the entry.
+/// writers file entries in an ordered index and evict the lowest ranked one when the cache is full,
+/// an entry hit since it was filed is ranked again instead of evicted.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncLruCache;
+///
+/// let cache = SyncLruCache::new(2);
+/// cache.insert(1, "a");
+/// cache.insert(2, "b");
+/// cache.get(&1);
+/// cache.insert(3, "c");
+/// assert_eq!(cache.get(&2).is_none(), true);
+/// assert_eq!(*cache.get_or_insert_with(1, || "x"), "a");
+/// assert_eq!((cache.hits(), cache.misses()), (2, 1));
+/// ```
+pub struct SyncLruCache<K: Eq + Hash, V> {
+    map: SyncHashMap<K, CacheValue<V>>,
+    index: Mutex<CacheIndex<K>>,
+    capacity: usize,
+    window: usize,
+    policy: CachePolicy,
+    clock: AtomicU64,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    sketch: Option<FrequencySketch>,
+    on_evict: Option<EvictFn<K, V>>,
+}
+
+type EvictFn<K, V> = Box<dyn Fn(&K, &V) + Send + Sync>;
+
+type Rank = (u64, u64);
+
+struct CacheValue<V> {
+    value: V,
+    /// tick of the last access
+    stamp: AtomicU64,
+    hits: AtomicU64,
+    /// where the key is filed in the index, only touched under the index lock
+    rank: (AtomicU64, AtomicU64),
+    main: AtomicBool,
+}
+
+impl<V> CacheValue<V> {
+    fn new(value: V, tick: u64) -> Self {
+        Self {
+            value,
+            stamp: AtomicU64::new(tick),
+            hits: AtomicU64::new(0),
+            rank: (AtomicU64::new(0), AtomicU64::new(0)),
+            main: AtomicBool::new(false),
+        }
+    }
+
+    fn touch(&self, tick: u64) {
+        self.stamp.store(tick, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn rank(&self, policy: CachePolicy) -> Rank {
+        let stamp = self.stamp.load(Ordering::Relaxed);
+        match policy {
+            CachePolicy::Lfu => (self.hits.load(Ordering::Relaxed), stamp),
+            _ => (0, stamp),
+        }
+    }
+
+    fn filed(&self) -> Rank {
+        (
+            self.rank.0.load(Ordering::Relaxed),
+            self.rank.1.load(Ordering::Relaxed),
+        )
+    }
+
+    fn file(&self, rank: Rank, main: bool) {
+        self.rank.0.store(rank.0, Ordering::Relaxed);
+        self.rank.1.store(rank.1, Ordering::Relaxed);
+        self.main.store(main, Ordering::Relaxed);
+    }
+}
+
+struct CacheIndex<K> {
+    window: BTreeMap<Rank, K>,
+    main: BTreeMap<Rank, K>,
+}
+
+impl<K> CacheIndex<K> {
+    fn segment(&mut self, main: bool) -> &mut BTreeMap<Rank, K> {
+        if main {
+            &mut self.main
+        } else {
+            &mut self.window
+        }
+    }
+}
+
+impl<K, V> SyncLruCache<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    pub fn new_arc(capacity: usize) -> Arc<Self> {
+        Arc::new(Self::new(capacity))
+    }
+
+    /// a LRU cache, `capacity` is at least 1
+    pub fn new(capacity: usize) -> Self {
+        Self::with_policy(capacity, CachePolicy::Lru)
+    }
+
+    pub fn with_policy(capacity: usize, policy: CachePolicy) -> Self {
+        let capacity = capacity.max(1);
+        let (window, sketch) = match policy {
+            CachePolicy::TinyLfu => (
+                (capacity / 100).max(1),
+                Some(FrequencySketch::new(capacity)),
+            ),
+            _ => (0, None),
+        };
+        Self {
+            map: SyncHashMap::with_capacity(capacity),
+            index: Mutex::new(CacheIndex {
+                window: BTreeMap::new(),
+                main: BTreeMap::new(),
+            }),
+            capacity,
+            window,
+            policy,
+            clock: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            sketch,
+            on_evict: None,
+        }
+    }
+
+    /// `f` is called with every evicted entry, outside of the cache lock
+    pub fn on_evict<F>(mut self, f: F) -> Self
+        where
+            F: Fn(&K, &V) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+        self
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn policy(&self) -> CachePolicy {
+        self.policy
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// counts a hit or a miss, a hit marks the entry as used
+    pub fn get(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        if let Some(s) = &self.sketch {
+            s.increment(k);
+        }
+        match self.map.get(k) {
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Some(v) => {
+                v.touch(self.clock.fetch_add(1, Ordering::Relaxed));
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                Some(HashMapRef::map(v, |v| &v.value))
+            }
+        }
+    }
+
+    /// like `get`, but neither counted nor marked as used
+    pub fn peek(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        Some(HashMapRef::map(self.map.get(k)?, |v| &v.value))
+    }
+
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.map.contains_key(k)
+    }
+
+    /// the replaced value stays readable through the returned guard
+    pub fn insert(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        if let Some(s) = &self.sketch {
+            s.increment(&k);
+        }
+        let mut index = self.index.lock();
+        let old = self.insert_locked(&mut index, k.clone(), v);
+        let evicted = self.evict_locked(&mut index, &k);
+        drop(index);
+        self.notify(evicted);
+        old
+    }
+
+    /// `f` runs under the cache lock, so it is called at most once per missing key.
+    /// it must not write to this cache.
+    pub fn get_or_insert_with<F>(&self, k: K, f: F) -> HashMapRef<'_, V>
+        where
+            F: FnOnce() -> V,
+    {
+        if let Some(v) = self.get(&k) {
+            return v;
+        }
+        let mut index = self.index.lock();
+        if let Some(v) = self.map.get(&k) {
+            return HashMapRef::map(v, |v| &v.value);
+        }
+        self.insert_locked(&mut index, k.clone(), f());
+        // pinned before the eviction, so the value stays readable even if it does not stay cached
+        let v = self.map.get(&k).expect("inserted key not found");
+        let evicted = self.evict_locked(&mut index, &k);
+        drop(index);
+        self.notify(evicted);
+        HashMapRef::map(v, |v| &v.value)
+    }
+
+    pub fn remove(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        let mut index = self.index.lock();
+        let old = self.map.remove(k)?;
+        index.segment(old.main.load(Ordering::Relaxed)).remove(&old.filed());
+        drop(index);
+        Some(HashMapRef::map(old, |v| &v.value))
+    }
+
+    pub fn clear(&self) {
+        let mut index = self.index.lock();
+        index.window.clear();
+        index.main.clear();
+        self.map.clear();
+    }
+
+    pub fn iter(&self) -> CacheIter<'_, K, V> {
+        CacheIter {
+            inner: self.map.iter(),
+        }
+    }
+
+    fn insert_locked(&self, index: &mut CacheIndex<K>, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        let v = CacheValue::new(v, self.clock.fetch_add(1, Ordering::Relaxed));
+        // without a window new entries go to the main space directly, an updated key keeps its segment
+        let main = self.sketch.is_none()
+            || self.map.get(&k).is_some_and(|old| old.main.load(Ordering::Relaxed));
+        let rank = v.rank(self.policy);
+        v.file(rank, main);
+        let old = self.map.insert(k.clone(), v);
+        if let Some(old) = &old {
+            index.segment(old.main.load(Ordering::Relaxed)).remove(&old.filed());
+        }
+        index.segment(main).insert(rank, k);
+        Some(HashMapRef::map(old?, |v| &v.value))
+    }
+
+    /// unfile the lowest ranked key of a segment, keys used since they were filed are filed again
+    fn pop_locked(&self, index: &mut CacheIndex<K>, main: bool) -> Option<K> {
+        let segment = index.segment(main);
+        loop {
+            let (rank, k) = segment.pop_first()?;
+            let v = match self.map.get(&k) {
+                None => continue,
+                Some(v) => v,
+            };
+            let now = v.rank(self.policy);
+            if now == rank {
+                return Some(k);
+            }
+            v.file(now, main);
+            segment.insert(now, k);
+        }
+    }
+
+    fn file_locked(&self, index: &mut CacheIndex<K>, k: K, main: bool) {
+        if let Some(v) = self.map.get(&k) {
+            let rank = v.rank(self.policy);
+            v.file(rank, main);
+            index.segment(main).insert(rank, k);
+        }
+    }
+
+    /// make room after `admitted` was inserted
+    fn evict_locked(&self, index: &mut CacheIndex<K>, admitted: &K) -> Vec<(K, HashMapRef<'_, V>)> {
+        let mut evicted = vec![];
+        match &self.sketch {
+            None => {
+                // a new key ranks the lowest under LFU, it must not be evicted in place of an old one
+                let mut skipped = None;
+                while self.map.len() > self.capacity {
+                    match self.pop_locked(index, true) {
+                        None => break,
+                        Some(k) if k == *admitted => skipped = Some(k),
+                        Some(k) => evicted.extend(self.remove_evicted(k)),
+                    }
+                }
+                if let Some(k) = skipped {
+                    self.file_locked(index, k, true);
+                }
+            }
+            Some(sketch) => {
+                while index.window.len() > self.window {
+                    let candidate = match self.pop_locked(index, false) {
+                        None => break,
+                        Some(k) => k,
+                    };
+                    if index.main.len() < self.capacity - self.window {
+                        self.file_locked(index, candidate, true);
+                        continue;
+                    }
+                    let victim = match self.pop_locked(index, true) {
+                        // the window takes the whole capacity
+                        None => {
+                            evicted.extend(self.remove_evicted(candidate));
+                            continue;
+                        }
+                        Some(k) => k,
+                    };
+                    let (loser, winner) = if sketch.estimate(&candidate) > sketch.estimate(&victim) {
+                        (victim, candidate)
+                    } else {
+                        (candidate, victim)
+                    };
+                    self.file_locked(index, winner, true);
+                    evicted.extend(self.remove_evicted(loser));
+                }
+            }
+        }
+        evicted
+    }
+
+    fn remove_evicted(&self, k: K) -> Option<(K, HashMapRef<'_, V>)> {
+        let v = self.map.remove(&k)?;
+        Some((k, HashMapRef::map(v, |v| &v.value)))
+    }
+
+    fn notify(&self, evicted: Vec<(K, HashMapRef<'_, V>)>) {
+        if let Some(f) = &self.on_evict {
+            for (k, v) in evicted {
+                f(&k, &v);
+            }
+        }
+    }
+}
+
+/// a count-min sketch of 4 bit counters, 4 rows of `4 * capacity` counters halved every `10 * capacity` increments
+struct FrequencySketch {
+    table: Box<[AtomicU8]>,
+    width: usize,
+    additions: AtomicUsize,
+    sample: usize,
+    hasher: RandomState,
+}
+
+const SKETCH_SEEDS: [u64; 4] = [
+    0xc3a5c85c97cb3127,
+    0xb492b66fbe98f273,
+    0x9ae16a3b2f90404f,
+    0xcbf29ce484222325,
+];
+
+impl FrequencySketch {
+    fn new(capacity: usize) -> Self {
+        let width = capacity.saturating_mul(4).next_power_of_two().max(64);
+        Self {
+            table: (0..width * SKETCH_SEEDS.len()).map(|_| AtomicU8::new(0)).collect(),
+            width,
+            additions: AtomicUsize::new(0),
+            sample: capacity.saturating_mul(10),
+            hasher: RandomState::new(),
+        }
+    }
+
+    fn slots<K: Hash>(&self, k: &K) -> impl Iterator<Item = &AtomicU8> {
+        let h = self.hasher.hash_one(k);
+        SKETCH_SEEDS.iter().enumerate().map(move |(i, seed)| {
+            let slot = (h.wrapping_mul(*seed) >> 32) as usize & (self.width - 1);
+            &self.table[i * self.width + slot]
+        })
+    }
+
+    fn increment<K: Hash>(&self, k: &K) {
+        for c in self.slots(k) {
+            let _ = c.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| {
+                if c < 15 {
+                    Some(c + 1)
+                } else {
+                    None
+                }
+            });
+        }
+        // only the thread hitting the sample size ages the counters
+        if self.additions.fetch_add(1, Ordering::Relaxed) + 1 == self.sample {
+            for c in self.table.iter() {
+                let _ = c.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| Some(c >> 1));
+            }
+            self.additions.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn estimate<K: Hash>(&self, k: &K) -> u8 {
+        self.slots(k)
+            .map(|c| c.load(Ordering::Relaxed))
+            .min()
+            .unwrap_or_default()
+    }
+}
+
+pub struct CacheIter<'a, K, V> {
+    inner: HashRefIter<'a, K, CacheValue<V>>,
+}
+
+impl<'a, K, V> Iterator for CacheIter<'a, K, V> {
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        Some((k, HashMapRef::map(v, |v| &v.value)))
+    }
+}
+
+impl<K, V> Serialize for SyncLruCache<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Serialize,
+        V: Send + 'static + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        // like SyncHashMap, collect the entries first so formats needing the length work
+        let pin = self.map.pin();
+        let entries: Vec<(&K, &V)> = pin.iter().map(|(k, v)| (k, &v.value)).collect();
+        serializer.collect_map(entries)
+    }
+}
+
+/// the capacity is the number of entries, the policy is LRU
+impl<'de, K, V> serde::Deserialize<'de> for SyncLruCache<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + serde::Deserialize<'de>,
+        V: Send + 'static + serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let m = Map::<K, V>::deserialize(deserializer)?;
+        let s = Self::new(m.len());
+        for (k, v) in m {
+            s.insert(k, v);
+        }
+        Ok(s)
+    }
+}
+
+impl<K, V> Debug for SyncLruCache<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,122 @@
+use atomic_shim::AtomicU64;
+use flume::{Receiver, TrySendError};
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// a change of a map, see `SyncHashMap::subscribe` and `SyncBtreeMap::subscribe`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapEvent<K, V> {
+    Inserted { key: K, old: Option<V>, new: V },
+    Removed { key: K, value: V },
+    Cleared,
+    /// this many events were dropped because the subscriber was full
+    Lagged(u64),
+}
+
+/// a change borrowed from inside the writer lock
+pub(crate) enum MapChange<'a, K, V> {
+    Inserted {
+        key: &'a K,
+        old: Option<&'a V>,
+        new: &'a V,
+    },
+    Removed {
+        key: &'a K,
+        value: &'a V,
+    },
+    Cleared,
+}
+
+impl<K: Clone, V: Clone> MapChange<'_, K, V> {
+    fn to_event(&self) -> MapEvent<K, V> {
+        match self {
+            MapChange::Inserted { key, old, new } => MapEvent::Inserted {
+                key: (*key).clone(),
+                old: old.cloned(),
+                new: (*new).clone(),
+            },
+            MapChange::Removed { key, value } => MapEvent::Removed {
+                key: (*key).clone(),
+                value: (*value).clone(),
+            },
+            MapChange::Cleared => MapEvent::Cleared,
+        }
+    }
+}
+
+/// returns false once the subscriber is gone
+type Subscriber<K, V> = Box<dyn Fn(&MapChange<'_, K, V>) -> bool + Send + Sync>;
+
+/// the subscribers of a map, changes are emitted under the writer lock of the map,
+/// so every subscriber sees them in mutation order.
+pub(crate) struct MapEvents<K, V> {
+    len: AtomicUsize,
+    subscribers: Mutex<Vec<Subscriber<K, V>>>,
+}
+
+impl<K, V> MapEvents<K, V> {
+    pub fn new() -> Self {
+        Self {
+            len: AtomicUsize::new(0),
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    /// no one listens
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Acquire) == 0
+    }
+
+    /// `change` is only built when someone listens
+    #[inline]
+    pub fn emit<'a, F>(&self, change: F)
+        where
+            F: FnOnce() -> MapChange<'a, K, V>,
+            K: 'a,
+            V: 'a,
+    {
+        if self.len.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        let change = change();
+        let mut subscribers = self.subscribers.lock();
+        subscribers.retain(|s| s(&change));
+        self.len.store(subscribers.len(), Ordering::Release);
+    }
+
+    /// a subscriber more than `capacity` events behind misses the next ones,
+    /// it receives a [`MapEvent::Lagged`] with their count once it catches up.
+    pub fn subscribe(&self, capacity: usize) -> Receiver<MapEvent<K, V>>
+        where
+            K: Clone + Send + 'static,
+            V: Clone + Send + 'static,
+    {
+        let (send, recv) = flume::bounded(capacity.max(1));
+        let lagged = AtomicU64::new(0);
+        let mut subscribers = self.subscribers.lock();
+        subscribers.push(Box::new(move |change| {
+            let missed = lagged.load(Ordering::Relaxed);
+            if missed > 0 {
+                match send.try_send(MapEvent::Lagged(missed)) {
+                    Ok(_) => lagged.store(0, Ordering::Relaxed),
+                    Err(TrySendError::Full(_)) => {
+                        lagged.store(missed + 1, Ordering::Relaxed);
+                        return true;
+                    }
+                    Err(TrySendError::Disconnected(_)) => return false,
+                }
+            }
+            match send.try_send(change.to_event()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    lagged.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        }));
+        self.len.store(subscribers.len(), Ordering::Release);
+        recv
+    }
+}
@@ -0,0 +1,266 @@
+use parking_lot::{Condvar, Mutex};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::thread::ThreadId;
+
+/// the writer lock of the sync containers, it can be taken by a thread or by an async task.
+///
+/// * [`WriteLock::lock`] blocks the thread, the guard is `!Send`. it is not reentrant:
+///   the containers borrow their data under the guard, so taking the lock again on the
+///   thread holding it panics instead of letting the nested write alias that borrow.
+/// * [`WriteLock::lock_async`] awaits without blocking the executor, the guard is `Send`
+///   and may be held across `.await`. it is not reentrant, taking the lock again (blocking or not)
+///   while the guard is alive deadlocks the task, just like `tokio::sync::Mutex`.
+/// * [`WriteLock::try_lock`] never waits, it fails while any thread (this one included) or task holds the lock.
+///
+/// waiters are served in arrival order, blocking and async alike.
+pub struct WriteLock {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Owner {
+    Free,
+    Thread(ThreadId),
+    Task,
+}
+
+struct Waiter {
+    id: u64,
+    /// `None` for a parked thread
+    waker: Option<Waker>,
+}
+
+struct State {
+    owner: Owner,
+    count: usize,
+    queue: VecDeque<Waiter>,
+    next_id: u64,
+    /// bumped by every acquisition, see `SnapshotCache`
+    generation: u64,
+}
+
+impl State {
+    fn enqueue(&mut self, waker: Option<Waker>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.queue.push_back(Waiter { id, waker });
+        id
+    }
+
+    /// the lock is free and `id` is the first in line
+    fn is_turn(&self, id: u64) -> bool {
+        self.owner == Owner::Free && self.queue.front().map(|w| w.id) == Some(id)
+    }
+
+    fn acquire(&mut self, owner: Owner) {
+        self.owner = owner;
+        self.count = 1;
+        self.generation += 1;
+    }
+}
+
+/// guard of a lock taken by [`WriteLock::lock`]
+pub struct Blocking;
+
+/// guard of a lock taken by [`WriteLock::lock_async`]
+pub struct Async;
+
+impl WriteLock {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                owner: Owner::Free,
+                count: 0,
+                queue: VecDeque::new(),
+                next_id: 0,
+                generation: 0,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// block the current thread until the lock is acquired.
+    ///
+    /// # Panics
+    ///
+    /// if the current thread already holds the lock
+    pub fn lock(&self) -> WriteGuard<'_, Blocking> {
+        let me = Owner::Thread(std::thread::current().id());
+        let mut s = self.state.lock();
+        if s.owner == me {
+            drop(s);
+            panic!("WriteLock is not reentrant, the current thread already holds it");
+        } else if s.owner == Owner::Free && s.queue.is_empty() {
+            s.acquire(me);
+        } else {
+            let id = s.enqueue(None);
+            while !s.is_turn(id) {
+                self.cond.wait(&mut s);
+            }
+            s.queue.pop_front();
+            s.acquire(me);
+        }
+        WriteGuard::new(self)
+    }
+
+    /// acquire the lock if it is free, without waiting
+    pub fn try_lock(&self) -> Option<WriteGuard<'_, Blocking>> {
+        let me = Owner::Thread(std::thread::current().id());
+        let mut s = self.state.lock();
+        if s.owner != Owner::Free || !s.queue.is_empty() {
+            return None;
+        }
+        s.acquire(me);
+        Some(WriteGuard::new(self))
+    }
+
+    /// acquire the lock without blocking the executor
+    pub fn lock_async(&self) -> WriteLockFuture<'_> {
+        WriteLockFuture { lock: self, id: None }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.state.lock().owner != Owner::Free
+    }
+
+    /// the lock is held by [`WriteLock::lock`] on the current thread
+    pub(crate) fn held_by_current_thread(&self) -> bool {
+        self.state.lock().owner == Owner::Thread(std::thread::current().id())
+    }
+
+    /// the acquisitions so far, `None` while the lock is held
+    pub(crate) fn idle_generation(&self) -> Option<u64> {
+        let s = self.state.lock();
+        match s.owner {
+            Owner::Free => Some(s.generation),
+            _ => None,
+        }
+    }
+
+    /// count a write made through `&mut` access, which does not take the lock
+    pub(crate) fn touch(&mut self) {
+        self.state.get_mut().generation += 1;
+    }
+
+    fn unlock(&self) {
+        let mut s = self.state.lock();
+        s.count -= 1;
+        if s.count == 0 {
+            s.owner = Owner::Free;
+            self.wake_front(&s);
+        }
+    }
+
+    fn wake_front(&self, s: &State) {
+        match s.queue.front() {
+            None => {}
+            Some(Waiter { waker: Some(w), .. }) => w.wake_by_ref(),
+            Some(Waiter { waker: None, .. }) => {
+                self.cond.notify_all();
+            }
+        }
+    }
+}
+
+impl Default for WriteLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// the future returned by [`WriteLock::lock_async`]
+pub struct WriteLockFuture<'a> {
+    lock: &'a WriteLock,
+    id: Option<u64>,
+}
+
+impl<'a> Future for WriteLockFuture<'a> {
+    type Output = WriteGuard<'a, Async>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let lock = self.lock;
+        let mut s = lock.state.lock();
+        match self.id {
+            None => {
+                if s.owner == Owner::Free && s.queue.is_empty() {
+                    s.acquire(Owner::Task);
+                    return Poll::Ready(WriteGuard::new(lock));
+                }
+                self.id = Some(s.enqueue(Some(cx.waker().clone())));
+                Poll::Pending
+            }
+            Some(id) => {
+                if s.is_turn(id) {
+                    s.queue.pop_front();
+                    s.acquire(Owner::Task);
+                    self.id = None;
+                    return Poll::Ready(WriteGuard::new(lock));
+                }
+                if let Some(w) = s.queue.iter_mut().find(|w| w.id == id) {
+                    if !w.waker.as_ref().map(|w| w.will_wake(cx.waker())).unwrap_or_default() {
+                        w.waker = Some(cx.waker().clone());
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for WriteLockFuture<'_> {
+    fn drop(&mut self) {
+        // cancelled while waiting, give our turn to the next one
+        if let Some(id) = self.id {
+            let mut s = self.lock.state.lock();
+            let first = s.queue.front().map(|w| w.id) == Some(id);
+            s.queue.retain(|w| w.id != id);
+            if first && s.owner == Owner::Free {
+                self.lock.wake_front(&s);
+            }
+        }
+    }
+}
+
+/// the lock is released when the guard drops.
+///
+/// `WriteGuard<Blocking>` is bound to the thread that took it, `WriteGuard<Async>` is `Send`.
+pub struct WriteGuard<'a, M = Blocking> {
+    lock: &'a WriteLock,
+    _m: PhantomData<(M, *const ())>,
+}
+
+/// the owner of an async guard is the task, not the thread
+unsafe impl Send for WriteGuard<'_, Async> {}
+
+unsafe impl Sync for WriteGuard<'_, Async> {}
+
+impl<'a, M> WriteGuard<'a, M> {
+    fn new(lock: &'a WriteLock) -> Self {
+        Self {
+            lock,
+            _m: PhantomData,
+        }
+    }
+
+    /// one more guard of the same holder, the lock is released after both drop
+    pub(crate) fn fork(&self) -> Self {
+        self.lock.state.lock().count += 1;
+        Self::new(self.lock)
+    }
+
+    /// the acquisition this guard belongs to
+    pub(crate) fn generation(&self) -> u64 {
+        self.lock.state.lock().generation
+    }
+}
+
+impl<M> Drop for WriteGuard<'_, M> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
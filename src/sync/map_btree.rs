@@ -1,39 +1,50 @@
-use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use crate::sync::{
+    Async, Blocking, MapChange, MapEvent, MapEvents, Snapshot, SnapshotCache, WriteGuard, WriteLock,
+};
+use crate::sync::persist::{self, Kind};
+use serde::de::DeserializeOwned;
 use serde::{Deserializer, Serialize, Serializer};
 use std::borrow::Borrow;
 use std::cell::UnsafeCell;
-use std::collections::{btree_map::IntoIter as MapIntoIter, btree_map::Iter as MapIter, BTreeMap};
+use std::collections::{
+    btree_map::Entry as MapEntry, btree_map::IntoIter as MapIntoIter, btree_map::Iter as MapIter,
+    btree_map::OccupiedEntry as MapOccupiedEntry, btree_map::Range as MapRange,
+    btree_map::RangeMut as MapRangeMut, btree_map::VacantEntry as MapVacantEntry, BTreeMap,
+};
 use std::fmt::{Debug, Display, Formatter};
-use std::hash::Hash;
-use std::ops::{Deref, DerefMut};
+use std::ops::{Bound, Deref, DerefMut, RangeBounds};
+use std::path::Path;
 use std::sync::Arc;
 
 /// this sync map used to many reader,writer less.space-for-time strategy
-pub struct SyncBtreeMap<K: Eq + Hash, V> {
+pub struct SyncBtreeMap<K, V> {
     dirty: UnsafeCell<BTreeMap<K, V>>,
-    lock: ReentrantMutex<()>,
+    lock: WriteLock,
+    events: MapEvents<K, V>,
+    snapshots: SnapshotCache<BTreeMap<K, V>>,
 }
 
 /// this is safety, dirty mutex ensure
-unsafe impl<K: Eq + Hash, V> Send for SyncBtreeMap<K, V> {}
+unsafe impl<K: Send, V: Send> Send for SyncBtreeMap<K, V> {}
 
 /// this is safety, dirty mutex ensure
-unsafe impl<K: Eq + Hash, V> Sync for SyncBtreeMap<K, V> {}
+unsafe impl<K: Send + Sync, V: Send + Sync> Sync for SyncBtreeMap<K, V> {}
 
-impl<K, V> std::ops::Index<&K> for SyncBtreeMap<K, V>
+impl<K, Q, V> std::ops::Index<&Q> for SyncBtreeMap<K, V>
     where
-        K: Eq + Hash + Ord,
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
 {
     type Output = V;
 
-    fn index(&self, index: &K) -> &Self::Output {
+    fn index(&self, index: &Q) -> &Self::Output {
         unsafe { &(&*self.dirty.get())[index] }
     }
 }
 
-impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
+impl<K, V> SyncBtreeMap<K, V>
     where
-        K: Eq + Hash,
+        K: Ord,
 {
     pub fn new_arc() -> Arc<Self> {
         Arc::new(Self::new())
@@ -43,6 +54,8 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
         Self {
             dirty: UnsafeCell::new(BTreeMap::new()),
             lock: Default::default(),
+            events: MapEvents::new(),
+            snapshots: SnapshotCache::new(),
         }
     }
 
@@ -54,45 +67,124 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
         Self {
             dirty: UnsafeCell::new(map),
             lock: Default::default(),
+            events: MapEvents::new(),
+            snapshots: SnapshotCache::new(),
         }
     }
 
-    pub fn insert(&self, k: K, v: V) -> Option<V>
+    pub fn insert(&self, k: K, v: V) -> Option<V> {
+        let g = self.lock.lock();
+        let r = self.insert_locked(k, v);
+        drop(g);
+        r
+    }
+
+    pub fn insert_mut(&mut self, k: K, v: V) -> Option<V> {
+        self.lock.touch();
+        self.insert_locked(k, v)
+    }
+
+    pub fn remove<Q>(&self, k: &Q) -> Option<V>
         where
-            K: Ord,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
     {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        let r = m.insert(k, v);
+        let r = self.remove_locked(k);
         drop(g);
         r
     }
 
-    pub fn insert_mut(&mut self, k: K, v: V) -> Option<V>
+    pub fn remove_mut<Q>(&mut self, k: &Q) -> Option<V>
         where
-            K: Ord,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
     {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.insert(k, v)
+        self.lock.touch();
+        self.remove_locked(k)
     }
 
-    pub fn remove(&self, k: &K) -> Option<V>
+    /// Insert every entry under one acquisition of the writer lock, returns how many keys were new.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// let map = SyncBtreeMap::new();
+    /// assert_eq!(map.insert_many((0..10).map(|i| (i, i))), 10);
+    /// assert_eq!(map.remove_many(&[0, 1, 100]), 2);
+    /// let odd = map.extract_if(|_, v| *v % 2 == 1);
+    /// assert_eq!(odd.keys().copied().collect::<Vec<_>>(), vec![3, 5, 7, 9]);
+    /// assert_eq!(map.drain().len(), 4);
+    /// ```
+    pub fn insert_many<I>(&self, entries: I) -> usize
         where
-            K: Ord,
+            I: IntoIterator<Item = (K, V)>,
     {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        let r = m.remove(k);
+        let added = entries
+            .into_iter()
+            .map(|(k, v)| self.insert_locked(k, v))
+            .filter(|old| old.is_none())
+            .count();
         drop(g);
-        r
+        added
     }
 
-    pub fn remove_mut(&mut self, k: &K) -> Option<V>
+    /// like [`SyncBtreeMap::insert_many`]
+    pub fn extend<I>(&self, entries: I)
         where
-            K: Ord,
+            I: IntoIterator<Item = (K, V)>,
     {
+        self.insert_many(entries);
+    }
+
+    /// remove every key under one acquisition of the writer lock, returns how many were present
+    pub fn remove_many<'q, Q, I>(&self, keys: I) -> usize
+        where
+            I: IntoIterator<Item = &'q Q>,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized + 'q,
+    {
+        let g = self.lock.lock();
+        let removed = keys
+            .into_iter()
+            .filter_map(|k| self.remove_locked(k))
+            .count();
+        drop(g);
+        removed
+    }
+
+    /// keep only the entries `f` holds for, under one acquisition of the writer lock
+    pub fn retain<F>(&self, mut f: F)
+        where
+            F: FnMut(&K, &mut V) -> bool,
+    {
+        let g = self.lock.lock();
+        self.extract_locked(|k, v| !f(k, v));
+        drop(g);
+    }
+
+    /// remove and return the entries `f` holds for, under one acquisition of the writer lock
+    pub fn extract_if<F>(&self, f: F) -> BTreeMap<K, V>
+        where
+            F: FnMut(&K, &mut V) -> bool,
+    {
+        let g = self.lock.lock();
+        let r = self.extract_locked(f);
+        drop(g);
+        r
+    }
+
+    /// remove and return every entry
+    pub fn drain(&self) -> BTreeMap<K, V> {
+        let g = self.lock.lock();
         let m = unsafe { &mut *self.dirty.get() };
-        m.remove(k)
+        let r = std::mem::take(m);
+        self.events.emit(|| MapChange::Cleared);
+        drop(g);
+        r
     }
 
     pub fn len(&self) -> usize {
@@ -103,32 +195,26 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
         unsafe { (&*self.dirty.get()).is_empty() }
     }
 
-    pub fn clear(&self)
-        where
-            K: Eq + Hash,
-    {
+    pub fn clear(&self) {
         let g = self.lock.lock();
         let m = unsafe { &mut *self.dirty.get() };
         m.clear();
+        self.events.emit(|| MapChange::Cleared);
         drop(g);
     }
 
-    pub fn clear_mut(&mut self)
-        where
-            K: Eq + Hash,
-    {
+    pub fn clear_mut(&mut self) {
+        self.lock.touch();
         let m = unsafe { &mut *self.dirty.get() };
         m.clear();
+        self.events.emit(|| MapChange::Cleared);
     }
 
     pub fn shrink_to_fit(&self) {}
 
     pub fn shrink_to_fit_mut(&mut self) {}
 
-    pub fn from(map: BTreeMap<K, V>) -> Self
-        where
-            K: Eq + Hash,
-    {
+    pub fn from(map: BTreeMap<K, V>) -> Self {
         let s = Self::with_map(map);
         s
     }
@@ -136,7 +222,7 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
     /// Returns a reference to the value corresponding to the key.
     ///
     /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// [`Ord`] on the borrowed form *must* match the ordering on
     /// the key type.
     ///
     /// Since reading a map is unlocked, it is very fast
@@ -153,19 +239,19 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
     /// assert_eq!(map.get(&2).is_none(), true);
     /// ```
     #[inline]
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
         where
-            K: Borrow<Q> + Ord,
-            Q: Hash + Eq + Ord,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
     {
         unsafe { (&*self.dirty.get()).get(k) }
     }
 
     #[inline]
-    pub fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<BtreeMapRefMut<'_, V>>
+    pub fn get_mut<Q>(&self, k: &Q) -> Option<BtreeMapRefMut<'_, V>>
         where
-            K: Borrow<Q> + Ord,
-            Q: Hash + Eq + Ord,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
     {
         let m = unsafe { &mut *self.dirty.get() };
         Some(BtreeMapRefMut {
@@ -174,13 +260,37 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
         })
     }
 
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// The writer lock is held until the entry, or the guard it turns into, is dropped,
+    /// so a read-modify-write through it is atomic with respect to other writers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// let map = SyncBtreeMap::new();
+    /// *map.entry("a").or_insert(0) += 1;
+    /// map.entry("a").and_modify(|v| *v += 1).or_insert(0);
+    /// assert_eq!(*map.get("a").unwrap(), 2);
+    /// ```
+    pub fn entry(&self, key: K) -> BtreeMapEntry<'_, K, V> {
+        let g = self.lock.lock();
+        let m = unsafe { &mut *self.dirty.get() };
+        match m.entry(key) {
+            MapEntry::Occupied(inner) => BtreeMapEntry::Occupied(BtreeMapOccupiedEntry { _g: g, events: &self.events, inner }),
+            MapEntry::Vacant(inner) => BtreeMapEntry::Vacant(BtreeMapVacantEntry { _g: g, events: &self.events, inner }),
+        }
+    }
+
     #[inline]
-    pub fn contains_key(&self, x: &K) -> bool
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
         where
-            K: PartialEq + Ord,
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
     {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.contains_key(x)
+        unsafe { (&*self.dirty.get()).contains_key(k) }
     }
 
     pub fn iter(&self) -> MapIter<'_, K, V> {
@@ -188,13 +298,382 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
     }
 
     pub fn iter_mut(&self) -> BtreeIterMut<'_, K, V> {
+        let g = self.lock.lock();
         let m = unsafe { &mut *self.dirty.get() };
         return BtreeIterMut {
-            _g: self.lock.lock(),
+            _g: g,
             inner: m.iter_mut(),
         };
     }
 
+    /// Iterate the entries whose keys are in `range`, in key order.
+    ///
+    /// like [`SyncBtreeMap::range_mut`], the writer lock is held until the iterator drops,
+    /// so writers restructuring the tree wait for the scan. the lock is not reentrant,
+    /// a write to the map on the thread holding the iterator panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// let map = SyncBtreeMap::new();
+    /// for i in 0..10 {
+    ///     map.insert(i, i * 10);
+    /// }
+    /// let keys: Vec<_> = map.range(3..6).map(|(k, _)| *k).collect();
+    /// assert_eq!(keys, vec![3, 4, 5]);
+    /// ```
+    pub fn range<T, R>(&self, range: R) -> BtreeRange<'_, K, V>
+        where
+            K: Borrow<T>,
+            T: Ord + ?Sized,
+            R: RangeBounds<T>,
+    {
+        let g = self.lock.lock();
+        BtreeRange {
+            _g: g,
+            inner: self.range_locked(range),
+        }
+    }
+
+    /// like [`SyncBtreeMap::iter_mut`], the writer lock is held until the iterator drops,
+    /// a write to the map on the thread holding it panics
+    pub fn range_mut<T, R>(&self, range: R) -> BtreeRangeMut<'_, K, V>
+        where
+            K: Borrow<T>,
+            T: Ord + ?Sized,
+            R: RangeBounds<T>,
+    {
+        let g = self.lock.lock();
+        let m = unsafe { &mut *self.dirty.get() };
+        BtreeRangeMut {
+            _g: g,
+            inner: m.range_mut(range),
+        }
+    }
+
+    /// a copy of the entry with the smallest key, taken under the writer lock
+    pub fn first_key_value(&self) -> Option<(K, V)>
+        where
+            K: Clone,
+            V: Clone,
+    {
+        self.range::<K, _>(..).next().map(Self::copy_entry)
+    }
+
+    /// a copy of the entry with the largest key, taken under the writer lock
+    pub fn last_key_value(&self) -> Option<(K, V)>
+        where
+            K: Clone,
+            V: Clone,
+    {
+        self.range::<K, _>(..).next_back().map(Self::copy_entry)
+    }
+
+    /// a copy of the entry with the largest key less than or equal to `k`, taken under the writer lock
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// let map = SyncBtreeMap::new();
+    /// map.insert(10, "a");
+    /// map.insert(20, "b");
+    /// assert_eq!(map.floor(&15), Some((10, "a")));
+    /// assert_eq!(map.ceiling(&15), Some((20, "b")));
+    /// assert_eq!(map.floor(&5), None);
+    /// ```
+    pub fn floor<Q>(&self, k: &Q) -> Option<(K, V)>
+        where
+            K: Borrow<Q> + Clone,
+            Q: Ord + ?Sized,
+            V: Clone,
+    {
+        self.range::<Q, _>((Bound::Unbounded, Bound::Included(k)))
+            .next_back()
+            .map(Self::copy_entry)
+    }
+
+    /// a copy of the entry with the smallest key greater than or equal to `k`, taken under the writer lock
+    pub fn ceiling<Q>(&self, k: &Q) -> Option<(K, V)>
+        where
+            K: Borrow<Q> + Clone,
+            Q: Ord + ?Sized,
+            V: Clone,
+    {
+        self.range::<Q, _>((Bound::Included(k), Bound::Unbounded))
+            .next()
+            .map(Self::copy_entry)
+    }
+
+    /// Iterate the entries whose keys start with `prefix`, in key order.
+    /// like [`SyncBtreeMap::range`], the writer lock is held until the iterator drops,
+    /// a write to the map on the thread holding it panics.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// let map = SyncBtreeMap::new();
+    /// map.insert("eu/fr/a".to_string(), 1);
+    /// map.insert("eu/de/b".to_string(), 2);
+    /// map.insert("us/ca/c".to_string(), 3);
+    /// let keys: Vec<_> = map.scan_prefix("eu/").map(|(k, _)| k.as_str()).collect();
+    /// assert_eq!(keys, vec!["eu/de/b", "eu/fr/a"]);
+    /// assert_eq!(map.count_prefix("us/"), 1);
+    /// assert_eq!(map.remove_prefix("eu/"), 2);
+    /// ```
+    pub fn scan_prefix<'a, Q>(&'a self, prefix: &'a Q) -> BtreePrefixIter<'a, K, V, Q>
+        where
+            K: Borrow<Q>,
+            Q: PrefixKey + Ord + ?Sized,
+    {
+        let g = self.lock.lock();
+        BtreePrefixIter {
+            _g: g,
+            inner: self.range_locked::<Q, _>((Bound::Included(prefix), Bound::Unbounded)),
+            prefix,
+        }
+    }
+
+    pub fn count_prefix<Q>(&self, prefix: &Q) -> usize
+        where
+            K: Borrow<Q>,
+            Q: PrefixKey + Ord + ?Sized,
+    {
+        self.scan_prefix(prefix).count()
+    }
+
+    /// remove every key starting with `prefix` under one acquisition of the writer lock,
+    /// returns how many were removed
+    pub fn remove_prefix<Q>(&self, prefix: &Q) -> usize
+        where
+            K: Borrow<Q> + Clone,
+            Q: PrefixKey + Ord + ?Sized,
+    {
+        let g = self.lock.lock();
+        let r = self.remove_prefix_locked(prefix);
+        drop(g);
+        r
+    }
+
+    /// remove and return the entry with the smallest key
+    pub fn pop_first(&self) -> Option<(K, V)> {
+        let g = self.lock.lock();
+        let r = self.pop_locked(true);
+        drop(g);
+        r
+    }
+
+    /// remove and return the entry with the largest key
+    pub fn pop_last(&self) -> Option<(K, V)> {
+        let g = self.lock.lock();
+        let r = self.pop_locked(false);
+        drop(g);
+        r
+    }
+
+    /// Move every entry with a key greater than or equal to `k` out of the map.
+    /// they are reported to subscribers as removed.
+    pub fn split_off<Q>(&self, k: &Q) -> BTreeMap<K, V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+    {
+        let g = self.lock.lock();
+        let m = unsafe { &mut *self.dirty.get() };
+        let tail = m.split_off(k);
+        for (k, v) in tail.iter() {
+            self.events.emit(|| MapChange::Removed { key: k, value: v });
+        }
+        drop(g);
+        tail
+    }
+
+    /// Move every entry of `other` into the map, leaving `other` empty.
+    /// a key present in both takes the value of `other`, as with `insert`.
+    pub fn append(&self, other: &mut BTreeMap<K, V>) {
+        let g = self.lock.lock();
+        if self.events.is_empty() {
+            let m = unsafe { &mut *self.dirty.get() };
+            m.append(other);
+        } else {
+            for (k, v) in std::mem::take(other) {
+                self.insert_locked(k, v);
+            }
+        }
+        drop(g);
+    }
+
+    /// like [`SyncBtreeMap::insert`], but awaits the writer lock instead of blocking the thread
+    pub async fn insert_async(&self, k: K, v: V) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        let r = self.insert_locked(k, v);
+        drop(g);
+        r
+    }
+
+    /// like [`SyncBtreeMap::remove`], but awaits the writer lock instead of blocking the thread
+    pub async fn remove_async<Q>(&self, k: &Q) -> Option<V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+    {
+        let g = self.lock.lock_async().await;
+        let r = self.remove_locked(k);
+        drop(g);
+        r
+    }
+
+    pub async fn clear_async(&self) {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        m.clear();
+        self.events.emit(|| MapChange::Cleared);
+        drop(g);
+    }
+
+    /// the returned guard is `Send` and may be held across `.await`,
+    /// the map must not be written by the same task until it drops.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncBtreeMap};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let map = SyncBtreeMap::new();
+    ///     map.insert(1, 1);
+    ///     let mut v = map.get_mut_async(&1).await.unwrap();
+    ///     tokio::task::yield_now().await;
+    ///     *v += 1;
+    ///     drop(v);
+    ///     assert_eq!(*map.get(&1).unwrap(), 2);
+    /// }
+    /// ```
+    pub async fn get_mut_async<Q>(&self, k: &Q) -> Option<BtreeMapRefMut<'_, V, Async>>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+    {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        Some(BtreeMapRefMut {
+            _g: g,
+            value: m.get_mut(k)?,
+        })
+    }
+
+    pub async fn entry_async(&self, key: K) -> BtreeMapEntry<'_, K, V, Async> {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        match m.entry(key) {
+            MapEntry::Occupied(inner) => BtreeMapEntry::Occupied(BtreeMapOccupiedEntry { _g: g, events: &self.events, inner }),
+            MapEntry::Vacant(inner) => BtreeMapEntry::Vacant(BtreeMapVacantEntry { _g: g, events: &self.events, inner }),
+        }
+    }
+
+    pub async fn iter_mut_async(&self) -> BtreeIterMut<'_, K, V, Async> {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        BtreeIterMut {
+            _g: g,
+            inner: m.iter_mut(),
+        }
+    }
+
+    pub async fn range_mut_async<T, R>(&self, range: R) -> BtreeRangeMut<'_, K, V, Async>
+        where
+            K: Borrow<T>,
+            T: Ord + ?Sized,
+            R: RangeBounds<T>,
+    {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        BtreeRangeMut {
+            _g: g,
+            inner: m.range_mut(range),
+        }
+    }
+
+    pub async fn remove_prefix_async<Q>(&self, prefix: &Q) -> usize
+        where
+            K: Borrow<Q> + Clone,
+            Q: PrefixKey + Ord + ?Sized,
+    {
+        let g = self.lock.lock_async().await;
+        let r = self.remove_prefix_locked(prefix);
+        drop(g);
+        r
+    }
+
+    pub async fn pop_first_async(&self) -> Option<(K, V)> {
+        let g = self.lock.lock_async().await;
+        let r = self.pop_locked(true);
+        drop(g);
+        r
+    }
+
+    pub async fn pop_last_async(&self) -> Option<(K, V)> {
+        let g = self.lock.lock_async().await;
+        let r = self.pop_locked(false);
+        drop(g);
+        r
+    }
+
+    /// A consistent copy of the map as of now, later writes do not change it.
+    ///
+    /// every entry is cloned under the writer lock, O(n): writers wait for the copy, readers do not.
+    /// until the next write, a copy still held by a caller is returned again without copying,
+    /// the map itself keeps no copy alive.
+    pub fn snapshot(&self) -> Snapshot<BTreeMap<K, V>>
+        where
+            K: Clone,
+            V: Clone,
+    {
+        self.snapshots.get_or_copy(&self.lock, || unsafe { &*self.dirty.get() }.clone())
+    }
+
+    /// Write the map to `path`, encoded under the writer lock through its serde impl.
+    /// the file is replaced atomically, a crash meanwhile leaves the previous one intact.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> crate::errors::Result<()>
+        where
+            K: Serialize,
+            V: Serialize,
+    {
+        let g = self.lock.lock();
+        let payload = persist::encode(self);
+        drop(g);
+        persist::save(path.as_ref(), Kind::BtreeMap, &payload?)
+    }
+
+    /// Read a map written by [`SyncBtreeMap::save_to`], a truncated or corrupted file is an error.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> crate::errors::Result<Self>
+        where
+            K: DeserializeOwned,
+            V: DeserializeOwned,
+    {
+        let payload = persist::load(path.as_ref(), Kind::BtreeMap)?;
+        persist::decode(&payload)
+    }
+
+    /// Receive every later insert, remove and clear of the map, in the order they happen.
+    ///
+    /// Values changed in place through `get_mut` or `iter_mut` are not reported.
+    /// A subscriber more than `capacity` events behind misses the next ones and receives a
+    /// [`MapEvent::Lagged`] once it catches up. Drop the receiver to unsubscribe.
+    pub fn subscribe(&self, capacity: usize) -> flume::Receiver<MapEvent<K, V>>
+        where
+            K: Clone + Send + 'static,
+            V: Clone + Send + 'static,
+    {
+        self.events.subscribe(capacity)
+    }
+
     pub fn into_iter(self) -> MapIntoIter<K, V> {
         self.dirty.into_inner().into_iter()
     }
@@ -206,14 +685,129 @@ impl<K: Eq + Hash, V> SyncBtreeMap<K, V>
     pub fn into_inner(self) -> BTreeMap<K, V> {
         self.dirty.into_inner()
     }
+
+    fn insert_locked(&self, k: K, v: V) -> Option<V> {
+        let m = unsafe { &mut *self.dirty.get() };
+        match m.entry(k) {
+            MapEntry::Occupied(mut e) => {
+                let old = e.insert(v);
+                self.events.emit(|| MapChange::Inserted {
+                    key: e.key(),
+                    old: Some(&old),
+                    new: e.get(),
+                });
+                Some(old)
+            }
+            MapEntry::Vacant(e) => {
+                // emitted once the value is in the map, a subscriber may `get` it right away
+                let e = e.insert_entry(v);
+                self.events.emit(|| MapChange::Inserted {
+                    key: e.key(),
+                    old: None,
+                    new: e.get(),
+                });
+                None
+            }
+        }
+    }
+
+    fn remove_locked<Q>(&self, k: &Q) -> Option<V>
+        where
+            K: Borrow<Q>,
+            Q: Ord + ?Sized,
+    {
+        let m = unsafe { &mut *self.dirty.get() };
+        let (k, v) = m.remove_entry(k)?;
+        self.events.emit(|| MapChange::Removed { key: &k, value: &v });
+        Some(v)
+    }
+
+    fn extract_locked<F>(&self, mut f: F) -> BTreeMap<K, V>
+        where
+            F: FnMut(&K, &mut V) -> bool,
+    {
+        // `f` runs on one entry at a time, with no borrow of the tree alive
+        let entries: Vec<(*const K, *mut V)> = unsafe { &mut *self.dirty.get() }
+            .iter_mut()
+            .map(|(k, v)| (k as *const K, v as *mut V))
+            .collect();
+        let marked: Vec<bool> = entries
+            .into_iter()
+            .map(|(k, v)| unsafe { f(&*k, &mut *v) })
+            .collect();
+        if !marked.contains(&true) {
+            return BTreeMap::new();
+        }
+        let m = unsafe { &mut *self.dirty.get() };
+        let mut removed = vec![];
+        let mut kept = vec![];
+        // in key order, like `marked`
+        for ((k, v), mark) in std::mem::take(m).into_iter().zip(marked) {
+            if mark {
+                removed.push((k, v));
+            } else {
+                kept.push((k, v));
+            }
+        }
+        // both are in key order, collecting them builds the trees in bulk
+        *m = kept.into_iter().collect();
+        let removed: BTreeMap<K, V> = removed.into_iter().collect();
+        for (k, v) in removed.iter() {
+            self.events.emit(|| MapChange::Removed { key: k, value: v });
+        }
+        removed
+    }
+
+    /// only valid under the lock, the tree must not change while the range is alive
+    fn range_locked<T, R>(&self, range: R) -> MapRange<'_, K, V>
+        where
+            K: Borrow<T>,
+            T: Ord + ?Sized,
+            R: RangeBounds<T>,
+    {
+        unsafe { (&*self.dirty.get()).range(range) }
+    }
+
+    fn copy_entry((k, v): (&K, &V)) -> (K, V)
+        where
+            K: Clone,
+            V: Clone,
+    {
+        (k.clone(), v.clone())
+    }
+
+    fn remove_prefix_locked<Q>(&self, prefix: &Q) -> usize
+        where
+            K: Borrow<Q> + Clone,
+            Q: PrefixKey + Ord + ?Sized,
+    {
+        // the async variant holds an async guard, the blocking lock of `scan_prefix` would deadlock
+        let keys: Vec<K> = self
+            .range_locked::<Q, _>((Bound::Included(prefix), Bound::Unbounded))
+            .map(|(k, _)| k)
+            .take_while(|k| (*k).borrow().has_prefix(prefix))
+            .cloned()
+            .collect();
+        for k in &keys {
+            self.remove_locked::<K>(k);
+        }
+        keys.len()
+    }
+
+    fn pop_locked(&self, first: bool) -> Option<(K, V)> {
+        let m = unsafe { &mut *self.dirty.get() };
+        let (k, v) = if first { m.pop_first()? } else { m.pop_last()? };
+        self.events.emit(|| MapChange::Removed { key: &k, value: &v });
+        Some((k, v))
+    }
 }
 
-pub struct BtreeMapRefMut<'a, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
+pub struct BtreeMapRefMut<'a, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
     value: &'a mut V,
 }
 
-impl<'a, V> Deref for BtreeMapRefMut<'_, V> {
+impl<V, M> Deref for BtreeMapRefMut<'_, V, M> {
     type Target = V;
 
     fn deref(&self) -> &Self::Target {
@@ -221,13 +815,13 @@ impl<'a, V> Deref for BtreeMapRefMut<'_, V> {
     }
 }
 
-impl<'a, V> DerefMut for BtreeMapRefMut<'_, V> {
+impl<V, M> DerefMut for BtreeMapRefMut<'_, V, M> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.value
     }
 }
 
-impl<'a, V> Debug for BtreeMapRefMut<'_, V>
+impl<V, M> Debug for BtreeMapRefMut<'_, V, M>
     where
         V: Debug,
 {
@@ -236,7 +830,7 @@ impl<'a, V> Debug for BtreeMapRefMut<'_, V>
     }
 }
 
-impl<'a, V> Display for BtreeMapRefMut<'_, V>
+impl<V, M> Display for BtreeMapRefMut<'_, V, M>
     where
         V: Display,
 {
@@ -245,7 +839,7 @@ impl<'a, V> Display for BtreeMapRefMut<'_, V>
     }
 }
 
-impl<'a, V> PartialEq<Self> for BtreeMapRefMut<'_, V>
+impl<V, M> PartialEq<Self> for BtreeMapRefMut<'_, V, M>
     where
         V: Eq,
 {
@@ -254,14 +848,147 @@ impl<'a, V> PartialEq<Self> for BtreeMapRefMut<'_, V>
     }
 }
 
-impl<'a, V> Eq for BtreeMapRefMut<'_, V> where V: Eq {}
+impl<V, M> Eq for BtreeMapRefMut<'_, V, M> where V: Eq {}
+
+/// A view into a single entry of a [`SyncBtreeMap`], holding the writer lock.
+pub enum BtreeMapEntry<'a, K, V, M = Blocking> {
+    Occupied(BtreeMapOccupiedEntry<'a, K, V, M>),
+    Vacant(BtreeMapVacantEntry<'a, K, V, M>),
+}
+
+pub struct BtreeMapOccupiedEntry<'a, K, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    events: &'a MapEvents<K, V>,
+    inner: MapOccupiedEntry<'a, K, V>,
+}
+
+pub struct BtreeMapVacantEntry<'a, K, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    events: &'a MapEvents<K, V>,
+    inner: MapVacantEntry<'a, K, V>,
+}
+
+impl<'a, K: Ord, V, M> BtreeMapEntry<'a, K, V, M> {
+    pub fn or_insert(self, default: V) -> BtreeMapRefMut<'a, V, M> {
+        match self {
+            BtreeMapEntry::Occupied(e) => e.into_mut(),
+            BtreeMapEntry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> BtreeMapRefMut<'a, V, M> {
+        match self {
+            BtreeMapEntry::Occupied(e) => e.into_mut(),
+            BtreeMapEntry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> BtreeMapRefMut<'a, V, M> {
+        match self {
+            BtreeMapEntry::Occupied(e) => e.into_mut(),
+            BtreeMapEntry::Vacant(e) => {
+                let v = default(e.key());
+                e.insert(v)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> BtreeMapRefMut<'a, V, M>
+        where
+            V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            BtreeMapEntry::Occupied(mut e) => {
+                f(e.get_mut());
+                BtreeMapEntry::Occupied(e)
+            }
+            BtreeMapEntry::Vacant(e) => BtreeMapEntry::Vacant(e),
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            BtreeMapEntry::Occupied(e) => e.key(),
+            BtreeMapEntry::Vacant(e) => e.key(),
+        }
+    }
+}
+
+impl<'a, K: Ord, V, M> BtreeMapOccupiedEntry<'a, K, V, M> {
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    pub fn get(&self) -> &V {
+        self.inner.get()
+    }
+
+    pub fn get_mut(&mut self) -> &mut V {
+        self.inner.get_mut()
+    }
+
+    pub fn into_mut(self) -> BtreeMapRefMut<'a, V, M> {
+        BtreeMapRefMut {
+            _g: self._g,
+            value: self.inner.into_mut(),
+        }
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    pub fn insert(&mut self, value: V) -> V {
+        let old = self.inner.insert(value);
+        self.events.emit(|| MapChange::Inserted {
+            key: self.inner.key(),
+            old: Some(&old),
+            new: self.inner.get(),
+        });
+        old
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(self) -> (K, V) {
+        let (k, v) = self.inner.remove_entry();
+        self.events.emit(|| MapChange::Removed { key: &k, value: &v });
+        (k, v)
+    }
+}
+
+impl<'a, K: Ord, V, M> BtreeMapVacantEntry<'a, K, V, M> {
+    pub fn key(&self) -> &K {
+        self.inner.key()
+    }
+
+    pub fn into_key(self) -> K {
+        self.inner.into_key()
+    }
+
+    pub fn insert(self, value: V) -> BtreeMapRefMut<'a, V, M> {
+        let e = self.inner.insert_entry(value);
+        self.events.emit(|| MapChange::Inserted {
+            key: e.key(),
+            old: None,
+            new: e.get(),
+        });
+        BtreeMapRefMut {
+            _g: self._g,
+            value: e.into_mut(),
+        }
+    }
+}
 
-pub struct BtreeIterMut<'a, K, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
+pub struct BtreeIterMut<'a, K, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
     inner: std::collections::btree_map::IterMut<'a, K, V>,
 }
 
-impl<'a, K, V> Deref for BtreeIterMut<'a, K, V> {
+impl<'a, K, V, M> Deref for BtreeIterMut<'a, K, V, M> {
     type Target = std::collections::btree_map::IterMut<'a, K, V>;
 
     fn deref(&self) -> &Self::Target {
@@ -269,13 +996,13 @@ impl<'a, K, V> Deref for BtreeIterMut<'a, K, V> {
     }
 }
 
-impl<'a, K, V> DerefMut for BtreeIterMut<'a, K, V> {
+impl<'a, K, V, M> DerefMut for BtreeIterMut<'a, K, V, M> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.inner
     }
 }
 
-impl<'a, K, V> Iterator for BtreeIterMut<'a, K, V> {
+impl<'a, K, V, M> Iterator for BtreeIterMut<'a, K, V, M> {
     type Item = (&'a K, &'a mut V);
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -283,7 +1010,90 @@ impl<'a, K, V> Iterator for BtreeIterMut<'a, K, V> {
     }
 }
 
-impl<'a, K: Eq + Hash, V> IntoIterator for &'a SyncBtreeMap<K, V> {
+/// a key type that can be scanned by prefix, keys with the same prefix are adjacent in its order.
+/// implemented for `str` and `[u8]`, so maps keyed by `String`, `Vec<u8>` or `&[u8]` can use it.
+pub trait PrefixKey {
+    fn has_prefix(&self, prefix: &Self) -> bool;
+}
+
+impl PrefixKey for str {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(prefix)
+    }
+}
+
+impl PrefixKey for [u8] {
+    fn has_prefix(&self, prefix: &Self) -> bool {
+        self.starts_with(prefix)
+    }
+}
+
+/// holds the writer lock until it drops, a write to the map on the same thread panics
+pub struct BtreePrefixIter<'a, K, V, Q: ?Sized> {
+    _g: WriteGuard<'a>,
+    inner: MapRange<'a, K, V>,
+    prefix: &'a Q,
+}
+
+impl<'a, K, V, Q> Iterator for BtreePrefixIter<'a, K, V, Q>
+    where
+        K: Borrow<Q>,
+        Q: PrefixKey + ?Sized,
+{
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        if k.borrow().has_prefix(self.prefix) {
+            return Some((k, v));
+        }
+        // the first key past the prefix ends the scan
+        self.inner = MapRange::default();
+        None
+    }
+}
+
+/// holds the writer lock until it drops, a write to the map on the same thread panics
+pub struct BtreeRange<'a, K, V> {
+    _g: WriteGuard<'a>,
+    inner: MapRange<'a, K, V>,
+}
+
+impl<'a, K, V> Iterator for BtreeRange<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<K, V> DoubleEndedIterator for BtreeRange<'_, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+/// holds the writer lock until it drops, a write to the map on the same thread panics
+pub struct BtreeRangeMut<'a, K, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    inner: MapRangeMut<'a, K, V>,
+}
+
+impl<'a, K, V, M> Iterator for BtreeRangeMut<'a, K, V, M> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl<K, V, M> DoubleEndedIterator for BtreeRangeMut<'_, K, V, M> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner.next_back()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a SyncBtreeMap<K, V> {
     type Item = (&'a K, &'a V);
     type IntoIter = MapIter<'a, K, V>;
 
@@ -292,7 +1102,7 @@ impl<'a, K: Eq + Hash, V> IntoIterator for &'a SyncBtreeMap<K, V> {
     }
 }
 
-impl<K: Eq + Hash, V> IntoIterator for SyncBtreeMap<K, V> {
+impl<K: Ord, V> IntoIterator for SyncBtreeMap<K, V> {
     type Item = (K, V);
     type IntoIter = MapIntoIter<K, V>;
 
@@ -301,15 +1111,15 @@ impl<K: Eq + Hash, V> IntoIterator for SyncBtreeMap<K, V> {
     }
 }
 
-impl<K: Eq + Hash, V> From<BTreeMap<K, V>> for SyncBtreeMap<K, V> {
+impl<K: Ord, V> From<BTreeMap<K, V>> for SyncBtreeMap<K, V> {
     fn from(arg: BTreeMap<K, V>) -> Self {
         Self::from(arg)
     }
 }
 
-impl<K: Eq + Hash, V> serde::Serialize for SyncBtreeMap<K, V>
+impl<K, V> serde::Serialize for SyncBtreeMap<K, V>
     where
-        K: Eq + Hash + Serialize + Ord,
+        K: Ord + Serialize,
         V: Serialize,
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -322,7 +1132,7 @@ impl<K: Eq + Hash, V> serde::Serialize for SyncBtreeMap<K, V>
 
 impl<'de, K, V> serde::Deserialize<'de> for SyncBtreeMap<K, V>
     where
-        K: Eq + Hash + Ord + serde::Deserialize<'de>,
+        K: Ord + serde::Deserialize<'de>,
         V: serde::Deserialize<'de>,
 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -334,9 +1144,9 @@ impl<'de, K, V> serde::Deserialize<'de> for SyncBtreeMap<K, V>
     }
 }
 
-impl<K: Eq + Hash, V> Debug for SyncBtreeMap<K, V>
+impl<K, V> Debug for SyncBtreeMap<K, V>
     where
-        K: Eq + Hash + Debug,
+        K: Ord + Debug,
         V: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -344,9 +1154,9 @@ impl<K: Eq + Hash, V> Debug for SyncBtreeMap<K, V>
     }
 }
 
-impl<K: Eq + Hash, V> Display for SyncBtreeMap<K, V>
+impl<K, V> Display for SyncBtreeMap<K, V>
     where
-        K: Eq + Hash + Display,
+        K: Ord + Display,
         V: Display,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -370,7 +1180,7 @@ impl<'a, K, V> Iterator for BtreeIter<'a, K, V> {
     }
 }
 
-impl<K: Clone + Eq + Hash, V: Clone> Clone for SyncBtreeMap<K, V> {
+impl<K: Clone + Ord, V: Clone> Clone for SyncBtreeMap<K, V> {
     fn clone(&self) -> Self {
         let c = (*self.dirty_ref()).clone();
         SyncBtreeMap::from(c)
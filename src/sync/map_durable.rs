@@ -0,0 +1,384 @@
+use crate::errors::{Error, Result};
+use crate::sync::persist::{self, crc32};
+use crate::sync::{HashMapRef, HashRefIter, Snapshot, SyncHashMap};
+use parking_lot::Mutex;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+const LOG_FILE: &str = "wal.log";
+
+/// `len` and `crc` of a record, both little endian u32
+const FRAME_HEADER_LEN: usize = 8;
+
+const INSERT: u8 = 0;
+const REMOVE: u8 = 1;
+const CLEAR: u8 = 2;
+
+/// a SyncHashMap kept on disk, a small embedded key-value store.
+///
+/// every `insert`, `remove` and `clear` is appended to a write-ahead log in `dir` before it is
+/// applied, [`SyncDurableHashMap::open`] replays the log on top of the last snapshot.
+/// [`SyncDurableHashMap::compact`] writes a fresh snapshot and empties the log.
+/// how often the log reaches the disk is chosen by [`FsyncPolicy`].
+///
+/// a directory is opened by one map at a time: the log stays locked until the map drops,
+/// opening it again meanwhile, from this process or another, is an error.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncDurableHashMap;
+///
+/// let dir = std::env::temp_dir().join("dark_std_doc_durable");
+/// # let _ = std::fs::remove_dir_all(&dir);
+/// let map = SyncDurableHashMap::open(&dir).unwrap();
+/// map.insert("a".to_string(), 1).unwrap();
+/// map.insert("b".to_string(), 2).unwrap();
+/// map.remove(&"a".to_string()).unwrap();
+/// drop(map);
+///
+/// let map = SyncDurableHashMap::<String, i32>::open(&dir).unwrap();
+/// assert_eq!(map.len(), 1);
+/// assert_eq!(*map.get(&"b".to_string()).unwrap(), 2);
+/// # std::fs::remove_dir_all(&dir).unwrap();
+/// ```
+pub struct SyncDurableHashMap<K: Eq + Hash, V> {
+    map: SyncHashMap<K, V>,
+    dir: PathBuf,
+    policy: FsyncPolicy,
+    /// only written under the writer lock of `map`
+    log: Mutex<Log>,
+}
+
+/// when the write-ahead log of a [`SyncDurableHashMap`] is flushed to the disk
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// before every write returns, nothing acknowledged is lost
+    #[default]
+    Always,
+    /// once this many writes are pending, a crash loses at most the pending ones
+    Batch(usize),
+    /// with the first write at least this long after the last flush,
+    /// call `sync` (or `spawn_sync` with the `tokio` feature) to flush a quiet tail
+    Interval(Duration),
+}
+
+struct Log {
+    file: File,
+    /// bytes of whole records
+    len: u64,
+    records: u64,
+    unsynced: usize,
+    last_sync: Instant,
+}
+
+impl<K, V> SyncDurableHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Serialize + DeserializeOwned,
+        V: Send + 'static + Serialize + DeserializeOwned,
+{
+    /// open the map stored in `dir`, created if missing, flushing every write
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        Self::open_with(dir, FsyncPolicy::default())
+    }
+
+    /// Open the map stored in `dir`, created if missing.
+    ///
+    /// the log is replayed up to its first record that does not check out.
+    /// a record torn by a crash at the end of the log is dropped, and so is everything from a length
+    /// running past the end, which a corrupted length can not be told apart from.
+    /// a record failing its checksum with others after it is reported as an error.
+    pub fn open_with<P: AsRef<Path>>(dir: P, policy: FsyncPolicy) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(LOG_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(crate::err!("{}: already opened by another map", path.display()));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        let snapshot = dir.join(SNAPSHOT_FILE);
+        let map = if snapshot.exists() {
+            SyncHashMap::load_from(&snapshot)?
+        } else {
+            SyncHashMap::new()
+        };
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes)?;
+        let (len, records) = replay(&map, &bytes, &path)?;
+        if len < bytes.len() as u64 {
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+        Ok(Self {
+            map,
+            dir,
+            policy,
+            log: Mutex::new(Log {
+                file,
+                len,
+                records,
+                unsynced: 0,
+                last_sync: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn insert(&self, k: K, v: V) -> Result<Option<HashMapRef<'_, V>>> {
+        let g = self.map.write_lock();
+        self.append(&(INSERT, Some(&k), Some(&v)))?;
+        let old = self.map.insert_guarded(&g, k, v);
+        drop(g);
+        Ok(old)
+    }
+
+    pub fn remove(&self, k: &K) -> Result<Option<HashMapRef<'_, V>>> {
+        let g = self.map.write_lock();
+        if !self.map.contains_key(k) {
+            return Ok(None);
+        }
+        self.append(&(REMOVE, Some(k), None::<&V>))?;
+        let old = self.map.remove_guarded(&g, k);
+        drop(g);
+        Ok(old)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let g = self.map.write_lock();
+        if !self.map.is_empty() {
+            self.append(&(CLEAR, None::<&K>, None::<&V>))?;
+            self.map.clear_guarded(&g);
+        }
+        drop(g);
+        Ok(())
+    }
+
+    /// Write the map to a fresh snapshot and empty the log.
+    ///
+    /// writers wait meanwhile, readers do not. a crash halfway is harmless,
+    /// replaying the old log on top of the new snapshot gives the same map.
+    pub fn compact(&self) -> Result<()> {
+        let g = self.map.write_lock();
+        self.map.save_to_guarded(&g, self.dir.join(SNAPSHOT_FILE))?;
+        let mut log = self.log.lock();
+        log.file.set_len(0)?;
+        log.file.sync_all()?;
+        log.len = 0;
+        log.records = 0;
+        log.unsynced = 0;
+        log.last_sync = Instant::now();
+        drop(log);
+        drop(g);
+        Ok(())
+    }
+
+    /// flush the writes not yet on the disk
+    pub fn sync(&self) -> Result<()> {
+        let mut log = self.log.lock();
+        if log.unsynced > 0 {
+            log.sync()?;
+        }
+        Ok(())
+    }
+
+    /// sync every `every` on the tokio runtime, the task ends once the map is dropped.
+    /// a failed flush is retried on the next tick, `sync` reports it.
+    #[cfg(feature = "tokio")]
+    pub fn spawn_sync(self: &std::sync::Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()>
+        where
+            K: Sync,
+            V: Sync,
+    {
+        let map = std::sync::Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match map.upgrade() {
+                    None => break,
+                    Some(m) => {
+                        let _ = m.sync();
+                    }
+                }
+            }
+        })
+    }
+
+    /// the records written since the last compaction
+    pub fn log_len(&self) -> u64 {
+        self.log.lock().records
+    }
+
+    /// the size of the log in bytes, compare it with the size of the map to decide when to compact
+    pub fn log_bytes(&self) -> u64 {
+        self.log.lock().len
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn append<T: Serialize>(&self, record: &T) -> Result<()> {
+        let payload = persist::encode(record)?;
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&crc32(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+        let mut log = self.log.lock();
+        let written = log.file.write_all(&frame).map_err(Error::from).and_then(|_| {
+            log.unsynced += 1;
+            let due = match self.policy {
+                FsyncPolicy::Always => true,
+                FsyncPolicy::Batch(n) => log.unsynced >= n,
+                FsyncPolicy::Interval(d) => log.last_sync.elapsed() >= d,
+            };
+            if due {
+                log.sync()?;
+            }
+            Ok(())
+        });
+        if let Err(e) = written {
+            // the write is not applied, so it must not be replayed either
+            let len = log.len;
+            let _ = log.file.set_len(len);
+            log.unsynced = log.unsynced.saturating_sub(1);
+            return Err(e);
+        }
+        log.len += frame.len() as u64;
+        log.records += 1;
+        Ok(())
+    }
+}
+
+impl<K, V> SyncDurableHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    pub fn get(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        self.map.get(k)
+    }
+
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.map.contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn iter(&self) -> HashRefIter<'_, K, V> {
+        self.map.iter()
+    }
+
+    pub fn snapshot(&self) -> Snapshot<HashMap<K, V>>
+        where
+            V: Clone,
+    {
+        self.map.snapshot()
+    }
+}
+
+impl Log {
+    fn sync(&mut self) -> Result<()> {
+        self.file.sync_data()?;
+        self.unsynced = 0;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+}
+
+/// apply the records of `bytes` to `map`, returns the length of the whole records and their count
+fn replay<K, V>(map: &SyncHashMap<K, V>, bytes: &[u8], path: &Path) -> Result<(u64, u64)>
+    where
+        K: Eq + Hash + Clone + Send + 'static + DeserializeOwned,
+        V: Send + 'static + DeserializeOwned,
+{
+    let mut offset = 0;
+    let mut records = 0;
+    // a single pass, the first record that does not check out ends the log
+    while let Some(header) = bytes.get(offset..offset + FRAME_HEADER_LEN) {
+        let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+        let start = offset + FRAME_HEADER_LEN;
+        let end = start.saturating_add(len);
+        // torn by a crash while appending, or a corrupted length. a record is never empty,
+        // a zeroed tail left by the crash is torn as well
+        let payload = match bytes.get(start..end) {
+            Some(payload) if len > 0 => payload,
+            _ => break,
+        };
+        if crc32(payload) != crc {
+            // the last record may be torn as well, one followed by others is corrupted
+            if end == bytes.len() {
+                break;
+            }
+            return Err(crate::err!(
+                "{}: corrupted record at offset {}",
+                path.display(),
+                offset
+            ));
+        }
+        let (op, k, v): (u8, Option<K>, Option<V>) = persist::decode(payload)?;
+        match (op, k, v) {
+            (INSERT, Some(k), Some(v)) => {
+                map.insert(k, v);
+            }
+            (REMOVE, Some(k), None) => {
+                map.remove(&k);
+            }
+            (CLEAR, None, None) => map.clear(),
+            (op, _, _) => {
+                return Err(crate::err!(
+                    "{}: unknown record {} at offset {}",
+                    path.display(),
+                    op,
+                    offset
+                ));
+            }
+        }
+        offset = end;
+        records += 1;
+    }
+    Ok((offset as u64, records))
+}
+
+impl<K: Eq + Hash, V> Drop for SyncDurableHashMap<K, V> {
+    fn drop(&mut self) {
+        let log = self.log.get_mut();
+        if log.unsynced > 0 {
+            let _ = log.sync();
+        }
+    }
+}
+
+impl<K, V> Debug for SyncDurableHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.map.fmt(f)
+    }
+}
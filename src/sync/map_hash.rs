@@ -1,303 +1,2157 @@
-use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use atomic_shim::AtomicU64;
+use crate::sync::{
+    Async, Blocking, MapChange, MapEvent, MapEvents, Snapshot, SnapshotCache, WriteGuard, WriteLock,
+};
+use crate::sync::persist::{self, Kind};
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned, Shared};
+use serde::de::DeserializeOwned;
 use serde::{Deserializer, Serialize, Serializer};
 use std::borrow::Borrow;
 use std::cell::UnsafeCell;
 use std::collections::{
-    hash_map::IntoIter as MapIntoIter, hash_map::Iter as MapIter, hash_map::IterMut as MapIterMut,
+    hash_map::IntoIter as MapIntoIter, hash_map::Iter as MapIter, hash_map::RandomState,
     HashMap as Map, HashMap,
 };
 use std::fmt::{Debug, Display, Formatter};
-use std::hash::Hash;
-use std::ops::{Deref, DerefMut};
+use std::hash::{BuildHasher, Hash};
+use std::ops::{Deref, DerefMut, Index};
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// this sync map used to many reader,writer less.space-for-time strategy
-pub struct SyncHashMap<K: Eq + Hash, V> {
-    dirty: UnsafeCell<Map<K, V>>,
-    lock: ReentrantMutex<()>,
+///
+/// it is the Golang `sync.Map` design:
+/// * `read` is an immutable map published through an atomic pointer, readers never lock it.
+/// * `dirty` holds every key (including the ones not yet promoted) and is only changed under `lock`.
+/// * a read that misses `read` falls back to `dirty` and counts a miss, once the misses
+///   reach the size of `dirty` it is promoted to be the new `read`.
+///   readers never wait for `lock`: they look into `dirty` under the short-lived `dirty_lock`,
+///   and skip counting the miss while a writer holds `lock`.
+///
+/// both maps share the same `Entry`, so overwriting a promoted key is seen by readers at once.
+/// retired `read` maps are released by epoch based reclamation after the last reader leaves.
+///
+/// keys are hashed by `S`, SipHash by default like `std::collections::HashMap`.
+/// see [`SyncHashMap::with_hasher`] to trade its HashDoS resistance for speed.
+pub struct SyncHashMap<K: Eq + Hash, V, S = RandomState> {
+    read: Atomic<ReadOnly<K, V, S>>,
+    dirty: UnsafeCell<Option<Entries<K, V, S>>>,
+    /// shared by readers looking into `dirty`, exclusive for writers while they change it
+    dirty_lock: parking_lot::RwLock<()>,
+    misses: UnsafeCell<usize>,
+    len: AtomicUsize,
+    lock: WriteLock,
+    events: MapEvents<K, V>,
+    waiters: Waiters<K>,
+    snapshots: SnapshotCache<HashMap<K, V, S>>,
+    hasher: S,
 }
 
 /// this is safety, dirty mutex ensure
-unsafe impl<K: Eq + Hash, V> Send for SyncHashMap<K, V> {}
+unsafe impl<K: Eq + Hash + Send, V: Send, S: Send> Send for SyncHashMap<K, V, S> {}
 
 /// this is safety, dirty mutex ensure
-unsafe impl<K: Eq + Hash, V> Sync for SyncHashMap<K, V> {}
-
-impl<K, V> std::ops::Index<&K> for SyncHashMap<K, V>
-where
-    K: Eq + Hash,
+unsafe impl<K: Eq + Hash + Send + Sync, V: Send + Sync, S: Send + Sync> Sync
+    for SyncHashMap<K, V, S>
 {
-    type Output = V;
+}
+
+/// the slots of the map, hashed by `S`
+type Entries<K, V, S> = Map<K, Arc<Entry<V>>, S>;
+
+/// the lock-free view of the map
+struct ReadOnly<K, V, S> {
+    m: Entries<K, V, S>,
+    /// true if `dirty` contains some key not in `m`, or an entry replacing a moved one of `m`
+    amended: AtomicBool,
+}
+
+impl<K, V, S> ReadOnly<K, V, S> {
+    fn new(m: Entries<K, V, S>) -> Self {
+        Self {
+            m,
+            amended: AtomicBool::new(false),
+        }
+    }
 
-    fn index(&self, index: &K) -> &Self::Output {
-        unsafe { &(&*self.dirty.get())[index] }
+    /// the entry of `k`, unless a transaction moved the key to `dirty`
+    #[inline]
+    fn get<Q>(&self, k: &Q) -> Option<&Arc<Entry<V>>>
+        where
+            K: Eq + Hash + Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            S: BuildHasher,
+    {
+        self.m.get(k).filter(|e| !e.moved.load(Ordering::Acquire))
+    }
+}
+
+/// the slot of one key.
+/// a null `p` means the key was deleted, `expunged` means it was deleted and
+/// is also missing from `dirty`, so it must be added back there before reuse.
+/// `moved` means a transaction replaced it in `dirty`, it is never written again
+/// and readers of `read` look for the key in `dirty` instead.
+struct Entry<V> {
+    p: Atomic<V>,
+    expunged: AtomicBool,
+    moved: AtomicBool,
+}
+
+impl<V> Entry<V> {
+    fn new(v: V) -> Self {
+        Self {
+            p: Atomic::new(v),
+            expunged: AtomicBool::new(false),
+            moved: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn load<'g>(&self, guard: &'g Guard) -> Option<&'g V> {
+        unsafe { self.p.load(Ordering::Acquire, guard).as_ref() }
+    }
+
+    fn swap_locked<'g>(&self, v: Owned<V>, guard: &'g Guard) -> Shared<'g, V> {
+        self.p.swap(v, Ordering::AcqRel, guard)
+    }
+
+    fn delete_locked<'g>(&self, guard: &'g Guard) -> Shared<'g, V> {
+        self.p.swap(Shared::null(), Ordering::AcqRel, guard)
+    }
+
+    fn try_expunge_locked(&self, guard: &Guard) -> bool {
+        if self.p.load(Ordering::Acquire, guard).is_null() {
+            self.expunged.store(true, Ordering::Relaxed);
+            return true;
+        }
+        false
+    }
+
+    fn unexpunge_locked(&self) -> bool {
+        self.expunged.swap(false, Ordering::Relaxed)
+    }
+}
+
+impl<V> Drop for Entry<V> {
+    fn drop(&mut self) {
+        // the last owner of an entry is either a retired map, which readers can no longer
+        // reach, or the dirty map after the value was already taken out, so drop it right now
+        unsafe {
+            let p = self.p.load(Ordering::Relaxed, epoch::unprotected());
+            if !p.is_null() {
+                drop(p.into_owned());
+            }
+        }
+    }
+}
+
+/// the threads and tasks waiting for a key to be inserted, see `SyncHashMap::wait_for`
+struct Waiters<K> {
+    /// keys waited for, lets inserts skip the lock when nobody waits
+    len: AtomicUsize,
+    next_id: AtomicU64,
+    m: parking_lot::Mutex<Map<K, Vec<WaitSender>>>,
+}
+
+/// the id of a waiter and the channel that wakes it
+type WaitSender = (u64, flume::Sender<()>);
+
+impl<K: Eq + Hash + Clone> Waiters<K> {
+    fn new() -> Self {
+        Self {
+            len: AtomicUsize::new(0),
+            next_id: AtomicU64::new(0),
+            m: parking_lot::Mutex::new(Map::new()),
+        }
+    }
+
+    /// register before looking the key up, so an insert in between is not missed
+    fn register(&self, k: &K) -> WaitTicket<'_, K> {
+        let (send, recv) = flume::bounded(1);
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut m = self.m.lock();
+        m.entry(k.clone()).or_default().push((id, send));
+        self.len.store(m.len(), Ordering::Relaxed);
+        drop(m);
+        // pairs with the fence of `wake`: either the waiter sees the value or the inserter sees the waiter
+        fence(Ordering::SeqCst);
+        WaitTicket {
+            waiters: self,
+            key: k.clone(),
+            id,
+            recv,
+        }
+    }
+
+    /// called under the writer lock once the value of `k` is visible
+    fn wake(&self, k: &K) {
+        fence(Ordering::SeqCst);
+        if self.len.load(Ordering::Relaxed) == 0 {
+            return;
+        }
+        let mut m = self.m.lock();
+        if let Some(list) = m.remove(k) {
+            for (_, send) in list {
+                let _ = send.try_send(());
+            }
+        }
+        self.len.store(m.len(), Ordering::Relaxed);
+    }
+}
+
+/// a registered waiter, unregisters on drop so a timed out or cancelled wait leaves nothing behind
+struct WaitTicket<'a, K: Eq + Hash> {
+    waiters: &'a Waiters<K>,
+    key: K,
+    id: u64,
+    recv: flume::Receiver<()>,
+}
+
+impl<K: Eq + Hash> Drop for WaitTicket<'_, K> {
+    fn drop(&mut self) {
+        let mut m = self.waiters.m.lock();
+        if let Some(list) = m.get_mut(&self.key) {
+            list.retain(|(id, _)| *id != self.id);
+            if list.is_empty() {
+                m.remove(&self.key);
+            }
+        }
+        self.waiters.len.store(m.len(), Ordering::Relaxed);
     }
 }
 
 impl<K, V> SyncHashMap<K, V>
     where
-        K: Eq + Hash,
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
 {
     pub fn new_arc() -> Arc<Self> {
         Arc::new(Self::new())
     }
 
-    pub fn new() -> Self {
-        Self {
-            dirty: UnsafeCell::new(Map::new()),
-            lock: Default::default(),
+    pub fn new() -> Self {
+        Self::with_hasher(RandomState::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_capacity_and_hasher(capacity, RandomState::new())
+    }
+}
+
+impl<K, V, S> SyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    /// Creates an empty map which will use the given hash builder to hash keys.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    /// use std::collections::hash_map::RandomState;
+    ///
+    /// let map = SyncHashMap::with_hasher(RandomState::new());
+    /// map.insert(1, "a");
+    /// assert_eq!(*map.get(&1).unwrap(), "a");
+    /// ```
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_map(Map::with_hasher(hasher))
+    }
+
+    pub fn with_capacity_and_hasher(capacity: usize, hasher: S) -> Self {
+        Self {
+            read: Atomic::new(ReadOnly::new(Map::with_hasher(hasher.clone()))),
+            dirty: UnsafeCell::new(Some(Map::with_capacity_and_hasher(
+                capacity,
+                hasher.clone(),
+            ))),
+            dirty_lock: parking_lot::RwLock::new(()),
+            misses: UnsafeCell::new(0),
+            len: AtomicUsize::new(0),
+            lock: Default::default(),
+            events: MapEvents::new(),
+            waiters: Waiters::new(),
+            snapshots: SnapshotCache::new(),
+            hasher,
+        }
+    }
+
+    /// the map keeps the hash builder of `map`
+    pub fn with_map(map: Map<K, V, S>) -> Self {
+        let len = map.len();
+        let hasher = map.hasher().clone();
+        let mut m = Map::with_capacity_and_hasher(len, hasher.clone());
+        m.extend(map.into_iter().map(|(k, v)| (k, Arc::new(Entry::new(v)))));
+        Self {
+            read: Atomic::new(ReadOnly::new(m)),
+            dirty: UnsafeCell::new(None),
+            dirty_lock: parking_lot::RwLock::new(()),
+            misses: UnsafeCell::new(0),
+            len: AtomicUsize::new(len),
+            lock: Default::default(),
+            events: MapEvents::new(),
+            waiters: Waiters::new(),
+            snapshots: SnapshotCache::new(),
+            hasher,
+        }
+    }
+
+    pub fn hasher(&self) -> &S {
+        &self.hasher
+    }
+
+    /// the replaced value stays readable through the returned guard,
+    /// it is released once neither it nor any reader can observe it.
+    pub fn insert(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let old = self.insert_locked(k, v, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    pub fn insert_mut(&mut self, k: K, v: V) -> Option<V> {
+        self.lock.touch();
+        let guard = unsafe { epoch::unprotected() };
+        let old = self.insert_locked(k, v, guard);
+        Self::take(old)
+    }
+
+    /// the removed value stays readable through the returned guard,
+    /// it is released once neither it nor any reader can observe it.
+    pub fn remove(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let old = self.remove_locked(k, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    /// Insert only if the key is absent, otherwise the present value is returned and `v` is dropped.
+    pub fn insert_if_absent(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        if let Some((_, present)) = self.find_locked(&k, &guard) {
+            let present = present as *const V;
+            drop(g);
+            return Some(unsafe { HashMapRef::new(guard, present) });
+        }
+        let old = self.insert_locked(k, v, &guard);
+        debug_assert!(old.is_null());
+        drop(g);
+        None
+    }
+
+    /// Replace the value only if it equals `expected`, checked under the writer lock.
+    ///
+    /// returns the replaced value, or gives `new` back when the key is absent or holds another value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert("version".to_string(), 1);
+    /// assert_eq!(*map.compare_and_swap("version", &1, 2).unwrap(), 1);
+    /// assert_eq!(map.compare_and_swap("version", &1, 3).unwrap_err(), 3);
+    /// assert_eq!(*map.get("version").unwrap(), 2);
+    /// ```
+    pub fn compare_and_swap<Q>(&self, k: &Q, expected: &V, new: V) -> Result<HashMapRef<'_, V>, V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            V: PartialEq,
+    {
+        self.replace_if(k, |v| v == expected, new)
+    }
+
+    /// Replace the value only if `f` holds for it, checked under the writer lock.
+    ///
+    /// returns the replaced value, or gives `new` back when the key is absent or `f` fails.
+    pub fn replace_if<Q, F>(&self, k: &Q, f: F, new: V) -> Result<HashMapRef<'_, V>, V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            F: FnOnce(&V) -> bool,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let (k, e) = match self.find_key_locked(k, &guard) {
+            Some((k, e, v)) if f(v) => (k, e),
+            _ => return Err(new),
+        };
+        let old = self.replace_locked(&k, &e, new, &guard);
+        drop(g);
+        Ok(self.retire(old.as_raw(), guard).expect("replaced value not found"))
+    }
+
+    /// Set the value to `f` of the current one under the writer lock, returns the previous value.
+    /// nothing happens to an absent key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert("hits", 1);
+    /// assert_eq!(*map.update(&"hits", |v| v + 1).unwrap(), 1);
+    /// assert_eq!(*map.get(&"hits").unwrap(), 2);
+    /// assert_eq!(map.update(&"misses", |v| v + 1).is_none(), true);
+    /// ```
+    pub fn update<Q, F>(&self, k: &Q, f: F) -> Option<HashMapRef<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            F: FnOnce(&V) -> V,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let (k, e, v) = self.find_key_locked(k, &guard)?;
+        let new = f(v);
+        let old = self.replace_locked(&k, &e, new, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    /// Remove the key only if `f` holds for its current value, checked under the writer lock.
+    pub fn remove_if<Q, F>(&self, k: &Q, f: F) -> Option<HashMapRef<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            F: FnOnce(&V) -> bool,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let (k, _, v) = self.find_key_locked(k, &guard)?;
+        if !f(v) {
+            return None;
+        }
+        let old = self.remove_locked(&k, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    pub fn remove_mut(&mut self, k: &K) -> Option<V> {
+        self.lock.touch();
+        let guard = unsafe { epoch::unprotected() };
+        let old = self.remove_locked(k, guard);
+        Self::take(old)
+    }
+
+    /// Insert every entry under one acquisition of the writer lock, returns how many keys were new.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert(1, 0);
+    /// assert_eq!(map.insert_many((0..100).map(|i| (i, i))), 99);
+    /// assert_eq!(map.remove_many(&[0, 1, 1000]), 2);
+    /// map.retain(|_, v| v % 2 == 0);
+    /// assert_eq!(map.len(), 49);
+    /// ```
+    pub fn insert_many<I>(&self, entries: I) -> usize
+        where
+            I: IntoIterator<Item = (K, V)>,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let mut added = 0;
+        for (k, v) in entries {
+            let old = self.insert_locked(k, v, &guard);
+            if old.is_null() {
+                added += 1;
+            } else {
+                unsafe {
+                    guard.defer_destroy(old);
+                }
+            }
+        }
+        drop(g);
+        added
+    }
+
+    /// like [`SyncHashMap::insert_many`]
+    pub fn extend<I>(&self, entries: I)
+        where
+            I: IntoIterator<Item = (K, V)>,
+    {
+        self.insert_many(entries);
+    }
+
+    /// remove every key under one acquisition of the writer lock, returns how many were present
+    pub fn remove_many<'q, I>(&self, keys: I) -> usize
+        where
+            I: IntoIterator<Item = &'q K>,
+            K: 'q,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let mut removed = 0;
+        for k in keys {
+            let old = self.remove_locked(k, &guard);
+            if !old.is_null() {
+                removed += 1;
+                unsafe {
+                    guard.defer_destroy(old);
+                }
+            }
+        }
+        drop(g);
+        removed
+    }
+
+    /// keep only the entries `f` holds for, under one acquisition of the writer lock
+    pub fn retain<F>(&self, mut f: F)
+        where
+            F: FnMut(&K, &V) -> bool,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        for (_, old) in self.extract_locked(|k, v| !f(k, v), &guard) {
+            unsafe {
+                guard.defer_destroy(old);
+            }
+        }
+        drop(g);
+    }
+
+    /// remove and return the entries `f` holds for, under one acquisition of the writer lock
+    pub fn extract_if<F>(&self, f: F) -> Vec<(K, HashMapRef<'_, V>)>
+        where
+            F: FnMut(&K, &V) -> bool,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let removed = self.extract_locked(f, &guard);
+        drop(g);
+        self.retire_all(removed, &guard)
+    }
+
+    /// remove and return every entry
+    pub fn drain(&self) -> Vec<(K, HashMapRef<'_, V>)> {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let entries: Vec<(K, *const V)> = self
+            .entries_locked(&guard)
+            .iter()
+            .filter_map(|(k, e)| Some((k.clone(), e.load(&guard)? as *const V)))
+            .collect();
+        // retires the maps, which own the values
+        self.clear_locked();
+        drop(g);
+        entries
+            .into_iter()
+            .map(|(k, v)| (k, unsafe { HashMapRef::new(epoch::pin(), v) }))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        let g = self.lock.lock();
+        self.clear_locked();
+        drop(g);
+    }
+
+    pub fn clear_mut(&mut self) {
+        self.clear()
+    }
+
+    pub fn shrink_to_fit(&self) {
+        let g = self.lock.lock();
+        self.dirty_mut_locked(|dirty| {
+            if let Some(m) = dirty.as_mut() {
+                m.shrink_to_fit();
+            }
+        });
+        drop(g);
+    }
+
+    pub fn shrink_to_fit_mut(&mut self) {
+        self.shrink_to_fit()
+    }
+
+    pub fn from(map: Map<K, V, S>) -> Self
+        where
+            K: Eq + Hash,
+    {
+        let s = Self::with_map(map);
+        s
+    }
+
+    /// Returns a reference to the value corresponding to the key.
+    ///
+    /// The key may be any borrowed form of the map's key type, but
+    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
+    /// the key type.
+    ///
+    /// Since reading a map is unlocked, it is very fast
+    ///
+    /// The returned reference pins the current epoch, the value stays valid even if
+    /// another thread removes or overwrites the key meanwhile. Use [`SyncHashMap::pin`]
+    /// to read many keys under one guard.
+    ///
+    /// test bench_sync_hash_map_read   ... bench:           8 ns/iter (+/- 0)
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncHashMap};
+    ///
+    /// let mut map = SyncHashMap::new();
+    /// map.insert_mut(1, "a");
+    /// assert_eq!(*map.get(&1).unwrap(), "a");
+    /// assert_eq!(map.get(&2).is_none(), true);
+    /// ```
+    #[inline]
+    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<HashMapRef<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq,
+    {
+        let guard = epoch::pin();
+        let p = self.load(k, &guard)? as *const V;
+        Some(unsafe { HashMapRef::new(guard, p) })
+    }
+
+    /// Pin the current epoch, nothing read through the returned guard is released until it drops.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncHashMap};
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert(1, "a".to_string());
+    /// let guard = map.pin();
+    /// let a = guard.get(&1).unwrap();
+    /// map.remove(&1);
+    /// assert_eq!(a, "a");
+    /// ```
+    pub fn pin(&self) -> HashMapGuard<'_, K, V, S> {
+        HashMapGuard {
+            map: self,
+            guard: epoch::pin(),
+        }
+    }
+
+    /// the value is copied out and published back when the returned guard drops,
+    /// so lock-free readers never observe a value while it is being written.
+    #[inline]
+    pub fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<HashMapRefMut<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq,
+            V: Clone,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let (entry, v) = self.find_locked(k, &guard)?;
+        Some(HashMapRefMut::new(g, entry, v))
+    }
+
+    /// Gets the given key's corresponding entry in the map for in-place manipulation.
+    ///
+    /// The writer lock is held until the entry, or the guard it turns into, is dropped,
+    /// so a read-modify-write through it is atomic with respect to other writers.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncHashMap};
+    ///
+    /// let map = SyncHashMap::new();
+    /// *map.entry("a").or_insert(0) += 1;
+    /// map.entry("a").and_modify(|v| *v += 1).or_insert(0);
+    /// assert_eq!(*map.get("a").unwrap(), 2);
+    /// ```
+    pub fn entry(&self, key: K) -> HashMapEntry<'_, K, V, Blocking, S>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        match self.find_locked(&key, &guard) {
+            Some((entry, v)) => HashMapEntry::Occupied(HashMapOccupiedEntry {
+                map: self,
+                key,
+                value: HashMapRefMut::new(g, entry, v),
+            }),
+            None => HashMapEntry::Vacant(HashMapVacantEntry {
+                map: self,
+                _g: g,
+                key,
+            }),
+        }
+    }
+
+    #[inline]
+    pub fn contains_key(&self, x: &K) -> bool
+        where
+            K: PartialEq,
+    {
+        self.pin().contains_key(x)
+    }
+
+    /// every item pins the epoch on its own, see [`HashMapGuard::iter`] to borrow one guard instead
+    pub fn iter(&self) -> HashRefIter<'_, K, V> {
+        let guard = epoch::pin();
+        let read = self.load_read_complete(&guard);
+        let inner = unsafe { &*(&read.m as *const Entries<K, V, S>) }.iter();
+        HashRefIter { guard, inner }
+    }
+
+    pub fn iter_mut(&self) -> HashIterMut<'_, K, V>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock();
+        let guard = epoch::pin();
+        let read = self.load_read_complete(&guard);
+        let inner = unsafe { &*(&read.m as *const Entries<K, V, S>) }.iter();
+        HashIterMut {
+            _g: g,
+            guard,
+            inner,
+        }
+    }
+
+    /// A consistent copy of the map as of now, later writes do not change it.
+    ///
+    /// every value is cloned under the writer lock, O(n): writers wait for the copy, readers do not.
+    /// until the next write, a copy still held by a caller is returned again without copying,
+    /// the map itself keeps no copy alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert(1, "a");
+    /// let snapshot = map.snapshot();
+    /// map.insert(2, "b");
+    /// assert_eq!(snapshot.len(), 1);
+    /// assert_eq!(snapshot.get(&1), Some(&"a"));
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<HashMap<K, V, S>>
+        where
+            V: Clone,
+    {
+        self.snapshots.get_or_copy(&self.lock, || self.copy_locked())
+    }
+
+    /// Write the map to `path`, encoded through its serde impl.
+    ///
+    /// the map is encoded under the writer lock, so the file holds a consistent state.
+    /// the file is replaced atomically, a crash meanwhile leaves the previous one intact.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let path = std::env::temp_dir().join("dark_std_doc_hash_map.bin");
+    /// let map = SyncHashMap::new();
+    /// map.insert(1, "a".to_string());
+    /// map.save_to(&path).unwrap();
+    /// let loaded = SyncHashMap::<i32, String>::load_from(&path).unwrap();
+    /// assert_eq!(*loaded.get(&1).unwrap(), "a");
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> crate::errors::Result<()>
+        where
+            K: Serialize,
+            V: Serialize,
+    {
+        let g = self.lock.lock();
+        let payload = persist::encode(self);
+        drop(g);
+        persist::save(path.as_ref(), Kind::HashMap, &payload?)
+    }
+
+    /// Read a map written by [`SyncHashMap::save_to`].
+    ///
+    /// a truncated or corrupted file is reported as an error, it is never partially loaded.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> crate::errors::Result<Self>
+        where
+            K: DeserializeOwned,
+            V: DeserializeOwned,
+            S: Default,
+    {
+        let payload = persist::load(path.as_ref(), Kind::HashMap)?;
+        persist::decode(&payload)
+    }
+
+    /// Receive every later insert, remove and clear of the map, in the order they happen.
+    ///
+    /// Values changed in place through `get_mut`, `iter_mut` or an occupied entry's `get_mut` are not reported.
+    /// A subscriber more than `capacity` events behind misses the next ones and receives a
+    /// [`MapEvent::Lagged`] once it catches up. Drop the receiver to unsubscribe.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{MapEvent, SyncHashMap};
+    ///
+    /// let map = SyncHashMap::new();
+    /// let events = map.subscribe(16);
+    /// map.insert(1, "a");
+    /// map.remove(&1);
+    /// assert_eq!(events.recv().unwrap(), MapEvent::Inserted { key: 1, old: None, new: "a" });
+    /// assert_eq!(events.recv().unwrap(), MapEvent::Removed { key: 1, value: "a" });
+    /// ```
+    pub fn subscribe(&self, capacity: usize) -> flume::Receiver<MapEvent<K, V>>
+        where
+            V: Clone,
+    {
+        self.events.subscribe(capacity)
+    }
+
+    /// Wait until `k` is in the map and return its value, at once if it is there already.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// # tokio::runtime::Runtime::new().unwrap().block_on(async {
+    /// let map = std::sync::Arc::new(SyncHashMap::new());
+    /// let m = map.clone();
+    /// tokio::spawn(async move {
+    ///     m.insert_async("reply", 1).await;
+    /// });
+    /// assert_eq!(*map.wait_for(&"reply").await, 1);
+    /// # });
+    /// ```
+    pub async fn wait_for(&self, k: &K) -> HashMapRef<'_, V> {
+        loop {
+            let ticket = self.waiters.register(k);
+            if let Some(v) = self.get(k) {
+                return v;
+            }
+            let _ = ticket.recv.recv_async().await;
+        }
+    }
+
+    /// like [`SyncHashMap::wait_for`], but blocks the thread for at most `timeout`
+    pub fn wait_for_timeout(&self, k: &K, timeout: Duration) -> Option<HashMapRef<'_, V>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let ticket = self.waiters.register(k);
+            if let Some(v) = self.get(k) {
+                return Some(v);
+            }
+            ticket.recv.recv_deadline(deadline).ok()?;
+        }
+    }
+
+    /// Wait until `k` is in the map and remove it. when several wait for the same key,
+    /// each insert is taken by one of them.
+    pub async fn wait_take(&self, k: &K) -> HashMapRef<'_, V> {
+        loop {
+            let ticket = self.waiters.register(k);
+            if let Some(v) = self.remove_async(k).await {
+                return v;
+            }
+            let _ = ticket.recv.recv_async().await;
+        }
+    }
+
+    /// like [`SyncHashMap::wait_take`], but blocks the thread for at most `timeout`
+    pub fn wait_take_timeout(&self, k: &K, timeout: Duration) -> Option<HashMapRef<'_, V>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let ticket = self.waiters.register(k);
+            if let Some(v) = self.remove(k) {
+                return Some(v);
+            }
+            ticket.recv.recv_deadline(deadline).ok()?;
+        }
+    }
+
+    /// like [`SyncHashMap::insert`], but awaits the writer lock instead of blocking the thread
+    pub async fn insert_async(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        let g = self.lock.lock_async().await;
+        let guard = epoch::pin();
+        let old = self.insert_locked(k, v, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    /// like [`SyncHashMap::remove`], but awaits the writer lock instead of blocking the thread
+    pub async fn remove_async(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        let g = self.lock.lock_async().await;
+        let guard = epoch::pin();
+        let old = self.remove_locked(k, &guard);
+        drop(g);
+        self.retire(old.as_raw(), guard)
+    }
+
+    pub async fn clear_async(&self) {
+        let g = self.lock.lock_async().await;
+        self.clear_locked();
+        drop(g);
+    }
+
+    /// the returned guard is `Send` and may be held across `.await`,
+    /// the map must not be written by the same task until it drops. reads never wait for it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::{SyncHashMap};
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let map = SyncHashMap::new();
+    ///     map.insert(1, 1);
+    ///     let mut v = map.get_mut_async(&1).await.unwrap();
+    ///     tokio::task::yield_now().await;
+    ///     *v += 1;
+    ///     drop(v);
+    ///     assert_eq!(*map.get(&1).unwrap(), 2);
+    /// }
+    /// ```
+    pub async fn get_mut_async<Q>(&self, k: &Q) -> Option<HashMapRefMut<'_, V, Async>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            V: Clone,
+    {
+        let g = self.lock.lock_async().await;
+        let guard = epoch::pin();
+        let (entry, v) = self.find_locked(k, &guard)?;
+        Some(HashMapRefMut::new(g, entry, v))
+    }
+
+    pub async fn entry_async(&self, key: K) -> HashMapEntry<'_, K, V, Async, S>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock_async().await;
+        let guard = epoch::pin();
+        match self.find_locked(&key, &guard) {
+            Some((entry, v)) => HashMapEntry::Occupied(HashMapOccupiedEntry {
+                map: self,
+                key,
+                value: HashMapRefMut::new(g, entry, v),
+            }),
+            None => HashMapEntry::Vacant(HashMapVacantEntry {
+                map: self,
+                _g: g,
+                key,
+            }),
+        }
+    }
+
+    /// the writer lock is held until the iterator and every yielded value are dropped
+    pub async fn iter_mut_async(&self) -> HashAsyncIterMut<'_, K, V>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock_async().await;
+        let guard = epoch::pin();
+        if self.load_read(&guard).amended.load(Ordering::Acquire) {
+            self.promote_locked(&guard);
+        }
+        // the read map is only swapped under the lock, which we keep until the iterator drops
+        let read = self.load_read(&guard);
+        let inner = unsafe { &*(&read.m as *const Entries<K, V, S>) }.iter();
+        HashAsyncIterMut { _g: g, inner }
+    }
+
+    /// Run `f` with the writer lock held, its writes are applied together once it returns `Ok`.
+    ///
+    /// inside `f`, read and write through the transaction only, it sees its own writes.
+    /// returning `Err` (see [`HashMapTransaction::abort`]) or panicking discards every write.
+    ///
+    /// the written keys get new slots, published to readers at once, so nobody observes a half
+    /// applied transaction. a commit is O(written keys), except right after the read map was
+    /// promoted: then it first copies the read map, O(n), like the insert of a new key would.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncHashMap;
+    ///
+    /// let map = SyncHashMap::new();
+    /// map.insert("alice", 100);
+    /// map.insert("bob", 0);
+    /// let moved = map.transaction(|tx| {
+    ///     let from = *tx.get(&"alice").unwrap();
+    ///     if from < 30 {
+    ///         return tx.abort();
+    ///     }
+    ///     let to = *tx.get(&"bob").unwrap();
+    ///     tx.insert("alice", from - 30);
+    ///     tx.insert("bob", to + 30);
+    ///     Ok(30)
+    /// });
+    /// assert_eq!(moved, Ok(30));
+    /// assert_eq!(*map.get(&"alice").unwrap(), 70);
+    /// assert_eq!(*map.get(&"bob").unwrap(), 30);
+    /// ```
+    pub fn transaction<F, R>(&self, f: F) -> crate::errors::Result<R>
+        where
+            F: FnOnce(&mut HashMapTransaction<'_, K, V, S>) -> crate::errors::Result<R>,
+    {
+        let g = self.lock.lock();
+        let r = self.transaction_locked(f);
+        drop(g);
+        r
+    }
+
+    /// like [`SyncHashMap::transaction`], but awaits the writer lock instead of blocking the thread
+    pub async fn transaction_async<F, R>(&self, f: F) -> crate::errors::Result<R>
+        where
+            F: FnOnce(&mut HashMapTransaction<'_, K, V, S>) -> crate::errors::Result<R>,
+    {
+        let g = self.lock.lock_async().await;
+        let r = self.transaction_locked(f);
+        drop(g);
+        r
+    }
+
+    pub fn into_iter(self) -> MapIntoIter<K, V> {
+        self.into_inner().into_iter()
+    }
+
+    pub fn into_inner(mut self) -> HashMap<K, V, S> {
+        self.take_map()
+    }
+
+    /// the map no longer keeps a plain `HashMap` to borrow, this returns a copy of it
+    #[deprecated(note = "use `snapshot()` for a copy, or `pin()` to borrow the values")]
+    pub fn dirty_ref(&self) -> Snapshot<HashMap<K, V, S>>
+        where
+            V: Clone,
+    {
+        self.snapshot()
+    }
+
+    /// hold the writer lock across several calls, made through the `_guarded` methods.
+    /// the lock is not reentrant, the other writers of the map panic while it is held.
+    pub(crate) fn write_lock(&self) -> WriteGuard<'_> {
+        self.lock.lock()
+    }
+
+    /// like [`SyncHashMap::insert`], under the guard of `write_lock`
+    pub(crate) fn insert_guarded(&self, _g: &WriteGuard<'_>, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        let guard = epoch::pin();
+        let old = self.insert_locked(k, v, &guard);
+        self.retire(old.as_raw(), guard)
+    }
+
+    /// like [`SyncHashMap::remove`], under the guard of `write_lock`
+    pub(crate) fn remove_guarded(&self, _g: &WriteGuard<'_>, k: &K) -> Option<HashMapRef<'_, V>> {
+        let guard = epoch::pin();
+        let old = self.remove_locked(k, &guard);
+        self.retire(old.as_raw(), guard)
+    }
+
+    /// like [`SyncHashMap::clear`], under the guard of `write_lock`
+    pub(crate) fn clear_guarded(&self, _g: &WriteGuard<'_>) {
+        self.clear_locked();
+    }
+
+    /// like [`SyncHashMap::snapshot`], under the guard of `write_lock`. never cached,
+    /// the holder of the guard may have written since the lock was taken
+    pub(crate) fn snapshot_guarded(&self, _g: &WriteGuard<'_>) -> Snapshot<HashMap<K, V, S>>
+        where
+            V: Clone,
+    {
+        Snapshot::new(self.copy_locked())
+    }
+
+    /// like [`SyncHashMap::save_to`], under the guard of `write_lock`
+    pub(crate) fn save_to_guarded<P: AsRef<Path>>(
+        &self,
+        _g: &WriteGuard<'_>,
+        path: P,
+    ) -> crate::errors::Result<()>
+        where
+            K: Serialize,
+            V: Serialize,
+    {
+        let payload = persist::encode(self)?;
+        persist::save(path.as_ref(), Kind::HashMap, &payload)
+    }
+
+    /// every live value cloned, only valid under the lock
+    fn copy_locked(&self) -> HashMap<K, V, S>
+        where
+            V: Clone,
+    {
+        let guard = epoch::pin();
+        let entries = self.entries_locked(&guard);
+        let mut m = Map::with_capacity_and_hasher(entries.len(), self.hasher.clone());
+        m.extend(
+            entries
+                .iter()
+                .filter_map(|(k, e)| Some((k.clone(), e.load(&guard)?.clone()))),
+        );
+        m
+    }
+
+    #[inline]
+    fn load_read<'g>(&self, guard: &'g Guard) -> &'g ReadOnly<K, V, S> {
+        unsafe { self.read.load(Ordering::Acquire, guard).deref() }
+    }
+
+    /// the entry of a live key, only valid under the lock
+    fn find_locked<'g, Q>(&self, k: &Q, guard: &'g Guard) -> Option<(Arc<Entry<V>>, &'g V)>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        let read = self.load_read(guard);
+        let e = match read.get(k) {
+            Some(e) => e.clone(),
+            None => {
+                if !read.amended.load(Ordering::Acquire) {
+                    return None;
+                }
+                let dirty = unsafe { &*self.dirty.get() };
+                let e = dirty.as_ref().and_then(|m| m.get(k)).cloned();
+                self.miss_locked(guard);
+                e?
+            }
+        };
+        let v = e.load(guard)?;
+        Some((e, v))
+    }
+
+    /// like `find_locked`, with a copy of the key as stored in the map.
+    /// not borrowed from the maps, so the caller may run user code before writing
+    fn find_key_locked<'g, Q>(&self, k: &Q, guard: &'g Guard) -> Option<(K, Arc<Entry<V>>, &'g V)>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        let (e, v) = self.find_locked(k, guard)?;
+        let (k, _) = self.entries_locked(guard).get_key_value(k)?;
+        Some((k.clone(), e, v))
+    }
+
+    fn insert_locked<'g>(&self, k: K, v: V, guard: &'g Guard) -> Shared<'g, V> {
+        let read = self.load_read(guard);
+        let old = if let Some(e) = read.get(&k) {
+            // the entry was expunged, which implies dirty exists and lacks it
+            let expunged = e.unexpunge_locked();
+            let old = e.swap_locked(Owned::new(v), guard);
+            if expunged {
+                self.dirty_mut_locked(|dirty| {
+                    if let Some(dirty) = dirty.as_mut() {
+                        dirty.insert(k.clone(), e.clone());
+                    }
+                });
+            }
+            self.notify_inserted(&k, old, e, guard);
+            old
+        } else if let Some(e) = unsafe { &*self.dirty.get() }
+            .as_ref()
+            .and_then(|m| m.get(&k))
+        {
+            let old = e.swap_locked(Owned::new(v), guard);
+            self.notify_inserted(&k, old, e, guard);
+            old
+        } else {
+            if !read.amended.load(Ordering::Acquire) {
+                self.dirty_locked(guard);
+                read.amended.store(true, Ordering::Release);
+            }
+            let e = Arc::new(Entry::new(v));
+            self.dirty_mut_locked(|dirty| {
+                if let Some(dirty) = dirty.as_mut() {
+                    dirty.insert(k.clone(), e.clone());
+                }
+            });
+            self.notify_inserted(&k, Shared::null(), &e, guard);
+            Shared::null()
+        };
+        if old.is_null() {
+            self.len.fetch_add(1, Ordering::AcqRel);
+        }
+        old
+    }
+
+    /// every live entry, only valid under the lock
+    fn entries_locked<'g>(&self, guard: &'g Guard) -> &'g Entries<K, V, S> {
+        match unsafe { &*self.dirty.get() } {
+            // dirty, when present, holds every live entry of read
+            Some(dirty) => dirty,
+            None => &self.load_read(guard).m,
+        }
+    }
+
+    /// unlink the entries `f` holds for, the values are left to the caller to retire
+    fn extract_locked<'g, F>(&self, mut f: F, guard: &'g Guard) -> Vec<(K, Shared<'g, V>)>
+        where
+            F: FnMut(&K, &V) -> bool,
+    {
+        // `f` runs once nothing borrows the maps anymore
+        let entries: Vec<(K, Arc<Entry<V>>)> = self
+            .entries_locked(guard)
+            .iter()
+            .map(|(k, e)| (k.clone(), e.clone()))
+            .collect();
+        let keys: Vec<K> = entries
+            .into_iter()
+            .filter(|(k, e)| e.load(guard).map(|v| f(k, v)).unwrap_or_default())
+            .map(|(k, _)| k)
+            .collect();
+        keys.into_iter()
+            .map(|k| {
+                let old = self.remove_locked(&k, guard);
+                (k, old)
+            })
+            .collect()
+    }
+
+    /// like `retire`, `guard` was pinned before the values were unlinked
+    fn retire_all(
+        &self,
+        removed: Vec<(K, Shared<'_, V>)>,
+        guard: &Guard,
+    ) -> Vec<(K, HashMapRef<'_, V>)> {
+        removed
+            .into_iter()
+            .map(|(k, old)| unsafe {
+                guard.defer_destroy(old);
+                // pinned while `guard` is, so it keeps protecting the value once `guard` drops
+                (k, HashMapRef::new(epoch::pin(), old.as_raw()))
+            })
+            .collect()
+    }
+
+    /// overwrite the value of a live key found under the lock
+    fn replace_locked<'g>(&self, k: &K, e: &Entry<V>, v: V, guard: &'g Guard) -> Shared<'g, V> {
+        let old = e.swap_locked(Owned::new(v), guard);
+        self.notify_inserted(k, old, e, guard);
+        old
+    }
+
+    fn remove_locked<'g>(&self, k: &K, guard: &'g Guard) -> Shared<'g, V> {
+        let read = self.load_read(guard);
+        let old = if let Some(e) = read.get(k) {
+            e.delete_locked(guard)
+        } else if read.amended.load(Ordering::Acquire) {
+            let e = self.dirty_mut_locked(|dirty| dirty.as_mut().and_then(|m| m.remove(k)));
+            self.miss_locked(guard);
+            match e {
+                None => Shared::null(),
+                Some(e) => e.delete_locked(guard),
+            }
+        } else {
+            Shared::null()
+        };
+        if !old.is_null() {
+            self.len.fetch_sub(1, Ordering::AcqRel);
+            self.events.emit(|| MapChange::Removed {
+                key: k,
+                value: unsafe { old.deref() },
+            });
+        }
+        old
+    }
+
+    fn notify_inserted(&self, k: &K, old: Shared<'_, V>, e: &Entry<V>, guard: &Guard) {
+        self.events.emit(|| MapChange::Inserted {
+            key: k,
+            old: unsafe { old.as_ref() },
+            new: e.load(guard).expect("inserted value not found"),
+        });
+        // called once the value is reachable, woken waiters look it up without the lock
+        self.waiters.wake(k);
+    }
+
+    fn transaction_locked<F, R>(&self, f: F) -> crate::errors::Result<R>
+        where
+            F: FnOnce(&mut HashMapTransaction<'_, K, V, S>) -> crate::errors::Result<R>,
+    {
+        let mut tx = HashMapTransaction {
+            map: self,
+            guard: epoch::pin(),
+            writes: Map::with_hasher(self.hasher.clone()),
+        };
+        let r = f(&mut tx)?;
+        let HashMapTransaction { guard, writes, .. } = tx;
+        if !writes.is_empty() {
+            self.commit_locked(writes, &guard);
+        }
+        Ok(r)
+    }
+
+    /// publish the writes of a transaction at once: the written keys get new entries in `dirty`,
+    /// all swapped in while readers are kept out of it, and the entries of `read` they replace are
+    /// marked moved so readers look into `dirty` for them. the replaced entries are never written
+    /// again, a reader or an iterator that got one first keeps seeing the value before the commit.
+    fn commit_locked(&self, writes: Map<K, Option<V>, S>, guard: &Guard) {
+        // like an insert of a new key, the first write after a promotion copies read into dirty
+        self.dirty_locked(guard);
+        let read = self.load_read(guard);
+        let mut changes = Vec::with_capacity(writes.len());
+        let mut replaced = Vec::with_capacity(writes.len());
+        self.dirty_mut_locked(|dirty| {
+            let dirty = dirty.as_mut().expect("dirty not found");
+            // set before any entry is moved, a reader finding one moved looks into dirty
+            read.amended.store(true, Ordering::Release);
+            for (k, v) in writes {
+                let e = dirty.remove(&k);
+                let old = e.as_ref().and_then(|e| e.load(guard)).map(|v| v as *const V);
+                if let Some(e) = read.m.get(&k) {
+                    e.moved.store(true, Ordering::Release);
+                }
+                let new = v.map(|v| {
+                    let e = Arc::new(Entry::new(v));
+                    dirty.insert(k.clone(), e.clone());
+                    e
+                });
+                replaced.extend(e);
+                changes.push((k, old, new));
+            }
+        });
+        // a reader may have loaded a value of a replaced entry, retire them as well
+        unsafe {
+            guard.defer_unchecked(move || drop(replaced));
+        }
+        for (k, old, new) in changes {
+            let old = old.map(|v| unsafe { &*v });
+            match (old, new) {
+                (old, Some(e)) => {
+                    if old.is_none() {
+                        self.len.fetch_add(1, Ordering::AcqRel);
+                    }
+                    self.events.emit(|| MapChange::Inserted {
+                        key: &k,
+                        old,
+                        new: e.load(guard).expect("inserted value not found"),
+                    });
+                    self.waiters.wake(&k);
+                }
+                (Some(old), None) => {
+                    self.len.fetch_sub(1, Ordering::AcqRel);
+                    self.events.emit(|| MapChange::Removed { key: &k, value: old });
+                }
+                (None, None) => {}
+            }
+        }
+    }
+
+    /// hand a value that was unlinked under the lock to the collector,
+    /// the returned guard was pinned before, so the value outlives it
+    fn retire(&self, old: *const V, guard: Guard) -> Option<HashMapRef<'_, V>> {
+        if old.is_null() {
+            return None;
+        }
+        unsafe {
+            guard.defer_destroy(Shared::from(old));
+            Some(HashMapRef::new(guard, old))
+        }
+    }
+
+    /// only sound with exclusive access, no reader can observe the value
+    fn take(old: Shared<'_, V>) -> Option<V> {
+        if old.is_null() {
+            return None;
+        }
+        Some(*unsafe { old.into_owned() }.into_box())
+    }
+
+    fn load<'g, Q>(&self, k: &Q, guard: &'g Guard) -> Option<&'g V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        let read = self.load_read(guard);
+        if let Some(e) = read.get(k) {
+            return e.load(guard);
+        }
+        if !read.amended.load(Ordering::Acquire) {
+            return None;
+        }
+        let r = self.dirty_lock.read();
+        // read may be promoted while we were waiting for dirty
+        let read = self.load_read(guard);
+        let v = match read.get(k) {
+            Some(e) => return e.load(guard),
+            None => {
+                if !read.amended.load(Ordering::Acquire) {
+                    return None;
+                }
+                let dirty = unsafe { &*self.dirty.get() };
+                dirty
+                    .as_ref()
+                    .and_then(|m| m.get(k))
+                    .and_then(|e| e.load(guard))
+            }
+        };
+        drop(r);
+        // never wait for a writer, it may be this very task holding a guard across an `.await`.
+        // the miss is left uncounted then, a later read promotes dirty instead
+        if let Some(g) = self.lock.try_lock() {
+            self.miss_locked(guard);
+            drop(g);
+        }
+        v
+    }
+
+    /// the read map holding every key, promote dirty first if needed.
+    /// while a writer holds the lock, a copy of dirty is returned instead, released once `guard` unpins
+    fn load_read_complete<'g>(&self, guard: &'g Guard) -> &'g ReadOnly<K, V, S> {
+        let read = self.load_read(guard);
+        if !read.amended.load(Ordering::Acquire) {
+            return read;
+        }
+        if let Some(g) = self.lock.try_lock() {
+            if self.load_read(guard).amended.load(Ordering::Acquire) {
+                self.promote_locked(guard);
+            }
+            drop(g);
+            return self.load_read(guard);
+        }
+        let r = self.dirty_lock.read();
+        let read = self.load_read(guard);
+        let copy = match unsafe { &*self.dirty.get() } {
+            Some(dirty) if read.amended.load(Ordering::Acquire) => {
+                Owned::new(ReadOnly::new(dirty.clone())).into_shared(guard)
+            }
+            // promoted meanwhile
+            _ => return read,
+        };
+        drop(r);
+        unsafe {
+            guard.defer_destroy(copy);
+            copy.deref()
+        }
+    }
+
+    fn miss_locked(&self, guard: &Guard) {
+        let misses = unsafe { &mut *self.misses.get() };
+        *misses += 1;
+        let dirty_len = unsafe { &*self.dirty.get() }
+            .as_ref()
+            .map(|m| m.len())
+            .unwrap_or_default();
+        if *misses < dirty_len {
+            return;
         }
+        self.promote_locked(guard);
     }
 
-    pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            dirty: UnsafeCell::new(Map::with_capacity(capacity)),
-            lock: Default::default(),
+    fn promote_locked(&self, guard: &Guard) {
+        // readers looking into dirty find the new read once they get in
+        self.dirty_mut_locked(|dirty| {
+            if let Some(dirty) = dirty.take() {
+                let old = self
+                    .read
+                    .swap(Owned::new(ReadOnly::new(dirty)), Ordering::AcqRel, guard);
+                unsafe {
+                    guard.defer_destroy(old);
+                }
+            }
+        });
+        unsafe {
+            *self.misses.get() = 0;
         }
     }
 
-    pub fn with_map(map: Map<K, V>) -> Self {
-        Self {
-            dirty: UnsafeCell::new(map),
-            lock: Default::default(),
+    fn dirty_locked(&self, guard: &Guard) {
+        if unsafe { &*self.dirty.get() }.is_some() {
+            return;
         }
+        let read = self.load_read(guard);
+        let mut m = Map::with_capacity_and_hasher(read.m.len(), self.hasher.clone());
+        // a moved entry only exists while dirty does
+        for (k, e) in read.m.iter() {
+            if !e.try_expunge_locked(guard) {
+                m.insert(k.clone(), e.clone());
+            }
+        }
+        self.dirty_mut_locked(|dirty| *dirty = Some(m));
     }
 
-    pub fn insert(&self, k: K, v: V) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        let r = m.insert(k, v);
-        drop(g);
+    /// change dirty under the lock, keeping out the readers looking into it
+    fn dirty_mut_locked<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut Option<Entries<K, V, S>>) -> R,
+    {
+        let w = self.dirty_lock.write();
+        let r = f(unsafe { &mut *self.dirty.get() });
+        drop(w);
         r
     }
 
-    pub fn insert_mut(&mut self, k: K, v: V) -> Option<V> {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.insert(k, v)
+    fn clear_locked(&self) {
+        let guard = epoch::pin();
+        let old = self.read.swap(
+            Owned::new(ReadOnly::new(Map::with_hasher(self.hasher.clone()))),
+            Ordering::AcqRel,
+            &guard,
+        );
+        unsafe {
+            guard.defer_destroy(old);
+        }
+        // a reader may have loaded a value of the dirty map, retire it as well
+        if let Some(dirty) = self.dirty_mut_locked(|dirty| dirty.take()) {
+            unsafe {
+                guard.defer_unchecked(move || drop(dirty));
+            }
+        }
+        unsafe {
+            *self.misses.get() = 0;
+        }
+        self.len.store(0, Ordering::Release);
+        self.events.emit(|| MapChange::Cleared);
     }
 
-    pub fn remove(&self, k: &K) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        let r = m.remove(k);
-        drop(g);
-        r
+    /// drain every live value, only sound with exclusive access
+    fn take_map(&mut self) -> HashMap<K, V, S> {
+        unsafe {
+            let guard = epoch::unprotected();
+            let read = self.read.swap(
+                Owned::new(ReadOnly::new(Map::with_hasher(self.hasher.clone()))),
+                Ordering::Relaxed,
+                guard,
+            );
+            let read = read.into_owned().into_box();
+            // dirty, when present, holds every live entry of read
+            let entries = match self.dirty.get_mut().take() {
+                None => read.m,
+                Some(dirty) => {
+                    drop(read);
+                    dirty
+                }
+            };
+            *self.misses.get_mut() = 0;
+            self.len.store(0, Ordering::Relaxed);
+            let mut m = Map::with_capacity_and_hasher(entries.len(), self.hasher.clone());
+            m.extend(entries.into_iter().filter_map(|(k, e)| {
+                let v = e.delete_locked(guard);
+                if v.is_null() {
+                    None
+                } else {
+                    Some((k, *v.into_owned().into_box()))
+                }
+            }));
+            m
+        }
     }
+}
 
-    pub fn remove_mut(&mut self, k: &K) -> Option<V> {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.remove(k)
+impl<K: Eq + Hash, V, S> Drop for SyncHashMap<K, V, S> {
+    fn drop(&mut self) {
+        // the last `Entry` of a key drops its value, read and dirty share them
+        unsafe {
+            let read = self.read.load(Ordering::Relaxed, epoch::unprotected());
+            drop(read.into_owned());
+        }
+        drop(self.dirty.get_mut().take());
     }
+}
 
-    pub fn len(&self) -> usize {
-        unsafe { (&*self.dirty.get()).len() }
+impl<K, V, S> Default for SyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
     }
+}
 
-    pub fn is_empty(&self) -> bool {
-        unsafe { (&*self.dirty.get()).is_empty() }
+/// a value of the map, kept alive by the epoch pinned inside
+pub struct HashMapRef<'a, V> {
+    _guard: Guard,
+    value: &'a V,
+}
+
+impl<'a, V> HashMapRef<'a, V> {
+    /// `value` must have been loaded or retired while `guard` was pinned
+    unsafe fn new(guard: Guard, value: *const V) -> Self {
+        Self {
+            _guard: guard,
+            value: &*value,
+        }
     }
 
-    pub fn clear(&self) {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.clear();
-        drop(g);
+    /// a reference to a part of the value, the epoch stays pinned
+    pub fn map<U, F>(r: Self, f: F) -> HashMapRef<'a, U>
+        where
+            F: FnOnce(&V) -> &U,
+    {
+        HashMapRef {
+            value: f(r.value),
+            _guard: r._guard,
+        }
     }
+}
 
-    pub fn clear_mut(&mut self) {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.clear();
+impl<V> Deref for HashMapRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
     }
+}
 
-    pub fn shrink_to_fit(&self) {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.shrink_to_fit();
-        drop(g);
+impl<V> Debug for HashMapRef<'_, V>
+    where
+        V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
     }
+}
 
-    pub fn shrink_to_fit_mut(&mut self) {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.shrink_to_fit()
+impl<V> Display for HashMapRef<'_, V>
+    where
+        V: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
     }
+}
 
-    pub fn from(map: Map<K, V>) -> Self
-        where
-            K: Eq + Hash,
-    {
-        let s = Self::with_map(map);
-        s
+impl<V> PartialEq<Self> for HashMapRef<'_, V>
+    where
+        V: Eq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.value.eq(other.value)
     }
+}
 
-    /// Returns a reference to the value corresponding to the key.
-    ///
-    /// The key may be any borrowed form of the map's key type, but
-    /// [`Hash`] and [`Eq`] on the borrowed form *must* match those for
-    /// the key type.
-    ///
-    /// Since reading a map is unlocked, it is very fast
-    ///
-    /// test bench_sync_hash_map_read   ... bench:           8 ns/iter (+/- 0)
-    /// # Examples
-    ///
-    /// ```
-    /// use dark_std::sync::{SyncHashMap};
-    ///
-    /// let mut map = SyncHashMap::new();
-    /// map.insert_mut(1, "a");
-    /// assert_eq!(*map.get(&1).unwrap(), "a");
-    /// assert_eq!(map.get(&2).is_none(), true);
-    /// ```
+impl<V> Eq for HashMapRef<'_, V> where V: Eq {}
+
+/// a pinned epoch of one map, references read through it stay valid until it drops.
+/// keep it short-lived, memory retired by writers is not released while it is held.
+pub struct HashMapGuard<'a, K: Eq + Hash, V, S = RandomState> {
+    map: &'a SyncHashMap<K, V, S>,
+    guard: Guard,
+}
+
+impl<K, V, S> HashMapGuard<'_, K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
     #[inline]
-    pub fn get<Q: ?Sized>(&self, k: &Q) -> Option<&V>
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
         where
             K: Borrow<Q>,
-            Q: Hash + Eq,
+            Q: Hash + Eq + ?Sized,
     {
-        unsafe { (&*self.dirty.get()).get(k) }
+        self.map.load(k, &self.guard)
     }
 
     #[inline]
-    pub fn get_mut<Q: ?Sized>(&self, k: &Q) -> Option<HashMapRefMut<'_, V>>
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
         where
             K: Borrow<Q>,
-            Q: Hash + Eq,
+            Q: Hash + Eq + ?Sized,
     {
-        Some(HashMapRefMut {
-            _g: self.lock.lock(),
-            value: {
-                let m = unsafe { &mut *self.dirty.get() };
-                m.get_mut(k)?
-            },
-        })
+        self.get(k).is_some()
     }
 
-    #[inline]
-    pub fn contains_key(&self, x: &K) -> bool
-        where
-            K: PartialEq,
-    {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.contains_key(x)
+    pub fn len(&self) -> usize {
+        self.map.len()
     }
 
-    pub fn iter(&self) -> std::collections::hash_map::Iter<'_, K, V> {
-        unsafe { (&*self.dirty.get()).iter() }
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
     }
 
-    pub fn iter_mut(&self) -> HashIterMut<'_, K, V> {
-        return HashIterMut {
-            _g: self.lock.lock(),
-            inner: {
-                let m = unsafe { &mut *self.dirty.get() };
-                m.iter_mut()
-            },
-        };
+    pub fn iter(&self) -> HashIter<'_, K, V> {
+        let read = self.map.load_read_complete(&self.guard);
+        HashIter {
+            guard: &self.guard,
+            inner: read.m.iter(),
+        }
     }
 
-    pub fn into_iter(self) -> MapIntoIter<K, V> {
-        self.dirty.into_inner().into_iter()
+    /// unpin and pin again, letting writers release what they retired meanwhile
+    pub fn repin(&mut self) {
+        self.guard.repin();
+    }
+}
+
+/// `map[&k]` can not outlive a concurrent remove, index a guard instead: `map.pin()[&k]`
+impl<K, V, S, Q> Index<&Q> for HashMapGuard<'_, K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Borrow<Q>,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+        Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, k: &Q) -> &Self::Output {
+        self.get(k).expect("no entry found for key")
+    }
+}
+
+impl<'g, K, V, S> IntoIterator for &'g HashMapGuard<'_, K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (&'g K, &'g V);
+    type IntoIter = HashIter<'g, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// a copy of the value, written back to the map when dropped
+pub struct HashMapRefMut<'a, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    entry: Arc<Entry<V>>,
+    origin: *const V,
+    value: Option<Box<V>>,
+    changed: bool,
+}
+
+/// the copy is owned by the task holding the lock
+unsafe impl<V: Send + Sync> Send for HashMapRefMut<'_, V, Async> {}
+
+impl<'a, V: Clone, M> HashMapRefMut<'a, V, M> {
+    /// `value` is the current value of `entry`, loaded under the lock held by `g`
+    fn new(g: WriteGuard<'a, M>, entry: Arc<Entry<V>>, value: &V) -> Self {
+        Self {
+            _g: g,
+            origin: value,
+            entry,
+            value: Some(Box::new(value.clone())),
+            changed: false,
+        }
     }
 
-    pub fn dirty_ref(&self) -> &HashMap<K, V> {
-        unsafe { &*self.dirty.get() }
+    /// write a copy of the value back now, returns the value it replaced.
+    /// `None` if this thread overwrote or removed the key meanwhile, like on drop.
+    fn publish<'g>(&mut self, guard: &'g Guard) -> Option<Shared<'g, V>> {
+        let origin = Shared::from(self.origin);
+        let copy = Owned::new(V::clone(self.value.as_ref().unwrap()));
+        let new = self
+            .entry
+            .p
+            .compare_exchange(origin, copy, Ordering::AcqRel, Ordering::Acquire, guard)
+            .ok()?;
+        self.origin = new.as_raw();
+        self.changed = false;
+        Some(origin)
     }
+}
 
-    pub fn into_inner(self) -> HashMap<K, V> {
-        self.dirty.into_inner()
+impl<V, M> HashMapRefMut<'_, V, M> {
+    /// take the copy out, nothing is written back on drop
+    fn take(&mut self) -> V {
+        self.changed = false;
+        *self.value.take().unwrap()
     }
 }
 
-pub struct HashMapRefMut<'a, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
-    value: &'a mut V,
+impl<V, M> Drop for HashMapRefMut<'_, V, M> {
+    fn drop(&mut self) {
+        if !self.changed {
+            return;
+        }
+        if let Some(value) = self.value.take() {
+            let guard = epoch::pin();
+            let origin = Shared::from(self.origin);
+            // the key may be overwritten or removed by this thread meanwhile, then the copy is dropped.
+            // a HashMapRefMut is only built by SyncHashMap, which requires `V: Send + 'static`
+            if self
+                .entry
+                .p
+                .compare_exchange(
+                    origin,
+                    Owned::from(value),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                    &guard,
+                )
+                .is_ok()
+            {
+                unsafe {
+                    guard.defer_destroy(origin);
+                }
+            }
+        }
+    }
 }
 
-impl<'a, V> Deref for HashMapRefMut<'_, V> {
+impl<V, M> Deref for HashMapRefMut<'_, V, M> {
     type Target = V;
 
     fn deref(&self) -> &Self::Target {
-        self.value
+        self.value.as_ref().unwrap()
     }
 }
 
-impl<'a, V> DerefMut for HashMapRefMut<'_, V> {
+impl<V, M> DerefMut for HashMapRefMut<'_, V, M> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.value
+        self.changed = true;
+        self.value.as_mut().unwrap()
     }
 }
 
-impl<'a, V> Debug for HashMapRefMut<'_, V>
+impl<V, M> Debug for HashMapRefMut<'_, V, M>
     where
         V: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.value.fmt(f)
+        self.deref().fmt(f)
     }
 }
 
-impl<'a, V> Display for HashMapRefMut<'_, V>
+impl<V, M> Display for HashMapRefMut<'_, V, M>
     where
         V: Display,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.value.fmt(f)
+        self.deref().fmt(f)
     }
 }
 
-impl<'a, V> PartialEq<Self> for HashMapRefMut<'_, V>
+impl<V, M> PartialEq<Self> for HashMapRefMut<'_, V, M>
     where
         V: Eq,
 {
     fn eq(&self, other: &Self) -> bool {
-        self.value.eq(&other.value)
+        self.deref().eq(other.deref())
     }
 }
 
-impl<'a, V> Eq for HashMapRefMut<'_, V> where V: Eq {}
+impl<V, M> Eq for HashMapRefMut<'_, V, M> where V: Eq {}
 
-pub struct HashIterMut<'a, K, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
-    inner: MapIterMut<'a, K, V>,
+/// A view into a single entry of a [`SyncHashMap`], holding the writer lock.
+pub enum HashMapEntry<'a, K: Eq + Hash, V, M = Blocking, S = RandomState> {
+    Occupied(HashMapOccupiedEntry<'a, K, V, M, S>),
+    Vacant(HashMapVacantEntry<'a, K, V, M, S>),
 }
 
-impl<'a, K, V> Deref for HashIterMut<'a, K, V> {
-    type Target = MapIterMut<'a, K, V>;
+pub struct HashMapOccupiedEntry<'a, K: Eq + Hash, V, M = Blocking, S = RandomState> {
+    map: &'a SyncHashMap<K, V, S>,
+    key: K,
+    value: HashMapRefMut<'a, V, M>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        &self.inner
+pub struct HashMapVacantEntry<'a, K: Eq + Hash, V, M = Blocking, S = RandomState> {
+    map: &'a SyncHashMap<K, V, S>,
+    _g: WriteGuard<'a, M>,
+    key: K,
+}
+
+impl<'a, K, V, M, S> HashMapEntry<'a, K, V, M, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
+    pub fn or_insert(self, default: V) -> HashMapRefMut<'a, V, M> {
+        match self {
+            HashMapEntry::Occupied(e) => e.into_mut(),
+            HashMapEntry::Vacant(e) => e.insert(default),
+        }
+    }
+
+    pub fn or_insert_with<F: FnOnce() -> V>(self, default: F) -> HashMapRefMut<'a, V, M> {
+        match self {
+            HashMapEntry::Occupied(e) => e.into_mut(),
+            HashMapEntry::Vacant(e) => e.insert(default()),
+        }
+    }
+
+    pub fn or_insert_with_key<F: FnOnce(&K) -> V>(self, default: F) -> HashMapRefMut<'a, V, M> {
+        match self {
+            HashMapEntry::Occupied(e) => e.into_mut(),
+            HashMapEntry::Vacant(e) => {
+                let v = default(e.key());
+                e.insert(v)
+            }
+        }
+    }
+
+    pub fn or_default(self) -> HashMapRefMut<'a, V, M>
+        where
+            V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+
+    pub fn and_modify<F: FnOnce(&mut V)>(self, f: F) -> Self {
+        match self {
+            HashMapEntry::Occupied(mut e) => {
+                f(e.get_mut());
+                HashMapEntry::Occupied(e)
+            }
+            HashMapEntry::Vacant(e) => HashMapEntry::Vacant(e),
+        }
+    }
+
+    pub fn key(&self) -> &K {
+        match self {
+            HashMapEntry::Occupied(e) => e.key(),
+            HashMapEntry::Vacant(e) => e.key(),
+        }
     }
 }
 
-impl<'a, K, V> DerefMut for HashIterMut<'a, K, V> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.inner
+impl<'a, K, V, M, S> HashMapOccupiedEntry<'a, K, V, M, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn get(&self) -> &V {
+        &self.value
+    }
+
+    /// changes are published to readers when the entry is dropped
+    pub fn get_mut(&mut self) -> &mut V {
+        &mut self.value
+    }
+
+    pub fn into_mut(self) -> HashMapRefMut<'a, V, M> {
+        self.value
+    }
+
+    /// Sets the value of the entry, and returns the entry's old value.
+    ///
+    /// unlike a change through [`HashMapOccupiedEntry::get_mut`], the value is published to readers
+    /// at once, subscribers are notified after that.
+    pub fn insert(&mut self, value: V) -> V {
+        let old = std::mem::replace(&mut *self.value, value);
+        let guard = epoch::pin();
+        if let Some(replaced) = self.value.publish(&guard) {
+            self.map
+                .notify_inserted(&self.key, replaced, &self.value.entry, &guard);
+            unsafe {
+                guard.defer_destroy(replaced);
+            }
+        }
+        old
+    }
+
+    pub fn remove(self) -> V {
+        self.remove_entry().1
+    }
+
+    pub fn remove_entry(mut self) -> (K, V) {
+        let v = self.value.take();
+        let guard = epoch::pin();
+        let old = self.map.remove_locked(&self.key, &guard);
+        if !old.is_null() {
+            unsafe {
+                guard.defer_destroy(old);
+            }
+        }
+        (self.key, v)
+    }
+}
+
+impl<'a, K, V, M, S> HashMapVacantEntry<'a, K, V, M, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    pub fn into_key(self) -> K {
+        self.key
+    }
+
+    pub fn insert(self, value: V) -> HashMapRefMut<'a, V, M> {
+        let guard = epoch::pin();
+        self.map.insert_locked(self.key.clone(), value, &guard);
+        let (entry, v) = self
+            .map
+            .find_locked(&self.key, &guard)
+            .expect("inserted key not found");
+        HashMapRefMut::new(self._g, entry, v)
+    }
+}
+
+pub struct HashIter<'a, K, V> {
+    guard: &'a Guard,
+    inner: MapIter<'a, K, Arc<Entry<V>>>,
+}
+
+impl<'a, K, V> Iterator for HashIter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, e) = self.inner.next()?;
+            if let Some(v) = e.load(self.guard) {
+                return Some((k, v));
+            }
+        }
     }
 }
 
-impl<'a, K, V> Iterator for HashIterMut<'a, K, V> {
-    type Item = (&'a K, &'a mut V);
+pub struct HashRefIter<'a, K, V> {
+    guard: Guard,
+    inner: MapIter<'a, K, Arc<Entry<V>>>,
+}
+
+impl<'a, K, V> Iterator for HashRefIter<'a, K, V> {
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        loop {
+            let (k, e) = self.inner.next()?;
+            if let Some(v) = e.load(&self.guard) {
+                // the read map and the value are protected by our guard, a fresh pin taken
+                // now keeps protecting them after this iterator is dropped
+                unsafe {
+                    return Some((
+                        HashMapRef::new(epoch::pin(), k),
+                        HashMapRef::new(epoch::pin(), v),
+                    ));
+                }
+            }
+        }
     }
 }
 
-impl<'a, K, V> IntoIterator for &'a SyncHashMap<K, V>
+/// the writes of a [`SyncHashMap::transaction`], kept aside until it commits
+pub struct HashMapTransaction<'a, K: Eq + Hash, V, S = RandomState> {
+    map: &'a SyncHashMap<K, V, S>,
+    guard: Guard,
+    /// `None` removes the key
+    writes: Map<K, Option<V>, S>,
+}
+
+impl<K, V, S> HashMapTransaction<'_, K, V, S>
     where
-        K: Eq + Hash,
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
 {
-    type Item = (&'a K, &'a V);
-    type IntoIter = MapIter<'a, K, V>;
+    /// the value as written by this transaction so far
+    pub fn get<Q>(&self, k: &Q) -> Option<&V>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        match self.writes.get(k) {
+            Some(v) => v.as_ref(),
+            None => self.map.find_locked(k, &self.guard).map(|(_, v)| v),
+        }
+    }
+
+    pub fn contains_key<Q>(&self, k: &Q) -> bool
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        self.get(k).is_some()
+    }
+
+    pub fn insert(&mut self, k: K, v: V) {
+        self.writes.insert(k, Some(v));
+    }
+
+    /// returns whether the key was present
+    pub fn remove(&mut self, k: &K) -> bool {
+        let present = self.contains_key(k);
+        self.writes.insert(k.clone(), None);
+        present
+    }
+
+    /// the error to return from the transaction to discard its writes
+    pub fn abort<R>(&self) -> crate::errors::Result<R> {
+        Err(crate::err!("transaction aborted"))
+    }
+}
+
+pub struct HashIterMut<'a, K, V> {
+    _g: WriteGuard<'a>,
+    guard: Guard,
+    inner: MapIter<'a, K, Arc<Entry<V>>>,
+}
+
+impl<'a, K, V: Clone> Iterator for HashIterMut<'a, K, V> {
+    type Item = (HashMapRef<'a, K>, HashMapRefMut<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, e) = self.inner.next()?;
+            if let Some(v) = e.load(&self.guard) {
+                let v = HashMapRefMut::new(self._g.fork(), e.clone(), v);
+                return Some((unsafe { HashMapRef::new(epoch::pin(), k) }, v));
+            }
+        }
+    }
+}
+
+/// yields owned keys, so it holds nothing but the lock across `.await`
+pub struct HashAsyncIterMut<'a, K, V> {
+    _g: WriteGuard<'a, Async>,
+    inner: MapIter<'a, K, Arc<Entry<V>>>,
+}
+
+impl<'a, K: Clone, V: Clone> Iterator for HashAsyncIterMut<'a, K, V> {
+    type Item = (K, HashMapRefMut<'a, V, Async>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let guard = epoch::pin();
+        loop {
+            let (k, e) = self.inner.next()?;
+            if let Some(v) = e.load(&guard) {
+                return Some((k.clone(), HashMapRefMut::new(self._g.fork(), e.clone(), v)));
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a SyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
+    type IntoIter = HashRefIter<'a, K, V>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl<K, V> IntoIterator for SyncHashMap<K, V>
+impl<K, V, S> IntoIterator for SyncHashMap<K, V, S>
     where
-        K: Eq + Hash,
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
 {
     type Item = (K, V);
     type IntoIter = MapIntoIter<K, V>;
@@ -307,29 +2161,39 @@ impl<K, V> IntoIterator for SyncHashMap<K, V>
     }
 }
 
-impl<K: Eq + Hash, V> From<Map<K, V>> for SyncHashMap<K, V> {
-    fn from(arg: Map<K, V>) -> Self {
+impl<K, V, S> From<Map<K, V, S>> for SyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    fn from(arg: Map<K, V, S>) -> Self {
         Self::from(arg)
     }
 }
 
-impl<K, V> serde::Serialize for SyncHashMap<K, V>
+impl<K, V, H> serde::Serialize for SyncHashMap<K, V, H>
     where
-        K: Eq + Hash + Serialize,
-        V: Serialize,
+        K: Eq + Hash + Clone + Send + 'static + Serialize,
+        V: Send + 'static + Serialize,
+        H: BuildHasher + Clone,
 {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
         where
             S: Serializer,
     {
-        self.dirty_ref().serialize(serializer)
+        // deleted entries are skipped while iterating, collect them first to know the length
+        let guard = self.pin();
+        let entries: Vec<(&K, &V)> = guard.iter().collect();
+        serializer.collect_map(entries)
     }
 }
 
-impl<'de, K, V> serde::Deserialize<'de> for SyncHashMap<K, V>
+impl<'de, K, V, S> serde::Deserialize<'de> for SyncHashMap<K, V, S>
     where
-        K: Eq + Hash + serde::Deserialize<'de>,
-        V: serde::Deserialize<'de>,
+        K: Eq + Hash + Clone + Send + 'static + serde::Deserialize<'de>,
+        V: Send + 'static + serde::Deserialize<'de>,
+        S: BuildHasher + Clone + Default,
 {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
         where
@@ -340,31 +2204,44 @@ impl<'de, K, V> serde::Deserialize<'de> for SyncHashMap<K, V>
     }
 }
 
-impl<K, V> Debug for SyncHashMap<K, V>
+impl<K, V, S> Debug for SyncHashMap<K, V, S>
     where
-        K: Eq + Hash + Debug,
-        V: Debug,
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+        S: BuildHasher + Clone,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.dirty_ref().fmt(f)
+        f.debug_map().entries(self.pin().iter()).finish()
     }
 }
 
-impl<K, V> Display for SyncHashMap<K, V>
+impl<K, V, S> Display for SyncHashMap<K, V, S>
     where
-        K: Eq + Hash + Display,
-        V: Display,
+        K: Eq + Hash + Clone + Send + 'static + Display,
+        V: Send + 'static + Display,
+        S: BuildHasher + Clone,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        use std::fmt::Pointer;
-        self.dirty_ref().fmt(f)
+        f.write_str("{")?;
+        for (i, (k, v)) in self.pin().iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", k, v)?;
+        }
+        f.write_str("}")
     }
 }
 
-
-impl<K: Clone + Eq + Hash, V: Clone> Clone for SyncHashMap<K, V> {
+impl<K, V, S> Clone for SyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
     fn clone(&self) -> Self {
-        let c = (*self.dirty_ref()).clone();
+        let mut c = Map::with_capacity_and_hasher(self.len(), self.hasher.clone());
+        c.extend(self.pin().iter().map(|(k, v)| (k.clone(), v.clone())));
         SyncHashMap::from(c)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,360 @@
+use crate::sync::{Async, HashMapRef, HashMapRefMut, HashRefIter, HashIterMut, SyncHashMap};
+use serde::{Deserializer, Serialize, Serializer};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap as Map;
+use std::fmt::{Debug, Formatter};
+use std::hash::{BuildHasher, Hash};
+use std::sync::Arc;
+
+/// a SyncHashMap split into shards, each shard has its own writer lock.
+/// keys are spread by `S`, so writers of different shards never wait for each other.
+/// the shards hash with a clone of `S` as well.
+pub struct ShardedSyncHashMap<K: Eq + Hash, V, S = RandomState> {
+    shards: Box<[SyncHashMap<K, V, S>]>,
+    hasher: S,
+}
+
+impl<K, V> ShardedSyncHashMap<K, V, RandomState>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    pub fn new_arc() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    /// shards count is 4 times of the available parallelism
+    pub fn new() -> Self {
+        Self::with_shards(default_shards())
+    }
+
+    pub fn with_shards(shards: usize) -> Self {
+        Self::with_shards_and_hasher(shards, RandomState::new())
+    }
+
+    pub fn with_map(map: Map<K, V>) -> Self {
+        let s = Self::new();
+        for (k, v) in map {
+            s.insert(k, v);
+        }
+        s
+    }
+
+    pub fn from(map: Map<K, V>) -> Self {
+        Self::with_map(map)
+    }
+}
+
+impl<K, V, S> ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    pub fn with_hasher(hasher: S) -> Self {
+        Self::with_shards_and_hasher(default_shards(), hasher)
+    }
+
+    /// `shards` is at least 1
+    pub fn with_shards_and_hasher(shards: usize, hasher: S) -> Self {
+        let shards = (0..shards.max(1))
+            .map(|_| SyncHashMap::with_hasher(hasher.clone()))
+            .collect();
+        Self { shards, hasher }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    #[inline]
+    fn shard<Q>(&self, k: &Q) -> &SyncHashMap<K, V, S>
+        where
+            Q: Hash + ?Sized,
+    {
+        let idx = self.hasher.hash_one(k) as usize % self.shards.len();
+        &self.shards[idx]
+    }
+
+    #[inline]
+    fn shard_mut<Q>(&mut self, k: &Q) -> &mut SyncHashMap<K, V, S>
+        where
+            Q: Hash + ?Sized,
+    {
+        let idx = self.hasher.hash_one(k) as usize % self.shards.len();
+        &mut self.shards[idx]
+    }
+
+    pub fn insert(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        self.shard(&k).insert(k, v)
+    }
+
+    pub fn insert_mut(&mut self, k: K, v: V) -> Option<V> {
+        self.shard_mut(&k).insert_mut(k, v)
+    }
+
+    pub fn remove(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        self.shard(k).remove(k)
+    }
+
+    pub fn remove_mut(&mut self, k: &K) -> Option<V> {
+        self.shard_mut(k).remove_mut(k)
+    }
+
+    pub async fn insert_async(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        self.shard(&k).insert_async(k, v).await
+    }
+
+    pub async fn remove_async(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        self.shard(k).remove_async(k).await
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.iter().all(|s| s.is_empty())
+    }
+
+    pub fn clear(&self) {
+        for s in self.shards.iter() {
+            s.clear();
+        }
+    }
+
+    pub fn shrink_to_fit(&self) {
+        for s in self.shards.iter() {
+            s.shrink_to_fit();
+        }
+    }
+
+    #[inline]
+    pub fn get<Q>(&self, k: &Q) -> Option<HashMapRef<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+    {
+        self.shard(k).get(k)
+    }
+
+    /// only the shard of `k` is locked while the returned guard lives
+    #[inline]
+    pub fn get_mut<Q>(&self, k: &Q) -> Option<HashMapRefMut<'_, V>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            V: Clone,
+    {
+        self.shard(k).get_mut(k)
+    }
+
+    pub async fn get_mut_async<Q>(&self, k: &Q) -> Option<HashMapRefMut<'_, V, Async>>
+        where
+            K: Borrow<Q>,
+            Q: Hash + Eq + ?Sized,
+            V: Clone,
+    {
+        self.shard(k).get_mut_async(k).await
+    }
+
+    #[inline]
+    pub fn contains_key(&self, x: &K) -> bool {
+        self.shard(x).contains_key(x)
+    }
+
+    pub fn iter(&self) -> ShardedIter<'_, K, V, S> {
+        ShardedIter {
+            shards: self.shards.iter(),
+            inner: None,
+        }
+    }
+
+    /// shards are locked one after another, never all at once
+    pub fn iter_mut(&self) -> ShardedIterMut<'_, K, V, S>
+        where
+            V: Clone,
+    {
+        ShardedIterMut {
+            shards: self.shards.iter(),
+            inner: None,
+        }
+    }
+
+    pub fn into_inner(self) -> Map<K, V, S> {
+        let mut m = Map::with_capacity_and_hasher(self.len(), self.hasher.clone());
+        for s in self.shards.into_vec() {
+            m.extend(s.into_inner());
+        }
+        m
+    }
+}
+
+impl<K, V, S> Default for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone + Default,
+{
+    fn default() -> Self {
+        Self::with_hasher(S::default())
+    }
+}
+
+fn default_shards() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        * 4
+}
+
+pub struct ShardedIter<'a, K: Eq + Hash, V, S = RandomState> {
+    shards: std::slice::Iter<'a, SyncHashMap<K, V, S>>,
+    inner: Option<HashRefIter<'a, K, V>>,
+}
+
+impl<'a, K, V, S> Iterator for ShardedIter<'a, K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(v) = self.inner.as_mut().and_then(|it| it.next()) {
+                return Some(v);
+            }
+            self.inner = Some(self.shards.next()?.iter());
+        }
+    }
+}
+
+pub struct ShardedIterMut<'a, K: Eq + Hash, V, S = RandomState> {
+    shards: std::slice::Iter<'a, SyncHashMap<K, V, S>>,
+    inner: Option<HashIterMut<'a, K, V>>,
+}
+
+impl<'a, K, V, S> Iterator for ShardedIterMut<'a, K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (HashMapRef<'a, K>, HashMapRefMut<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(v) = self.inner.as_mut().and_then(|it| it.next()) {
+                return Some(v);
+            }
+            // release the lock of the finished shard before taking the next one
+            self.inner = None;
+            self.inner = Some(self.shards.next()?.iter_mut());
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
+    type IntoIter = ShardedIter<'a, K, V, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<K, V, S> IntoIterator for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+        S: BuildHasher + Clone,
+{
+    type Item = (K, V);
+    type IntoIter = std::collections::hash_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+impl<K, V> From<Map<K, V>> for ShardedSyncHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    fn from(arg: Map<K, V>) -> Self {
+        Self::with_map(arg)
+    }
+}
+
+impl<K, V, S> serde::Serialize for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Serialize,
+        V: Send + 'static + Serialize,
+        S: BuildHasher + Clone,
+{
+    fn serialize<Se>(&self, serializer: Se) -> Result<Se::Ok, Se::Error>
+        where
+            Se: Serializer,
+    {
+        // like SyncHashMap, collect the entries first so formats needing the length work
+        let pins: Vec<_> = self.shards.iter().map(|s| s.pin()).collect();
+        let entries: Vec<(&K, &V)> = pins.iter().flat_map(|p| p.iter()).collect();
+        serializer.collect_map(entries)
+    }
+}
+
+impl<'de, K, V, S> serde::Deserialize<'de> for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static + serde::Deserialize<'de>,
+        V: Send + 'static + serde::Deserialize<'de>,
+        S: BuildHasher + Clone + Default,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let m = Map::<K, V>::deserialize(deserializer)?;
+        let s = Self::default();
+        for (k, v) in m {
+            s.insert(k, v);
+        }
+        Ok(s)
+    }
+}
+
+impl<K, V, S> Debug for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+        S: BuildHasher + Clone,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let pins: Vec<_> = self.shards.iter().map(|s| s.pin()).collect();
+        f.debug_map()
+            .entries(pins.iter().flat_map(|p| p.iter()))
+            .finish()
+    }
+}
+
+impl<K, V, S> Clone for ShardedSyncHashMap<K, V, S>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Clone + Send + 'static,
+        S: BuildHasher + Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            shards: self.shards.iter().cloned().collect(),
+            hasher: self.hasher.clone(),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::sync::{AtomicDuration, HashMapRef, HashRefIter, SyncHashMap};
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// a SyncHashMap whose entries expire after a time-to-live.
+///
+/// expired entries are never returned. they are removed lazily by `get`,
+/// or all at once by `purge` (with the `tokio` feature, `spawn_purge` runs it on an interval).
+/// both report the removed entries to the `on_evict` callback.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncTtlHashMap;
+/// use std::time::Duration;
+///
+/// let map = SyncTtlHashMap::new(Duration::from_secs(60));
+/// map.insert("session", 1);
+/// map.insert_with_ttl("token", 2, Duration::from_millis(1));
+/// std::thread::sleep(Duration::from_millis(5));
+/// assert_eq!(*map.get(&"session").unwrap(), 1);
+/// assert_eq!(map.get(&"token").is_none(), true);
+/// ```
+pub struct SyncTtlHashMap<K: Eq + Hash, V> {
+    map: SyncHashMap<K, TtlValue<V>>,
+    ttl: Duration,
+    start: Instant,
+    on_evict: Option<EvictFn<K, V>>,
+}
+
+type EvictFn<K, V> = Box<dyn Fn(&K, &V) + Send + Sync>;
+
+struct TtlValue<V> {
+    value: V,
+    /// since `start` of the map
+    deadline: AtomicDuration,
+}
+
+impl<K, V> SyncTtlHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    pub fn new_arc(ttl: Duration) -> Arc<Self> {
+        Arc::new(Self::new(ttl))
+    }
+
+    /// `ttl` is used by `insert`
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            map: SyncHashMap::new(),
+            ttl,
+            start: Instant::now(),
+            on_evict: None,
+        }
+    }
+
+    /// `f` is called with every entry removed because it expired, outside of the writer lock
+    pub fn on_evict<F>(mut self, f: F) -> Self
+        where
+            F: Fn(&K, &V) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Box::new(f));
+        self
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// the replaced value is returned only if it was not expired yet
+    pub fn insert(&self, k: K, v: V) -> Option<HashMapRef<'_, V>> {
+        self.insert_with_ttl(k, v, self.ttl)
+    }
+
+    /// an expired value replaced here is reported to `on_evict`
+    pub fn insert_with_ttl(&self, k: K, v: V, ttl: Duration) -> Option<HashMapRef<'_, V>> {
+        let v = TtlValue {
+            value: v,
+            deadline: AtomicDuration::new(Some(self.deadline(ttl))),
+        };
+        let key = self.on_evict.as_ref().map(|_| k.clone());
+        let old = self.map.insert(k, v)?;
+        if self.is_expired(&old) {
+            if let (Some(f), Some(k)) = (&self.on_evict, &key) {
+                f(k, &old.value);
+            }
+            return None;
+        }
+        Some(HashMapRef::map(old, |v| &v.value))
+    }
+
+    pub fn remove(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        self.live(self.map.remove(k)?)
+    }
+
+    /// an expired entry is removed here and reported to `on_evict`
+    pub fn get(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        let v = self.map.get(k)?;
+        if !self.is_expired(&v) {
+            return Some(HashMapRef::map(v, |v| &v.value));
+        }
+        drop(v);
+        self.expire(k);
+        None
+    }
+
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.get(k).is_some()
+    }
+
+    /// the time left before `k` expires
+    pub fn expires_in(&self, k: &K) -> Option<Duration> {
+        let v = self.map.get(k)?;
+        let deadline = v.deadline.get()?;
+        deadline.checked_sub(self.start.elapsed())
+    }
+
+    /// restart the ttl of a live entry, an entry expiring meanwhile stays expired
+    pub fn touch(&self, k: &K, ttl: Duration) -> bool {
+        match self.map.get(k) {
+            Some(v) if !self.is_expired(&v) => {
+                v.deadline.store(Some(self.deadline(ttl)));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// expired entries are counted until they are removed
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.map.clear()
+    }
+
+    /// remove every expired entry, returns how many were removed
+    pub fn purge(&self) -> usize {
+        let expired: Vec<K> = self
+            .map
+            .pin()
+            .iter()
+            .filter(|(_, v)| self.is_expired(v))
+            .map(|(k, _)| k.clone())
+            .collect();
+        expired.iter().filter(|k| self.expire(k)).count()
+    }
+
+    /// purge every `every` on the tokio runtime, the task ends once the map is dropped
+    #[cfg(feature = "tokio")]
+    pub fn spawn_purge(self: &Arc<Self>, every: Duration) -> tokio::task::JoinHandle<()>
+        where
+            K: Sync,
+            V: Sync,
+    {
+        let map = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(every);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                match map.upgrade() {
+                    None => break,
+                    Some(m) => {
+                        m.purge();
+                    }
+                }
+            }
+        })
+    }
+
+    /// live entries only
+    pub fn iter(&self) -> TtlIter<'_, K, V> {
+        TtlIter {
+            now: self.start.elapsed(),
+            inner: self.map.iter(),
+        }
+    }
+
+    fn deadline(&self, ttl: Duration) -> Duration {
+        // a zero deadline would mean `never`
+        (self.start.elapsed() + ttl).max(Duration::from_millis(1))
+    }
+
+    fn is_expired(&self, v: &TtlValue<V>) -> bool {
+        v.is_expired(self.start.elapsed())
+    }
+
+    fn live<'a>(&self, v: HashMapRef<'a, TtlValue<V>>) -> Option<HashMapRef<'a, V>> {
+        if self.is_expired(&v) {
+            return None;
+        }
+        Some(HashMapRef::map(v, |v| &v.value))
+    }
+
+    /// remove `k` if it is still expired under the lock, a concurrent insert wins
+    fn expire(&self, k: &K) -> bool {
+        match self.map.remove_if(k, |v| self.is_expired(v)) {
+            None => false,
+            Some(v) => {
+                if let Some(f) = &self.on_evict {
+                    f(k, &v.value);
+                }
+                true
+            }
+        }
+    }
+}
+
+impl<V> TtlValue<V> {
+    fn is_expired(&self, now: Duration) -> bool {
+        match self.deadline.get() {
+            None => false,
+            Some(d) => d <= now,
+        }
+    }
+}
+
+pub struct TtlIter<'a, K, V> {
+    now: Duration,
+    inner: HashRefIter<'a, K, TtlValue<V>>,
+}
+
+impl<'a, K, V> Iterator for TtlIter<'a, K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    type Item = (HashMapRef<'a, K>, HashMapRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (k, v) = self.inner.next()?;
+            if !v.is_expired(self.now) {
+                return Some((k, HashMapRef::map(v, |v| &v.value)));
+            }
+        }
+    }
+}
+
+impl<K, V> Debug for SyncTtlHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,205 @@
+use atomic_shim::AtomicU64;
+use crate::sync::{HashMapRef, HashRefIter, Snapshot, SyncHashMap};
+use serde::{Serialize, Serializer};
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::hash::Hash;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// a SyncHashMap whose entries carry the revision of their last write.
+///
+/// the map counts every insert, remove and clear, an entry gets the count of the write
+/// that stored it, so revisions only grow, across keys and for each key.
+/// revision `0` stands for an absent key.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncVersionedHashMap;
+///
+/// let map = SyncVersionedHashMap::new();
+/// let rev = map.insert("config", 1);
+/// let (seen, v) = map.get_versioned(&"config").unwrap();
+/// assert_eq!((seen, *v), (rev, 1));
+/// assert_eq!(map.insert_if_rev("config", 2, seen).is_ok(), true);
+/// // someone wrote in between
+/// assert_eq!(map.insert_if_rev("config", 3, seen).unwrap_err(), (seen + 1, 3));
+/// ```
+pub struct SyncVersionedHashMap<K: Eq + Hash, V> {
+    map: SyncHashMap<K, Versioned<V>>,
+    revision: AtomicU64,
+}
+
+/// a value and the revision of the write that stored it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Versioned<V> {
+    pub rev: u64,
+    pub value: V,
+}
+
+impl<K, V> SyncVersionedHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    pub fn new_arc() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    pub fn new() -> Self {
+        Self {
+            map: SyncHashMap::new(),
+            revision: AtomicU64::new(0),
+        }
+    }
+
+    /// the count of writes so far, the revision of the last one
+    pub fn revision(&self) -> u64 {
+        self.revision.load(Ordering::Acquire)
+    }
+
+    /// returns the revision of the new value
+    pub fn insert(&self, k: K, v: V) -> u64 {
+        let g = self.map.write_lock();
+        let rev = self.next_revision();
+        self.map.insert_guarded(&g, k, Versioned { rev, value: v });
+        drop(g);
+        rev
+    }
+
+    /// Insert only if the key is still at `expected_rev`, `0` expecting it to be absent.
+    ///
+    /// returns the revision of the new value, or the current revision and `v` back on a conflict.
+    pub fn insert_if_rev(&self, k: K, v: V, expected_rev: u64) -> Result<u64, (u64, V)> {
+        let g = self.map.write_lock();
+        let current = self.map.get(&k).map(|v| v.rev).unwrap_or_default();
+        if current != expected_rev {
+            return Err((current, v));
+        }
+        let rev = self.next_revision();
+        self.map.insert_guarded(&g, k, Versioned { rev, value: v });
+        drop(g);
+        Ok(rev)
+    }
+
+    pub fn remove(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        let g = self.map.write_lock();
+        let old = self.map.remove_guarded(&g, k)?;
+        self.next_revision();
+        drop(g);
+        Some(HashMapRef::map(old, |v| &v.value))
+    }
+
+    pub fn get(&self, k: &K) -> Option<HashMapRef<'_, V>> {
+        Some(HashMapRef::map(self.map.get(k)?, |v| &v.value))
+    }
+
+    /// the value and the revision it was stored at
+    pub fn get_versioned(&self, k: &K) -> Option<(u64, HashMapRef<'_, V>)> {
+        let v = self.map.get(k)?;
+        Some((v.rev, HashMapRef::map(v, |v| &v.value)))
+    }
+
+    #[inline]
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.map.contains_key(k)
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&self) {
+        let g = self.map.write_lock();
+        self.map.clear_guarded(&g);
+        self.next_revision();
+        drop(g);
+    }
+
+    /// the entries with their revisions, written while iterating or not,
+    /// those above [`VersionedIter::revision`] were written after the iteration began.
+    pub fn iter(&self) -> VersionedIter<'_, K, V> {
+        VersionedIter {
+            revision: self.revision(),
+            inner: self.map.iter(),
+        }
+    }
+
+    /// a consistent copy of the map and the revision it was taken at
+    pub fn snapshot(&self) -> (u64, Snapshot<HashMap<K, Versioned<V>>>)
+        where
+            V: Clone,
+    {
+        let g = self.map.write_lock();
+        let r = (self.revision(), self.map.snapshot_guarded(&g));
+        drop(g);
+        r
+    }
+
+    /// only called under the writer lock, so revisions follow the order of writes
+    fn next_revision(&self) -> u64 {
+        self.revision.fetch_add(1, Ordering::AcqRel) + 1
+    }
+}
+
+impl<K, V> Default for SyncVersionedHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct VersionedIter<'a, K, V> {
+    revision: u64,
+    inner: HashRefIter<'a, K, Versioned<V>>,
+}
+
+impl<K, V> VersionedIter<'_, K, V> {
+    /// the revision of the map when the iteration began
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+}
+
+impl<'a, K, V> Iterator for VersionedIter<'a, K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static,
+        V: Send + 'static,
+{
+    type Item = (HashMapRef<'a, K>, u64, HashMapRef<'a, V>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        Some((k, v.rev, HashMapRef::map(v, |v| &v.value)))
+    }
+}
+
+/// a `(rev, value)` pair
+impl<V: Serialize> Serialize for Versioned<V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        (self.rev, &self.value).serialize(serializer)
+    }
+}
+
+impl<K, V> Debug for SyncVersionedHashMap<K, V>
+    where
+        K: Eq + Hash + Clone + Send + 'static + Debug,
+        V: Send + 'static + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_map()
+            .entries(self.iter().map(|(k, rev, v)| (k, (rev, v))))
+            .finish()
+    }
+}
@@ -1,12 +1,37 @@
+pub mod cache;
+pub mod event;
+pub mod lock;
 pub mod map_btree;
+pub mod map_durable;
 pub mod map_hash;
+pub mod map_sharded;
+pub mod map_ttl;
+pub mod map_versioned;
+mod persist;
+pub mod queue;
+pub mod ring_buffer;
+pub mod snapshot;
 pub mod vec;
+pub mod vec_append;
+pub mod vec_deque;
 pub mod wg;
 
 pub mod duration;
 
+pub use cache::*;
+pub use event::*;
+pub use lock::*;
 pub use map_btree::*;
+pub use map_durable::*;
 pub use map_hash::*;
+pub use map_sharded::*;
+pub use map_ttl::*;
+pub use map_versioned::*;
+pub use queue::*;
+pub use ring_buffer::*;
+pub use snapshot::*;
 pub use vec::*;
+pub use vec_append::*;
+pub use vec_deque::*;
 pub use wg::*;
 pub use duration::*;
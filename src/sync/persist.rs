@@ -0,0 +1,208 @@
+use crate::errors::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// the file written by `save_to` of the containers:
+///
+/// | bytes | field                                   |
+/// |-------|-----------------------------------------|
+/// | 4     | magic `DSTD`                            |
+/// | 2     | format version, little endian           |
+/// | 1     | container kind                          |
+/// | 1     | reserved, zero                          |
+/// | 8     | payload length, little endian           |
+/// | 4     | CRC-32 of the payload, little endian    |
+/// | ..    | payload, the container encoded by serde |
+const MAGIC: &[u8; 4] = b"DSTD";
+const VERSION: u16 = 1;
+const HEADER_LEN: usize = 20;
+
+/// the container a file was saved from, a map loaded as a vec would decode as garbage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Kind {
+    HashMap = 1,
+    BtreeMap = 2,
+    Vec = 3,
+}
+
+impl Kind {
+    fn from_u8(b: u8) -> Option<Self> {
+        match b {
+            1 => Some(Kind::HashMap),
+            2 => Some(Kind::BtreeMap),
+            3 => Some(Kind::Vec),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Kind::HashMap => "SyncHashMap",
+            Kind::BtreeMap => "SyncBtreeMap",
+            Kind::Vec => "SyncVec",
+        }
+    }
+}
+
+pub(crate) fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    bincode::serialize(value).map_err(|e| crate::err!("encode: {}", e))
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    bincode::deserialize(bytes).map_err(|e| crate::err!("decode: {}", e))
+}
+
+/// write `payload` to `path` through a temporary file renamed over it,
+/// so `path` holds either the old or the new content even if the process dies meanwhile.
+pub(crate) fn save(path: &Path, kind: Kind, payload: &[u8]) -> Result<()> {
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    header.push(kind as u8);
+    header.push(0);
+    header.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    header.extend_from_slice(&crc32(payload).to_le_bytes());
+
+    let tmp = temp_path(path);
+    let written = (|| -> Result<()> {
+        let mut f = File::create(&tmp)?;
+        f.write_all(&header)?;
+        f.write_all(payload)?;
+        f.sync_all()?;
+        Ok(())
+    })();
+    if let Err(e) = written.and_then(|_| Ok(fs::rename(&tmp, path)?)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    sync_dir(path)
+}
+
+/// the payload of a file written by `save`, checked against its header
+pub(crate) fn load(path: &Path, kind: Kind) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    File::open(path)?.read_to_end(&mut bytes)?;
+    let display = path.display();
+    if bytes.len() < HEADER_LEN {
+        if !MAGIC.starts_with(&bytes[..bytes.len().min(MAGIC.len())]) {
+            return Err(crate::err!("{}: not a dark-std snapshot", display));
+        }
+        return Err(crate::err!(
+            "{}: truncated, the header needs {} bytes, found {}",
+            display,
+            HEADER_LEN,
+            bytes.len()
+        ));
+    }
+    let (header, payload) = bytes.split_at(HEADER_LEN);
+    if &header[0..4] != MAGIC {
+        return Err(crate::err!("{}: not a dark-std snapshot", display));
+    }
+    let version = u16::from_le_bytes([header[4], header[5]]);
+    if version != VERSION {
+        return Err(crate::err!(
+            "{}: unsupported snapshot version {}, expected {}",
+            display,
+            version,
+            VERSION
+        ));
+    }
+    match Kind::from_u8(header[6]) {
+        Some(k) if k == kind => {}
+        Some(k) => {
+            return Err(crate::err!(
+                "{}: saved from a {}, not a {}",
+                display,
+                k.name(),
+                kind.name()
+            ));
+        }
+        None => {
+            return Err(crate::err!("{}: unknown container kind {}", display, header[6]));
+        }
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[8..16]);
+    let len = u64::from_le_bytes(len);
+    if (payload.len() as u64) < len {
+        return Err(crate::err!(
+            "{}: truncated, expected {} bytes of payload, found {}",
+            display,
+            len,
+            payload.len()
+        ));
+    }
+    if (payload.len() as u64) > len {
+        return Err(crate::err!(
+            "{}: {} unexpected bytes after the payload",
+            display,
+            payload.len() as u64 - len
+        ));
+    }
+    let crc = u32::from_le_bytes([header[16], header[17], header[18], header[19]]);
+    if crc32(payload) != crc {
+        return Err(crate::err!("{}: checksum mismatch, the file is corrupted", display));
+    }
+    Ok(payload.to_vec())
+}
+
+/// next to `path`, so the rename stays on the same file system
+fn temp_path(path: &Path) -> PathBuf {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        name,
+        std::process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// make the rename itself durable
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// CRC-32 (IEEE 802.3)
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    static TABLE: [u32; 256] = crc32_table();
+    let mut crc = !0u32;
+    for b in bytes {
+        crc = TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb8_8320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
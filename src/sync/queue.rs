@@ -0,0 +1,127 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// a FIFO queue holding at most `capacity` values, both sync and async.
+///
+/// `pop` waits until a value is available and `push` waits while the queue is full,
+/// threads block on [`SyncBoundedQueue::pop`] / [`SyncBoundedQueue::push`],
+/// tasks await [`SyncBoundedQueue::pop_async`] / [`SyncBoundedQueue::push_async`].
+/// waiters are served in arrival order.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncBoundedQueue;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let q = SyncBoundedQueue::new_arc(2);
+///     let producer = {
+///         let q = q.clone();
+///         tokio::spawn(async move {
+///             for i in 0..10 {
+///                 // waits while two values are pending
+///                 q.push_async(i).await;
+///             }
+///         })
+///     };
+///     let mut sum = 0;
+///     for _ in 0..10 {
+///         sum += q.pop_async().await;
+///     }
+///     producer.await.unwrap();
+///     assert_eq!(sum, 45);
+/// }
+/// ```
+pub struct SyncBoundedQueue<V> {
+    send: flume::Sender<V>,
+    recv: flume::Receiver<V>,
+    capacity: usize,
+}
+
+impl<V> SyncBoundedQueue<V> {
+    pub fn new_arc(capacity: usize) -> Arc<Self> {
+        Arc::new(Self::new(capacity))
+    }
+
+    /// a zero `capacity` makes every `push` wait for a `pop` to take the value
+    pub fn new(capacity: usize) -> Self {
+        let (send, recv) = flume::bounded(capacity);
+        Self {
+            send,
+            recv,
+            capacity,
+        }
+    }
+
+    /// block the thread while the queue is full
+    pub fn push(&self, v: V) {
+        // never disconnected, both ends live in self
+        let _ = self.send.send(v);
+    }
+
+    /// await while the queue is full
+    pub async fn push_async(&self, v: V) {
+        let _ = self.send.send_async(v).await;
+    }
+
+    /// push unless the queue is full, in which case `v` is given back
+    pub fn try_push(&self, v: V) -> Result<(), V> {
+        self.send.try_send(v).map_err(|e| e.into_inner())
+    }
+
+    /// block the thread for at most `timeout` while the queue is full, `v` is given back if it stays full
+    pub fn push_timeout(&self, v: V, timeout: Duration) -> Result<(), V> {
+        self.send.send_timeout(v, timeout).map_err(|e| e.into_inner())
+    }
+
+    /// block the thread until a value is available
+    pub fn pop(&self) -> V {
+        self.recv.recv().expect("never disconnected")
+    }
+
+    /// await until a value is available
+    pub async fn pop_async(&self) -> V {
+        self.recv.recv_async().await.expect("never disconnected")
+    }
+
+    pub fn try_pop(&self) -> Option<V> {
+        self.recv.try_recv().ok()
+    }
+
+    /// block the thread for at most `timeout` until a value is available
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<V> {
+        self.recv.recv_timeout(timeout).ok()
+    }
+
+    /// remove the values available now, oldest first
+    pub fn drain(&self) -> Vec<V> {
+        self.recv.drain().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.recv.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.recv.is_empty()
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.send.is_full()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+impl<V> Debug for SyncBoundedQueue<V> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncBoundedQueue")
+            .field("len", &self.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
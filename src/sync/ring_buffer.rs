@@ -0,0 +1,326 @@
+use atomic_shim::AtomicU64;
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned};
+use serde::{Deserializer, Serialize, Serializer};
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// a fixed-capacity buffer of the latest values, a push overwrites the oldest one once it is full.
+///
+/// both pushes and reads are lock-free: a push takes the next sequence number and swaps its
+/// slot, so a single producer never retries, and values overwritten while a reader holds them
+/// are released by epoch based reclamation after it leaves.
+/// readers see the values oldest to newest, a prefix of the pushes that already completed.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncRingBuffer;
+///
+/// let ring = SyncRingBuffer::new(3);
+/// for i in 0..5 {
+///     ring.push(i);
+/// }
+/// assert_eq!(ring.len(), 3);
+/// assert_eq!(ring.latest(2), vec![3, 4]);
+/// assert_eq!(ring.pin().iter().collect::<Vec<_>>(), vec![&2, &3, &4]);
+/// ```
+pub struct SyncRingBuffer<V> {
+    slots: Box<[Atomic<Slot<V>>]>,
+    /// the sequence number of the next push, the count of pushes so far
+    next: AtomicU64,
+}
+
+struct Slot<V> {
+    seq: u64,
+    value: V,
+}
+
+impl<V> SyncRingBuffer<V>
+    where
+        V: Send + 'static,
+{
+    pub fn new_arc(capacity: usize) -> Arc<Self> {
+        Arc::new(Self::new(capacity))
+    }
+
+    /// a zero `capacity` holds one value
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            slots: (0..capacity.max(1)).map(|_| Atomic::null()).collect(),
+            next: AtomicU64::new(0),
+        }
+    }
+
+    /// push `v`, overwriting the oldest value once the buffer is full
+    pub fn push(&self, v: V) {
+        let seq = self.next.fetch_add(1, Ordering::AcqRel);
+        let slot = &self.slots[(seq % self.slots.len() as u64) as usize];
+        let guard = epoch::pin();
+        let mut new = Owned::new(Slot { seq, value: v });
+        let mut current = slot.load(Ordering::Acquire, &guard);
+        loop {
+            // producers a whole lap apart race for the slot, the newer value wins
+            if let Some(c) = unsafe { current.as_ref() } {
+                if c.seq > seq {
+                    return;
+                }
+            }
+            match slot.compare_exchange(current, new, Ordering::AcqRel, Ordering::Acquire, &guard)
+            {
+                Ok(_) => {
+                    if !current.is_null() {
+                        unsafe {
+                            guard.defer_destroy(current);
+                        }
+                    }
+                    return;
+                }
+                Err(e) => {
+                    current = e.current;
+                    new = e.new;
+                }
+            }
+        }
+    }
+
+    /// push every value in order, only the last `capacity` ones are kept
+    pub fn extend<I>(&self, values: I)
+        where
+            I: IntoIterator<Item = V>,
+    {
+        for v in values {
+            self.push(v);
+        }
+    }
+
+    /// a copy of the latest `n` values, oldest first
+    pub fn latest(&self, n: usize) -> Vec<V>
+        where
+            V: Clone,
+    {
+        let guard = self.pin();
+        let values: Vec<&V> = guard.iter().collect();
+        let skip = values.len().saturating_sub(n);
+        values.into_iter().skip(skip).cloned().collect()
+    }
+
+    /// a copy of every value, oldest first
+    pub fn to_vec(&self) -> Vec<V>
+        where
+            V: Clone,
+    {
+        self.pin().iter().cloned().collect()
+    }
+
+    /// a pinned epoch, values read through it stay valid until it drops even if overwritten meanwhile
+    pub fn pin(&self) -> RingBufferGuard<'_, V> {
+        RingBufferGuard {
+            ring: self,
+            guard: epoch::pin(),
+        }
+    }
+
+    /// the values held, `capacity` once as many were pushed
+    pub fn len(&self) -> usize {
+        self.pushed().min(self.slots.len() as u64) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pushed() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// the count of pushes so far, overwritten values included
+    pub fn pushed(&self) -> u64 {
+        self.next.load(Ordering::Acquire)
+    }
+
+    /// the values, oldest first
+    pub fn into_inner(mut self) -> Vec<V> {
+        let end = *self.next.get_mut();
+        let cap = self.slots.len() as u64;
+        let mut values = Vec::with_capacity(self.len());
+        for seq in end.saturating_sub(cap)..end {
+            let slot = &self.slots[(seq % cap) as usize];
+            unsafe {
+                let guard = epoch::unprotected();
+                let p = slot.swap(epoch::Shared::null(), Ordering::Relaxed, guard);
+                if !p.is_null() {
+                    values.push(p.into_owned().into_box().value);
+                }
+            }
+        }
+        values
+    }
+}
+
+impl<V> Drop for SyncRingBuffer<V> {
+    fn drop(&mut self) {
+        for slot in self.slots.iter() {
+            unsafe {
+                let p = slot.load(Ordering::Relaxed, epoch::unprotected());
+                if !p.is_null() {
+                    drop(p.into_owned());
+                }
+            }
+        }
+    }
+}
+
+/// a pinned epoch of one ring buffer, see [`SyncRingBuffer::pin`].
+/// keep it short-lived, overwritten values are not released while it is held.
+pub struct RingBufferGuard<'a, V> {
+    ring: &'a SyncRingBuffer<V>,
+    guard: Guard,
+}
+
+impl<V> RingBufferGuard<'_, V> {
+    /// the values, oldest to newest
+    pub fn iter(&self) -> RingBufferIter<'_, V> {
+        let end = self.ring.next.load(Ordering::Acquire);
+        RingBufferIter {
+            slots: &self.ring.slots,
+            guard: &self.guard,
+            seq: end.saturating_sub(self.ring.slots.len() as u64),
+            end,
+        }
+    }
+
+    /// the latest value
+    pub fn last(&self) -> Option<&V> {
+        self.iter().last()
+    }
+}
+
+impl<'g, V> IntoIterator for &'g RingBufferGuard<'_, V> {
+    type Item = &'g V;
+    type IntoIter = RingBufferIter<'g, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct RingBufferIter<'g, V> {
+    slots: &'g [Atomic<Slot<V>>],
+    guard: &'g Guard,
+    seq: u64,
+    end: u64,
+}
+
+impl<'g, V> Iterator for RingBufferIter<'g, V> {
+    type Item = &'g V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.seq < self.end {
+            let seq = self.seq;
+            let slot = &self.slots[(seq % self.slots.len() as u64) as usize];
+            let s = unsafe { slot.load(Ordering::Acquire, self.guard).as_ref() };
+            match s {
+                Some(s) if s.seq == seq => {
+                    self.seq += 1;
+                    return Some(&s.value);
+                }
+                // overwritten since the iteration began, newer values follow
+                Some(s) if s.seq > seq => {
+                    self.seq += 1;
+                }
+                // still being pushed, stop at the completed prefix
+                _ => {
+                    self.seq = self.end;
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<V> IntoIterator for SyncRingBuffer<V>
+    where
+        V: Send + 'static,
+{
+    type Item = V;
+    type IntoIter = std::vec::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+/// the capacity is the count of values
+impl<V> From<Vec<V>> for SyncRingBuffer<V>
+    where
+        V: Send + 'static,
+{
+    fn from(arg: Vec<V>) -> Self {
+        let s = Self::new(arg.len());
+        s.extend(arg);
+        s
+    }
+}
+
+/// a sequence of the values oldest first, like `SyncVec`
+impl<V> Serialize for SyncRingBuffer<V>
+    where
+        V: Send + 'static + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        let guard = self.pin();
+        let values: Vec<&V> = guard.iter().collect();
+        serializer.collect_seq(values)
+    }
+}
+
+/// the capacity is the count of values
+impl<'de, V> serde::Deserialize<'de> for SyncRingBuffer<V>
+    where
+        V: Send + 'static + serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let m = Vec::deserialize(deserializer)?;
+        Ok(Self::from(m))
+    }
+}
+
+impl<V> Debug for SyncRingBuffer<V>
+    where
+        V: Send + 'static + Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.pin().iter()).finish()
+    }
+}
+
+/// create a [`SyncRingBuffer`](crate::sync::SyncRingBuffer):
+///
+/// * `sync_ring![capacity =>]` an empty one
+/// * `sync_ring![capacity => a, b, c]` holding the latest `capacity` of the values
+/// * `sync_ring![a, b, c]` holding the values, as many as its capacity
+/// * `sync_ring![elem; n]` holding `n` clones of `elem`
+#[macro_export]
+macro_rules! sync_ring {
+    ($cap:expr =>) => (
+        $crate::sync::SyncRingBuffer::new($cap)
+    );
+    ($cap:expr => $($x:expr),+ $(,)?) => ({
+        let ring = $crate::sync::SyncRingBuffer::new($cap);
+        $(ring.push($x);)+
+        ring
+    });
+    ($elem:expr; $n:expr) => (
+        $crate::sync::SyncRingBuffer::from(vec![$elem;$n])
+    );
+    ($($x:expr),+ $(,)?) => (
+        $crate::sync::SyncRingBuffer::from(vec![$($x),+,])
+    );
+}
@@ -0,0 +1,120 @@
+use crate::sync::WriteLock;
+use parking_lot::Mutex;
+use serde::{Serialize, Serializer};
+use std::fmt::{Debug, Formatter};
+use std::ops::Deref;
+use std::sync::{Arc, Weak};
+
+/// an immutable point-in-time copy of a container, see `SyncHashMap::snapshot`,
+/// `SyncBtreeMap::snapshot` and `SyncVec::snapshot`.
+///
+/// it is not a persistent structure, taking one is not cheap: every entry is cloned, O(n),
+/// under the writer lock of the container, so writers wait for the copy.
+/// it is shared through an `Arc`, cloning or sending it to another task copies nothing.
+pub struct Snapshot<T>(Arc<T>);
+
+impl<T> Snapshot<T> {
+    pub(crate) fn new(copy: T) -> Self {
+        Self(Arc::new(copy))
+    }
+
+    /// the copy itself, cloned only if the snapshot is still shared
+    pub fn into_inner(self) -> T
+        where
+            T: Clone,
+    {
+        Arc::try_unwrap(self.0).unwrap_or_else(|shared| (*shared).clone())
+    }
+
+    /// both are the same copy
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Clone for Snapshot<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T> Deref for Snapshot<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Snapshot<T>
+    where
+        &'a T: IntoIterator,
+{
+    type Item = <&'a T as IntoIterator>::Item;
+    type IntoIter = <&'a T as IntoIterator>::IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.as_ref().into_iter()
+    }
+}
+
+impl<T: Serialize> Serialize for Snapshot<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        self.0.serialize(serializer)
+    }
+}
+
+impl<T: Debug> Debug for Snapshot<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for Snapshot<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<T: Eq> Eq for Snapshot<T> {}
+
+/// the last snapshot of a container and the lock generation it was copied at.
+/// while nobody took the writer lock since, it is handed out again instead of a new copy.
+///
+/// only a weak reference is kept, the copy is freed with the last `Snapshot` the callers hold.
+pub(crate) struct SnapshotCache<T> {
+    last: Mutex<Option<(u64, Weak<T>)>>,
+}
+
+impl<T> SnapshotCache<T> {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new(None),
+        }
+    }
+
+    /// `copy` runs under the writer lock
+    pub fn get_or_copy<F>(&self, lock: &WriteLock, copy: F) -> Snapshot<T>
+        where
+            F: FnOnce() -> T,
+    {
+        if let Some(generation) = lock.idle_generation() {
+            if let Some((at, snapshot)) = self.last.lock().as_ref() {
+                if *at == generation {
+                    if let Some(snapshot) = snapshot.upgrade() {
+                        return Snapshot(snapshot);
+                    }
+                }
+            }
+        }
+        // the cache is not held while waiting for the lock, a writer may take a snapshot too
+        let g = lock.lock();
+        let snapshot = Snapshot::new(copy());
+        *self.last.lock() = Some((g.generation(), Arc::downgrade(&snapshot.0)));
+        drop(g);
+        snapshot
+    }
+}
@@ -1,22 +1,43 @@
-use parking_lot::{ReentrantMutex, ReentrantMutexGuard};
+use crate::sync::persist::{self, Kind};
+use crate::sync::{Async, Blocking, Snapshot, SnapshotCache, WriteGuard, WriteLock};
+use parking_lot::{RwLock, RwLockReadGuard};
+use serde::de::DeserializeOwned;
 use serde::{Deserializer, Serialize, Serializer};
-use std::cell::UnsafeCell;
+use std::cell::{RefCell, UnsafeCell};
+use std::cmp::Ordering;
 use std::fmt::{Debug, Display, Formatter};
-use std::ops::{Deref, DerefMut, Index};
-use std::slice::{Iter as SliceIter, IterMut as SliceIterMut};
+use std::ops::{Deref, DerefMut, RangeBounds};
+use std::path::Path;
+use std::slice::Iter as SliceIter;
 use std::sync::Arc;
 use std::vec::IntoIter;
 
+/// a vec written under one lock and read by many threads.
+///
+/// * writers take `lock`, then `dirty_lock` exclusively only while they move or drop values.
+///   the closures given to writers run outside of `dirty_lock` wherever they only read the values.
+/// * readers share `dirty_lock` for as long as they borrow a value, through a [`VecRef`] or a [`VecGuard`].
+///   keep those short-lived, writers wait for them.
+///
+/// a write on the thread borrowing the vec would wait for itself forever, it panics instead.
 pub struct SyncVec<V> {
     dirty: UnsafeCell<Vec<V>>,
-    lock: ReentrantMutex<()>,
+    /// shared by readers while they borrow the values, exclusive for writers while they change them
+    dirty_lock: RwLock<()>,
+    lock: WriteLock,
+    snapshots: SnapshotCache<Vec<V>>,
 }
 
 /// this is safety, dirty mutex ensure
-unsafe impl<V> Send for SyncVec<V> {}
+unsafe impl<V: Send> Send for SyncVec<V> {}
 
 /// this is safety, dirty mutex ensure
-unsafe impl<V> Sync for SyncVec<V> {}
+unsafe impl<V: Send + Sync> Sync for SyncVec<V> {}
+
+thread_local! {
+    /// the vecs borrowed by this thread, by address, once per borrow
+    static READING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
 
 impl<V> SyncVec<V> {
     pub fn new_arc() -> Arc<Self> {
@@ -24,92 +45,124 @@ impl<V> SyncVec<V> {
     }
 
     pub fn new() -> Self {
-        Self {
-            dirty: UnsafeCell::new(Vec::new()),
-            lock: Default::default(),
-        }
+        Self::with_vec(Vec::new())
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            dirty: UnsafeCell::new(Vec::with_capacity(capacity)),
-            lock: Default::default(),
-        }
+        Self::with_vec(Vec::with_capacity(capacity))
     }
 
     pub fn with_vec(vec: Vec<V>) -> Self {
         Self {
             dirty: UnsafeCell::new(vec),
+            dirty_lock: RwLock::new(()),
             lock: Default::default(),
+            snapshots: SnapshotCache::new(),
         }
     }
 
+    /// Insert `v` at `index`, shifting the values after it. always returns `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`, see [`SyncVec::try_insert`].
     pub fn insert(&self, index: usize, v: V) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.insert(index, v);
-        drop(g);
+        self.write(|m| m.insert(index, v));
         None
     }
 
+    /// Replace the value at `index`, returns the old one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds, see [`SyncVec::try_set`] and [`SyncVec::replace`].
     pub fn set(&self, index: usize, v: V) -> Option<V> {
+        Some(self.write(|m| std::mem::replace(&mut m[index], v)))
+    }
+
+    /// Like [`SyncVec::insert`], but an out of bounds `index` is an error instead of a panic.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// let v = SyncVec::with_vec(vec![1, 3]);
+    /// v.try_insert(1, 2).unwrap();
+    /// assert_eq!(v.replace(2, 4).unwrap(), 3);
+    /// assert_eq!(v.try_set(3, 5).is_err(), true);
+    /// assert_eq!(v.into_inner(), vec![1, 2, 4]);
+    /// ```
+    pub fn try_insert(&self, index: usize, v: V) -> crate::errors::Result<()> {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m[index] = v;
+        let len = self.values_locked().len();
+        if index > len {
+            return Err(crate::err!(
+                "insertion index (is {}) should be <= len (is {})",
+                index,
+                len
+            ));
+        }
+        self.modify_locked(|m| m.insert(index, v));
         drop(g);
-        None
+        Ok(())
     }
 
-    pub fn push(&self, v: V) -> Option<V> {
+    /// like [`SyncVec::set`], but an out of bounds `index` is an error instead of a panic
+    pub fn try_set(&self, index: usize, v: V) -> crate::errors::Result<()> {
+        self.replace(index, v).map(drop)
+    }
+
+    /// replace the value at `index` and return the old one, an out of bounds `index` is an error
+    pub fn replace(&self, index: usize, v: V) -> crate::errors::Result<V> {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.push(v);
+        let len = self.values_locked().len();
+        if index >= len {
+            return Err(crate::err!(
+                "index out of bounds: the len is {} but the index is {}",
+                len,
+                index
+            ));
+        }
+        let old = self.modify_locked(|m| std::mem::replace(&mut m[index], v));
         drop(g);
+        Ok(old)
+    }
+
+    /// always returns `None`
+    pub fn push(&self, v: V) -> Option<V> {
+        self.write(|m| m.push(v));
         None
     }
 
     pub fn pushes(&self, arr: Vec<V>) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        for v in arr {
-            m.push(v);
-        }
-        drop(g);
+        self.write(|m| m.extend(arr));
         None
     }
 
     pub fn push_mut(&mut self, v: V) -> Option<V> {
+        self.lock.touch();
         let m = unsafe { &mut *self.dirty.get() };
         m.push(v);
         None
     }
 
     pub fn pop(&self) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        let r = m.pop();
-        drop(g);
-        r
+        self.write(|m| m.pop())
     }
 
     pub fn pop_mut(&mut self) -> Option<V> {
+        self.lock.touch();
         let m = unsafe { &mut *self.dirty.get() };
         m.pop()
     }
 
     pub fn remove(&self, index: usize) -> Option<V> {
-        let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        if m.len() > index {
-            let v = m.remove(index);
-            drop(g);
-            Some(v)
-        } else {
-            None
-        }
+        self.write(|m| if m.len() > index { Some(m.remove(index)) } else { None })
     }
 
     pub fn remove_mut(&mut self, index: usize) -> Option<V> {
+        self.lock.touch();
         let m = unsafe { &mut *self.dirty.get() };
         if m.len() > index {
             let v = m.remove(index);
@@ -119,26 +172,310 @@ impl<V> SyncVec<V> {
         }
     }
 
-    pub fn len(&self) -> usize {
-        unsafe { (&*self.dirty.get()).len() }
+    /// push every value under one acquisition of the writer lock,
+    /// the values are collected before readers are made to wait
+    pub fn extend<I>(&self, values: I)
+        where
+            I: IntoIterator<Item = V>,
+    {
+        let g = self.lock.lock();
+        let values: Vec<V> = values.into_iter().collect();
+        self.modify_locked(|m| m.extend(values));
+        drop(g);
     }
 
-    pub fn is_empty(&self) -> bool {
-        unsafe { (&*self.dirty.get()).is_empty() }
+    /// Insert the values in order at `index`, shifting the ones after it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// let v = SyncVec::with_vec(vec![0, 4]);
+    /// v.insert_many(1, [1, 2, 3]);
+    /// assert_eq!(v.remove_many([0, 2, 9]), vec![0, 2]);
+    /// assert_eq!(v.extract_if(|x| *x > 3), vec![4]);
+    /// assert_eq!(v.drain(..), vec![1, 3]);
+    /// ```
+    pub fn insert_many<I>(&self, index: usize, values: I)
+        where
+            I: IntoIterator<Item = V>,
+    {
+        let g = self.lock.lock();
+        let values: Vec<V> = values.into_iter().collect();
+        self.modify_locked(|m| {
+            m.splice(index..index, values);
+        });
+        drop(g);
     }
 
-    pub fn clear(&self) {
+    /// Remove the values at `indices` under one acquisition of the writer lock,
+    /// returns them in index order. the indices are the positions before removing,
+    /// those out of bounds are skipped.
+    pub fn remove_many<I>(&self, indices: I) -> Vec<V>
+        where
+            I: IntoIterator<Item = usize>,
+    {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.clear();
+        let mut marked = vec![false; self.values_locked().len()];
+        for i in indices {
+            if let Some(mark) = marked.get_mut(i) {
+                *mark = true;
+            }
+        }
+        let r = self.remove_marked_locked(marked);
         drop(g);
+        r
     }
 
-    pub fn shrink_to_fit(&self) {
+    /// keep only the values `f` holds for, under one acquisition of the writer lock.
+    /// `f` only reads the values, readers do not wait for it
+    pub fn retain<F>(&self, mut f: F)
+        where
+            F: FnMut(&V) -> bool,
+    {
         let g = self.lock.lock();
-        let m = unsafe { &mut *self.dirty.get() };
-        m.shrink_to_fit();
+        let marked = self.values_locked().iter().map(|v| !f(v)).collect();
+        let removed = self.remove_marked_locked(marked);
+        drop(g);
+        drop(removed);
+    }
+
+    /// Remove and return the values `f` holds for, in order, under one acquisition of the writer lock.
+    ///
+    /// # Panics
+    ///
+    /// `f` is given the values mutably, so readers wait for it: reading the vec inside `f` panics.
+    pub fn extract_if<F>(&self, f: F) -> Vec<V>
+        where
+            F: FnMut(&mut V) -> bool,
+    {
+        let g = self.lock.lock();
+        let r = self.extract_locked(f);
         drop(g);
+        r
+    }
+
+    /// Remove and return the values in `range`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds.
+    pub fn drain<R>(&self, range: R) -> Vec<V>
+        where
+            R: RangeBounds<usize>,
+    {
+        self.write(|m| m.drain(range).collect())
+    }
+
+    /// Sort the values under the writer lock, stable.
+    ///
+    /// the order is worked out while readers still see the values, they only wait for it to be applied.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// let v = SyncVec::with_vec(vec![3, 1, 2, 1]);
+    /// v.sort();
+    /// v.dedup();
+    /// assert_eq!(v.insert_sorted(0), 0);
+    /// assert_eq!(v.insert_sorted(4), 4);
+    /// assert_eq!(v.binary_search(&2), Ok(2));
+    /// assert_eq!(v.binary_search(&5), Err(5));
+    /// assert_eq!(v.into_inner(), vec![0, 1, 2, 3, 4]);
+    /// ```
+    pub fn sort(&self)
+        where
+            V: Ord,
+    {
+        self.sort_with(|values, order| order.sort_by(|&a, &b| values[a].cmp(&values[b])))
+    }
+
+    pub fn sort_by<F>(&self, mut compare: F)
+        where
+            F: FnMut(&V, &V) -> Ordering,
+    {
+        self.sort_with(|values, order| order.sort_by(|&a, &b| compare(&values[a], &values[b])))
+    }
+
+    pub fn sort_by_key<K, F>(&self, mut f: F)
+        where
+            F: FnMut(&V) -> K,
+            K: Ord,
+    {
+        self.sort_with(|values, order| order.sort_by_key(|&i| f(&values[i])))
+    }
+
+    pub fn sort_unstable_by<F>(&self, mut compare: F)
+        where
+            F: FnMut(&V, &V) -> Ordering,
+    {
+        self.sort_with(|values, order| {
+            order.sort_unstable_by(|&a, &b| compare(&values[a], &values[b]))
+        })
+    }
+
+    /// remove consecutive repeated values
+    pub fn dedup(&self)
+        where
+            V: PartialEq,
+    {
+        let g = self.lock.lock();
+        let values = self.values_locked();
+        let mut marked = vec![false; values.len()];
+        let mut last = 0;
+        for i in 1..values.len() {
+            if values[i] == values[last] {
+                marked[i] = true;
+            } else {
+                last = i;
+            }
+        }
+        let removed = self.remove_marked_locked(marked);
+        drop(g);
+        drop(removed);
+    }
+
+    /// Remove consecutive values with the same key.
+    ///
+    /// # Panics
+    ///
+    /// `key` is given the values mutably, so readers wait for it: reading the vec inside `key` panics.
+    pub fn dedup_by_key<K, F>(&self, mut key: F)
+        where
+            F: FnMut(&mut V) -> K,
+            K: PartialEq,
+    {
+        let g = self.lock.lock();
+        let mut last = None;
+        let removed = self.extract_locked(|v| {
+            let k = key(v);
+            if last.as_ref() == Some(&k) {
+                true
+            } else {
+                last = Some(k);
+                false
+            }
+        });
+        drop(g);
+        drop(removed);
+    }
+
+    /// like `slice::binary_search`, the vec must be sorted
+    pub fn binary_search(&self, x: &V) -> Result<usize, usize>
+        where
+            V: Ord,
+    {
+        self.read().binary_search(x)
+    }
+
+    /// like `slice::binary_search_by`, the vec must be sorted by `f`
+    pub fn binary_search_by<F>(&self, f: F) -> Result<usize, usize>
+        where
+            F: FnMut(&V) -> Ordering,
+    {
+        self.read().binary_search_by(f)
+    }
+
+    /// insert `v` after the values not greater than it and return its index,
+    /// the vec must be sorted and stays so
+    pub fn insert_sorted(&self, v: V) -> usize
+        where
+            V: Ord,
+    {
+        let g = self.lock.lock();
+        let index = self.values_locked().partition_point(|x| x <= &v);
+        self.modify_locked(|m| m.insert(index, v));
+        drop(g);
+        index
+    }
+
+    /// Swap two values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` or `b` are out of bounds.
+    pub fn swap(&self, a: usize, b: usize) {
+        self.write(|m| m.swap(a, b))
+    }
+
+    /// remove a value and move the last one into its place, `None` if `index` is out of bounds
+    pub fn swap_remove(&self, index: usize) -> Option<V> {
+        self.write(|m| {
+            if index < m.len() {
+                Some(m.swap_remove(index))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn truncate(&self, len: usize) {
+        let removed = self.write(|m| m.split_off(len.min(m.len())));
+        drop(removed);
+    }
+
+    /// the new values are made by `f` before readers are made to wait
+    pub fn resize_with<F>(&self, new_len: usize, mut f: F)
+        where
+            F: FnMut() -> V,
+    {
+        let g = self.lock.lock();
+        let len = self.values_locked().len();
+        let removed = if new_len > len {
+            let values: Vec<V> = (len..new_len).map(|_| f()).collect();
+            self.modify_locked(|m| m.extend(values));
+            vec![]
+        } else {
+            self.modify_locked(|m| m.split_off(new_len))
+        };
+        drop(g);
+        drop(removed);
+    }
+
+    pub fn reverse(&self) {
+        self.write(|m| m.reverse())
+    }
+
+    /// Rotate the values `mid` places to the left.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    pub fn rotate_left(&self, mid: usize) {
+        self.write(|m| m.rotate_left(mid))
+    }
+
+    /// Rotate the values `k` places to the right.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k > len`.
+    pub fn rotate_right(&self, k: usize) {
+        self.write(|m| m.rotate_right(k))
+    }
+
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    pub fn clear(&self) {
+        let removed = self.write(|m| std::mem::replace(m, Vec::with_capacity(m.capacity())));
+        drop(removed);
+    }
+
+    pub fn shrink_to_fit(&self) {
+        self.write(|m| m.shrink_to_fit())
     }
 
     pub fn from(vec: Vec<V>) -> Self {
@@ -146,25 +483,40 @@ impl<V> SyncVec<V> {
         s
     }
 
+    /// the guard borrows the value, writers wait until it drops
     #[inline]
-    pub fn get(&self, index: usize) -> Option<&V> {
-        unsafe {
-            return (&*self.dirty.get()).get(index);
-        }
+    pub fn get(&self, index: usize) -> Option<VecRef<'_, V>> {
+        let r = self.read_lock();
+        let value = unsafe { &*self.dirty.get() }.get(index)?;
+        Some(VecRef { _r: r, value })
     }
 
+    /// Like [`SyncVec::get`], but panics if `index` is out of bounds.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
     #[inline]
-    pub fn get_uncheck(&self, index: usize) -> &V {
-        unsafe { (&*self.dirty.get()).get_unchecked(index) }
+    pub fn get_uncheck(&self, index: usize) -> VecRef<'_, V> {
+        match self.get(index) {
+            Some(v) => v,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len(),
+                index
+            ),
+        }
     }
 
+    /// the guard holds a copy of the value, written back when it drops
     #[inline]
-    pub fn get_mut(&self, index: usize) -> Option<VecRefMut<'_, V>> {
-        let m = unsafe { &mut *self.dirty.get() };
-        Some(VecRefMut {
-            _g: self.lock.lock(),
-            value: Some(m.get_mut(index)?),
-        })
+    pub fn get_mut(&self, index: usize) -> Option<VecRefMut<'_, V>>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock();
+        let value = self.values_locked().get(index)?.clone();
+        Some(VecRefMut::new(g, self, index, value))
     }
 
     #[inline]
@@ -172,22 +524,174 @@ impl<V> SyncVec<V> {
         where
             V: PartialEq,
     {
-        let m = unsafe { &mut *self.dirty.get() };
-        m.contains(x)
+        self.read().contains(x)
     }
 
-    pub fn iter(&self) -> std::slice::Iter<'_, V> {
-        unsafe { (&*self.dirty.get()).iter() }
+    /// Borrow every value until the guard drops, writers wait meanwhile.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// let v = SyncVec::with_vec(vec![1, 2, 3]);
+    /// let values = v.read();
+    /// assert_eq!(values[1], 2);
+    /// assert_eq!(values.iter().sum::<i32>(), 6);
+    /// ```
+    pub fn read(&self) -> VecGuard<'_, V> {
+        let r = self.read_lock();
+        VecGuard {
+            _r: r,
+            values: unsafe { &*self.dirty.get() },
+        }
     }
 
-    pub fn iter_mut(&self) -> VecIterMut<'_, V> {
-        let m = unsafe { &mut *self.dirty.get() };
-        let mut iter = VecIterMut {
+    /// every item borrows the vec on its own, see [`SyncVec::read`] to iterate under one borrow
+    pub fn iter(&self) -> VecRefIter<'_, V> {
+        VecRefIter {
+            vec: self,
+            index: 0,
+        }
+    }
+
+    pub fn iter_mut(&self) -> VecIterMut<'_, V>
+        where
+            V: Clone,
+    {
+        VecIterMut {
             _g: self.lock.lock(),
-            inner: None,
-        };
-        iter.inner = Some(m.iter_mut());
-        return iter;
+            vec: self,
+            index: 0,
+        }
+    }
+
+    /// A consistent copy of the vec as of now, later writes do not change it.
+    ///
+    /// every value is cloned under the writer lock, O(n): writers wait for the copy, readers do not.
+    /// until the next write, a copy still held by a caller is returned again without copying,
+    /// the vec itself keeps no copy alive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// let v = SyncVec::new();
+    /// v.push(1);
+    /// let snapshot = v.snapshot();
+    /// v.push(2);
+    /// assert_eq!(*snapshot, vec![1]);
+    /// assert_eq!(snapshot.ptr_eq(&v.snapshot()), false);
+    /// ```
+    pub fn snapshot(&self) -> Snapshot<Vec<V>>
+        where
+            V: Clone,
+    {
+        self.snapshots.get_or_copy(&self.lock, || self.values_locked().to_vec())
+    }
+
+    /// Write the vec to `path`, encoded under the writer lock through its serde impl.
+    /// the file is replaced atomically, a crash meanwhile leaves the previous one intact.
+    pub fn save_to<P: AsRef<Path>>(&self, path: P) -> crate::errors::Result<()>
+        where
+            V: Serialize,
+    {
+        let g = self.lock.lock();
+        let payload = persist::encode(self);
+        drop(g);
+        persist::save(path.as_ref(), Kind::Vec, &payload?)
+    }
+
+    /// Read a vec written by [`SyncVec::save_to`], a truncated or corrupted file is an error.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> crate::errors::Result<Self>
+        where
+            V: DeserializeOwned,
+    {
+        let payload = persist::load(path.as_ref(), Kind::Vec)?;
+        persist::decode(&payload)
+    }
+
+    /// like [`SyncVec::insert`], but awaits the writer lock instead of blocking the thread
+    pub async fn insert_async(&self, index: usize, v: V) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        self.modify_locked(|m| m.insert(index, v));
+        drop(g);
+        None
+    }
+
+    pub async fn set_async(&self, index: usize, v: V) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        let old = self.modify_locked(|m| std::mem::replace(&mut m[index], v));
+        drop(g);
+        Some(old)
+    }
+
+    pub async fn push_async(&self, v: V) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        self.modify_locked(|m| m.push(v));
+        drop(g);
+        None
+    }
+
+    pub async fn pop_async(&self) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        let r = self.modify_locked(|m| m.pop());
+        drop(g);
+        r
+    }
+
+    pub async fn remove_async(&self, index: usize) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        let r = self.modify_locked(|m| if m.len() > index { Some(m.remove(index)) } else { None });
+        drop(g);
+        r
+    }
+
+    pub async fn clear_async(&self) {
+        let g = self.lock.lock_async().await;
+        let removed = self.modify_locked(|m| std::mem::replace(m, Vec::with_capacity(m.capacity())));
+        drop(g);
+        drop(removed);
+    }
+
+    /// the returned guard is `Send` and may be held across `.await`,
+    /// the vec must not be written by the same task until it drops.
+    pub async fn get_mut_async(&self, index: usize) -> Option<VecRefMut<'_, V, Async>>
+        where
+            V: Clone,
+    {
+        let g = self.lock.lock_async().await;
+        let value = self.values_locked().get(index)?.clone();
+        Some(VecRefMut::new(g, self, index, value))
+    }
+
+    /// # Examples
+    ///
+    /// ```
+    /// use dark_std::sync::SyncVec;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let v = SyncVec::from(vec![1, 2]);
+    ///     let mut iter = v.iter_mut_async().await;
+    ///     tokio::task::yield_now().await;
+    ///     for mut x in &mut iter {
+    ///         *x += 1;
+    ///     }
+    ///     drop(iter);
+    ///     assert_eq!(*v.read(), [2, 3]);
+    /// }
+    /// ```
+    pub async fn iter_mut_async(&self) -> VecIterMut<'_, V, Async>
+        where
+            V: Clone,
+    {
+        VecIterMut {
+            _g: self.lock.lock_async().await,
+            vec: self,
+            index: 0,
+        }
     }
 
     pub fn into_iter(self) -> IntoIter<V> {
@@ -195,21 +699,248 @@ impl<V> SyncVec<V> {
         m.into_iter()
     }
 
-    pub fn dirty_ref(&self) -> &Vec<V> {
-        unsafe { &*self.dirty.get() }
+    /// the values are no longer borrowed without a guard, this returns [`SyncVec::read`]
+    #[deprecated(note = "use `read()` to borrow the values, or `snapshot()` for a copy")]
+    pub fn dirty_ref(&self) -> VecGuard<'_, V> {
+        self.read()
     }
 
     pub fn into_inner(self) -> Vec<V> {
         self.dirty.into_inner()
     }
+
+    fn addr(&self) -> usize {
+        self as *const Self as usize
+    }
+
+    /// share `dirty_lock`, counted as a borrow of this thread until the guard drops
+    fn read_lock(&self) -> Reading<'_> {
+        // a writer of this thread is inside `modify_locked`, it would never release the lock
+        if self.dirty_lock.is_locked_exclusive() && self.lock.held_by_current_thread() {
+            panic!("SyncVec is being written by the current thread, it can not be read meanwhile");
+        }
+        let r = self.dirty_lock.read_recursive();
+        READING.with(|reading| reading.borrow_mut().push(self.addr()));
+        Reading {
+            vec: self.addr(),
+            _r: r,
+        }
+    }
+
+    /// the values, with the writer lock held: nothing changes them, readers only share them
+    fn values_locked(&self) -> &[V] {
+        unsafe { &*self.dirty.get() }
+    }
+
+    /// run `f` with `dirty_lock` taken exclusively, the writer lock must be held.
+    /// the values it removes are returned to be dropped once readers are let in again
+    fn modify_locked<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut Vec<V>) -> R,
+    {
+        if READING.with(|reading| reading.borrow().contains(&self.addr())) {
+            panic!("SyncVec is borrowed by the current thread, drop its VecRef or VecGuard before writing");
+        }
+        let w = self.dirty_lock.write();
+        let r = f(unsafe { &mut *self.dirty.get() });
+        drop(w);
+        r
+    }
+
+    /// run `f` under the writer lock
+    fn write<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut Vec<V>) -> R,
+    {
+        let g = self.lock.lock();
+        let r = self.modify_locked(f);
+        drop(g);
+        r
+    }
+
+    /// sort the indices of the values with `sort`, then move the values into that order
+    fn sort_with<F>(&self, sort: F)
+        where
+            F: FnOnce(&[V], &mut [usize]),
+    {
+        let g = self.lock.lock();
+        let values = self.values_locked();
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        sort(values, &mut order);
+        self.modify_locked(|m| {
+            let mut slots: Vec<Option<V>> = std::mem::take(m).into_iter().map(Some).collect();
+            m.extend(order.into_iter().map(|i| slots[i].take().unwrap()));
+        });
+        drop(g);
+    }
+
+    /// split the vec into the values `f` holds for, returned, and the others, kept.
+    /// `f` changes the values, it runs under `dirty_lock`
+    fn extract_locked<F>(&self, mut f: F) -> Vec<V>
+        where
+            F: FnMut(&mut V) -> bool,
+    {
+        self.modify_locked(|m| {
+            let marked: Vec<bool> = m.iter_mut().map(&mut f).collect();
+            Self::partition(m, marked)
+        })
+    }
+
+    /// remove the values at the marked positions, returns them in order
+    fn remove_marked_locked(&self, marked: Vec<bool>) -> Vec<V> {
+        if !marked.contains(&true) {
+            return vec![];
+        }
+        self.modify_locked(|m| Self::partition(m, marked))
+    }
+
+    fn partition(m: &mut Vec<V>, marked: Vec<bool>) -> Vec<V> {
+        let mut removed = vec![];
+        let mut kept = Vec::with_capacity(m.len());
+        for (v, mark) in std::mem::take(m).into_iter().zip(marked) {
+            if mark {
+                removed.push(v);
+            } else {
+                kept.push(v);
+            }
+        }
+        *m = kept;
+        removed
+    }
+}
+
+/// a borrow of a vec by the current thread
+struct Reading<'a> {
+    vec: usize,
+    _r: RwLockReadGuard<'a, ()>,
 }
 
-pub struct VecRefMut<'a, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
-    value: Option<&'a mut V>,
+impl Drop for Reading<'_> {
+    fn drop(&mut self) {
+        // the thread local may be gone already while the thread exits
+        let _ = READING.try_with(|reading| {
+            let mut reading = reading.borrow_mut();
+            if let Some(i) = reading.iter().rposition(|vec| *vec == self.vec) {
+                reading.swap_remove(i);
+            }
+        });
+    }
 }
 
-impl<'a, V> Deref for VecRefMut<'_, V> {
+/// a value of the vec, writers wait until it drops
+pub struct VecRef<'a, V> {
+    _r: Reading<'a>,
+    value: &'a V,
+}
+
+impl<V> Deref for VecRef<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &Self::Target {
+        self.value
+    }
+}
+
+impl<V> Debug for VecRef<'_, V>
+    where
+        V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<V> Display for VecRef<'_, V>
+    where
+        V: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.value.fmt(f)
+    }
+}
+
+impl<V> PartialEq<Self> for VecRef<'_, V>
+    where
+        V: Eq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.value.eq(other.value)
+    }
+}
+
+impl<V> Eq for VecRef<'_, V> where V: Eq {}
+
+/// the values of one vec, borrowed until the guard drops.
+/// keep it short-lived, writers wait for it. writing the vec on the thread holding it panics.
+pub struct VecGuard<'a, V> {
+    _r: Reading<'a>,
+    values: &'a [V],
+}
+
+impl<V> Deref for VecGuard<'_, V> {
+    type Target = [V];
+
+    fn deref(&self) -> &Self::Target {
+        self.values
+    }
+}
+
+impl<V> Debug for VecGuard<'_, V>
+    where
+        V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.values.fmt(f)
+    }
+}
+
+impl<'g, V> IntoIterator for &'g VecGuard<'_, V> {
+    type Item = &'g V;
+    type IntoIter = SliceIter<'g, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.values.iter()
+    }
+}
+
+/// a copy of the value, written back to the vec when dropped
+pub struct VecRefMut<'a, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    vec: &'a SyncVec<V>,
+    index: usize,
+    value: Option<V>,
+    changed: bool,
+}
+
+impl<'a, V, M> VecRefMut<'a, V, M> {
+    /// `value` is a copy of the value at `index`, taken under the lock held by `g`
+    fn new(g: WriteGuard<'a, M>, vec: &'a SyncVec<V>, index: usize, value: V) -> Self {
+        Self {
+            _g: g,
+            vec,
+            index,
+            value: Some(value),
+            changed: false,
+        }
+    }
+}
+
+impl<V, M> Drop for VecRefMut<'_, V, M> {
+    fn drop(&mut self) {
+        if !self.changed {
+            return;
+        }
+        if let Some(value) = self.value.take() {
+            // the lock is still held, nothing moved the value meanwhile
+            let old = self
+                .vec
+                .modify_locked(|m| std::mem::replace(&mut m[self.index], value));
+            drop(old);
+        }
+    }
+}
+
+impl<V, M> Deref for VecRefMut<'_, V, M> {
     type Target = V;
 
     fn deref(&self) -> &Self::Target {
@@ -217,27 +948,28 @@ impl<'a, V> Deref for VecRefMut<'_, V> {
     }
 }
 
-impl<'a, V> DerefMut for VecRefMut<'_, V> {
+impl<V, M> DerefMut for VecRefMut<'_, V, M> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        self.changed = true;
         self.value.as_mut().unwrap()
     }
 }
 
-impl<'a, V> Debug for VecRefMut<'_, V>
+impl<V, M> Debug for VecRefMut<'_, V, M>
     where
         V: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.value.fmt(f)
+        self.deref().fmt(f)
     }
 }
 
-impl<'a, V> Display for VecRefMut<'_, V>
+impl<V, M> Display for VecRefMut<'_, V, M>
     where
         V: Display,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.value.as_ref().unwrap().fmt(f)
+        self.deref().fmt(f)
     }
 }
 
@@ -263,36 +995,42 @@ impl<'a, V> Iterator for Iter<'a, V> {
     }
 }
 
-pub struct VecIterMut<'a, V> {
-    _g: ReentrantMutexGuard<'a, ()>,
-    inner: Option<SliceIterMut<'a, V>>,
+/// yields the values by index, each borrowed on its own
+pub struct VecRefIter<'a, V> {
+    vec: &'a SyncVec<V>,
+    index: usize,
 }
 
-impl<'a, V> Deref for VecIterMut<'a, V> {
-    type Target = SliceIterMut<'a, V>;
+impl<'a, V> Iterator for VecRefIter<'a, V> {
+    type Item = VecRef<'a, V>;
 
-    fn deref(&self) -> &Self::Target {
-        self.inner.as_ref().unwrap()
+    fn next(&mut self) -> Option<Self::Item> {
+        let v = self.vec.get(self.index)?;
+        self.index += 1;
+        Some(v)
     }
 }
 
-impl<'a, V> DerefMut for VecIterMut<'a, V> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.inner.as_mut().unwrap()
-    }
+/// holds the writer lock, yields a copy of every value written back when dropped
+pub struct VecIterMut<'a, V, M = Blocking> {
+    _g: WriteGuard<'a, M>,
+    vec: &'a SyncVec<V>,
+    index: usize,
 }
 
-impl<'a, V> Iterator for VecIterMut<'a, V> {
-    type Item = &'a mut V;
+impl<'a, V: Clone, M> Iterator for VecIterMut<'a, V, M> {
+    type Item = VecRefMut<'a, V, M>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.as_mut().unwrap().next()
+        let value = self.vec.values_locked().get(self.index)?.clone();
+        self.index += 1;
+        Some(VecRefMut::new(self._g.fork(), self.vec, self.index - 1, value))
     }
 }
 
 impl<'a, V> IntoIterator for &'a SyncVec<V> {
-    type Item = &'a V;
-    type IntoIter = std::slice::Iter<'a, V>;
+    type Item = VecRef<'a, V>;
+    type IntoIter = VecRefIter<'a, V>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
@@ -316,7 +1054,7 @@ impl<V> Serialize for SyncVec<V>
         where
             S: Serializer,
     {
-        self.dirty_ref().serialize(serializer)
+        self.read().serialize(serializer)
     }
 }
 
@@ -338,7 +1076,7 @@ impl<V> Debug for SyncVec<V>
         V: Debug,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        self.dirty_ref().fmt(f)
+        self.read().fmt(f)
     }
 }
 
@@ -347,28 +1085,19 @@ impl<V> Display for SyncVec<V>
         V: Display,
 {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        use std::fmt::Pointer;
-        self.dirty_ref().fmt(f)
-    }
-}
-
-impl<V> Index<usize> for SyncVec<V> {
-    type Output = V;
-
-    fn index(&self, index: usize) -> &Self::Output {
-        self.get_uncheck(index)
+        std::fmt::Pointer::fmt(&self.dirty.get(), f)
     }
 }
 
 impl<V: PartialEq> PartialEq for SyncVec<V> {
     fn eq(&self, other: &Self) -> bool {
-        self.dirty_ref().eq(other.dirty_ref())
+        *self.read() == *other.read()
     }
 }
 
 impl<V: Clone> Clone for SyncVec<V> {
     fn clone(&self) -> Self {
-        SyncVec::from(self.dirty_ref().to_vec())
+        SyncVec::from(self.read().to_vec())
     }
 }
 
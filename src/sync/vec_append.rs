@@ -0,0 +1,315 @@
+use crate::sync::WriteLock;
+use serde::{Deserializer, Serialize, Serializer};
+use std::fmt::{Debug, Formatter};
+use std::mem::MaybeUninit;
+use std::ops::Index;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// the first bucket holds `1 << FIRST_BUCKET_BITS` values, each next one twice as many
+const FIRST_BUCKET_BITS: u32 = 5;
+const BUCKETS: usize = (usize::BITS - FIRST_BUCKET_BITS) as usize;
+
+/// an append-only vec, values never move once pushed.
+///
+/// values live in buckets of doubling size that are never reallocated, so a `&V` returned by
+/// [`SyncAppendVec::get`] stays valid while other threads push. reads are lock-free,
+/// pushes are serialized by the writer lock and publish the new length after the value is written,
+/// so readers and iterators see a prefix of the pushes, in order.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncAppendVec;
+///
+/// let v = SyncAppendVec::new();
+/// let i = v.push("a");
+/// let a = v.get(i).unwrap();
+/// for _ in 0..1000 {
+///     v.push("b");
+/// }
+/// assert_eq!(*a, "a");
+/// assert_eq!(v.len(), 1001);
+/// ```
+pub struct SyncAppendVec<V> {
+    buckets: [AtomicPtr<MaybeUninit<V>>; BUCKETS],
+    len: AtomicUsize,
+    lock: WriteLock,
+}
+
+/// values are moved in by any thread and borrowed by many
+unsafe impl<V: Send> Send for SyncAppendVec<V> {}
+
+unsafe impl<V: Send + Sync> Sync for SyncAppendVec<V> {}
+
+/// the bucket of `index` and the position inside it
+#[inline]
+fn locate(index: usize) -> (usize, usize) {
+    let i = index + (1 << FIRST_BUCKET_BITS);
+    let bit = usize::BITS - 1 - i.leading_zeros();
+    ((bit - FIRST_BUCKET_BITS) as usize, i - (1 << bit))
+}
+
+#[inline]
+fn bucket_len(bucket: usize) -> usize {
+    1 << (bucket as u32 + FIRST_BUCKET_BITS)
+}
+
+impl<V> SyncAppendVec<V> {
+    pub fn new_arc() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    pub fn new() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicPtr::new(ptr::null_mut())),
+            len: AtomicUsize::new(0),
+            lock: Default::default(),
+        }
+    }
+
+    /// returns the index of `v`
+    pub fn push(&self, v: V) -> usize {
+        let g = self.lock.lock();
+        let index = self.push_locked(v);
+        drop(g);
+        index
+    }
+
+    /// like [`SyncAppendVec::push`], but awaits the writer lock instead of blocking the thread
+    pub async fn push_async(&self, v: V) -> usize {
+        let g = self.lock.lock_async().await;
+        let index = self.push_locked(v);
+        drop(g);
+        index
+    }
+
+    /// the values are pushed together, a reader sees none or some of them in order.
+    /// returns the index of the first one.
+    pub fn extend<I: IntoIterator<Item = V>>(&self, iter: I) -> usize {
+        let g = self.lock.lock();
+        let first = self.len.load(Ordering::Relaxed);
+        for v in iter {
+            self.push_locked(v);
+        }
+        drop(g);
+        first
+    }
+
+    /// lock-free, the reference stays valid until the vec drops
+    #[inline]
+    pub fn get(&self, index: usize) -> Option<&V> {
+        if index >= self.len.load(Ordering::Acquire) {
+            return None;
+        }
+        let (bucket, offset) = locate(index);
+        // the length is published after the bucket and the value
+        let b = self.buckets[bucket].load(Ordering::Acquire);
+        Some(unsafe { (*b.add(offset)).assume_init_ref() })
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut V> {
+        if index >= *self.len.get_mut() {
+            return None;
+        }
+        let (bucket, offset) = locate(index);
+        let b = *self.buckets[bucket].get_mut();
+        Some(unsafe { (*b.add(offset)).assume_init_mut() })
+    }
+
+    pub fn first(&self) -> Option<&V> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&V> {
+        self.get(self.len().checked_sub(1)?)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// the values pushed before the iteration began, later pushes are not seen
+    pub fn iter(&self) -> AppendVecIter<'_, V> {
+        AppendVecIter {
+            vec: self,
+            index: 0,
+            len: self.len(),
+        }
+    }
+
+    pub fn into_inner(mut self) -> Vec<V> {
+        let len = *self.len.get_mut();
+        let mut m = Vec::with_capacity(len);
+        for i in 0..len {
+            let (bucket, offset) = locate(i);
+            let b = *self.buckets[bucket].get_mut();
+            m.push(unsafe { (*b.add(offset)).assume_init_read() });
+        }
+        // moved out, the buckets are only freed
+        *self.len.get_mut() = 0;
+        m
+    }
+
+    fn push_locked(&self, v: V) -> usize {
+        let index = self.len.load(Ordering::Relaxed);
+        let (bucket, offset) = locate(index);
+        let mut b = self.buckets[bucket].load(Ordering::Relaxed);
+        if b.is_null() {
+            let mut slots: Vec<MaybeUninit<V>> = Vec::with_capacity(bucket_len(bucket));
+            slots.resize_with(bucket_len(bucket), MaybeUninit::uninit);
+            b = Box::into_raw(slots.into_boxed_slice()) as *mut MaybeUninit<V>;
+            self.buckets[bucket].store(b, Ordering::Release);
+        }
+        unsafe {
+            (*b.add(offset)).write(v);
+        }
+        self.len.store(index + 1, Ordering::Release);
+        index
+    }
+}
+
+impl<V> Drop for SyncAppendVec<V> {
+    fn drop(&mut self) {
+        let len = *self.len.get_mut();
+        for i in 0..len {
+            let (bucket, offset) = locate(i);
+            let b = *self.buckets[bucket].get_mut();
+            unsafe {
+                (*b.add(offset)).assume_init_drop();
+            }
+        }
+        for (bucket, b) in self.buckets.iter_mut().enumerate() {
+            let b = *b.get_mut();
+            if b.is_null() {
+                break;
+            }
+            unsafe {
+                drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                    b,
+                    bucket_len(bucket),
+                )));
+            }
+        }
+    }
+}
+
+impl<V> Default for SyncAppendVec<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> Index<usize> for SyncAppendVec<V> {
+    type Output = V;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        match self.get(index) {
+            Some(v) => v,
+            None => panic!(
+                "index out of bounds: the len is {} but the index is {}",
+                self.len(),
+                index
+            ),
+        }
+    }
+}
+
+pub struct AppendVecIter<'a, V> {
+    vec: &'a SyncAppendVec<V>,
+    index: usize,
+    len: usize,
+}
+
+impl<'a, V> Iterator for AppendVecIter<'a, V> {
+    type Item = &'a V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.len {
+            return None;
+        }
+        let v = self.vec.get(self.index);
+        self.index += 1;
+        v
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let n = self.len - self.index;
+        (n, Some(n))
+    }
+}
+
+impl<V> ExactSizeIterator for AppendVecIter<'_, V> {}
+
+impl<'a, V> IntoIterator for &'a SyncAppendVec<V> {
+    type Item = &'a V;
+    type IntoIter = AppendVecIter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<V> IntoIterator for SyncAppendVec<V> {
+    type Item = V;
+    type IntoIter = std::vec::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+impl<V> From<Vec<V>> for SyncAppendVec<V> {
+    fn from(arg: Vec<V>) -> Self {
+        let s = Self::new();
+        s.extend(arg);
+        s
+    }
+}
+
+impl<V> FromIterator<V> for SyncAppendVec<V> {
+    fn from_iter<T: IntoIterator<Item = V>>(iter: T) -> Self {
+        let s = Self::new();
+        s.extend(iter);
+        s
+    }
+}
+
+impl<V> Serialize for SyncAppendVec<V>
+    where
+        V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+impl<'de, V> serde::Deserialize<'de> for SyncAppendVec<V>
+    where
+        V: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let m = Vec::deserialize(deserializer)?;
+        Ok(Self::from(m))
+    }
+}
+
+impl<V> Debug for SyncAppendVec<V>
+    where
+        V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
@@ -0,0 +1,235 @@
+use crate::sync::WriteLock;
+use serde::{Deserializer, Serialize, Serializer};
+use std::cell::UnsafeCell;
+use std::collections::VecDeque;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// a VecDeque shared between threads and tasks, every access takes the writer lock.
+///
+/// values may be popped from either end at any time, so they are returned by value,
+/// never borrowed. see [`SyncBoundedQueue`](crate::sync::SyncBoundedQueue) to wait for values.
+///
+/// # Examples
+///
+/// ```
+/// use dark_std::sync::SyncVecDeque;
+///
+/// let q = SyncVecDeque::new();
+/// q.push_back(2);
+/// q.push_front(1);
+/// q.push_back(3);
+/// assert_eq!(q.pop_front(), Some(1));
+/// assert_eq!(q.pop_back(), Some(3));
+/// assert_eq!(q.len(), 1);
+/// ```
+pub struct SyncVecDeque<V> {
+    dirty: UnsafeCell<VecDeque<V>>,
+    len: AtomicUsize,
+    lock: WriteLock,
+}
+
+/// this is safety, dirty mutex ensure
+unsafe impl<V: Send> Send for SyncVecDeque<V> {}
+
+/// this is safety, dirty mutex ensure
+unsafe impl<V: Send> Sync for SyncVecDeque<V> {}
+
+impl<V> SyncVecDeque<V> {
+    pub fn new_arc() -> Arc<Self> {
+        Arc::new(Self::new())
+    }
+
+    pub fn new() -> Self {
+        Self::with_deque(VecDeque::new())
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::with_deque(VecDeque::with_capacity(capacity))
+    }
+
+    pub fn with_deque(deque: VecDeque<V>) -> Self {
+        Self {
+            len: AtomicUsize::new(deque.len()),
+            dirty: UnsafeCell::new(deque),
+            lock: Default::default(),
+        }
+    }
+
+    pub fn push_front(&self, v: V) {
+        self.write(|m| m.push_front(v))
+    }
+
+    pub fn push_back(&self, v: V) {
+        self.write(|m| m.push_back(v))
+    }
+
+    pub fn pop_front(&self) -> Option<V> {
+        self.write(|m| m.pop_front())
+    }
+
+    pub fn pop_back(&self) -> Option<V> {
+        self.write(|m| m.pop_back())
+    }
+
+    /// like [`SyncVecDeque::push_back`], but awaits the writer lock instead of blocking the thread
+    pub async fn push_back_async(&self, v: V) {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        m.push_back(v);
+        self.len.store(m.len(), Ordering::Release);
+        drop(g);
+    }
+
+    /// like [`SyncVecDeque::pop_front`], but awaits the writer lock instead of blocking the thread.
+    /// it returns at once when empty, see [`SyncBoundedQueue::pop_async`](crate::sync::SyncBoundedQueue::pop_async) to wait for a value.
+    pub async fn pop_front_async(&self) -> Option<V> {
+        let g = self.lock.lock_async().await;
+        let m = unsafe { &mut *self.dirty.get() };
+        let r = m.pop_front();
+        self.len.store(m.len(), Ordering::Release);
+        drop(g);
+        r
+    }
+
+    /// a copy of the first value
+    pub fn front(&self) -> Option<V>
+        where
+            V: Clone,
+    {
+        self.write(|m| m.front().cloned())
+    }
+
+    /// a copy of the last value
+    pub fn back(&self) -> Option<V>
+        where
+            V: Clone,
+    {
+        self.write(|m| m.back().cloned())
+    }
+
+    /// push every value to the back under one acquisition of the writer lock
+    pub fn extend<I>(&self, values: I)
+        where
+            I: IntoIterator<Item = V>,
+    {
+        self.write(|m| m.extend(values))
+    }
+
+    /// remove every value, front to back
+    pub fn drain(&self) -> Vec<V> {
+        self.write(|m| m.drain(..).collect())
+    }
+
+    pub fn retain<F>(&self, f: F)
+        where
+            F: FnMut(&V) -> bool,
+    {
+        self.write(|m| m.retain(f))
+    }
+
+    pub fn clear(&self) {
+        self.write(|m| m.clear())
+    }
+
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Acquire)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// a copy of the values, front to back
+    pub fn to_vec(&self) -> Vec<V>
+        where
+            V: Clone,
+    {
+        self.write(|m| m.iter().cloned().collect())
+    }
+
+    pub fn into_inner(self) -> VecDeque<V> {
+        self.dirty.into_inner()
+    }
+
+    /// run `f` under the writer lock and keep `len` in step
+    fn write<F, R>(&self, f: F) -> R
+        where
+            F: FnOnce(&mut VecDeque<V>) -> R,
+    {
+        let g = self.lock.lock();
+        let m = unsafe { &mut *self.dirty.get() };
+        let r = f(m);
+        self.len.store(m.len(), Ordering::Release);
+        drop(g);
+        r
+    }
+}
+
+impl<V> Default for SyncVecDeque<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> From<VecDeque<V>> for SyncVecDeque<V> {
+    fn from(arg: VecDeque<V>) -> Self {
+        Self::with_deque(arg)
+    }
+}
+
+impl<V> From<Vec<V>> for SyncVecDeque<V> {
+    fn from(arg: Vec<V>) -> Self {
+        Self::with_deque(arg.into())
+    }
+}
+
+impl<V> IntoIterator for SyncVecDeque<V> {
+    type Item = V;
+    type IntoIter = std::collections::vec_deque::IntoIter<V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.into_inner().into_iter()
+    }
+}
+
+impl<V> Serialize for SyncVecDeque<V>
+    where
+        V: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+    {
+        let g = self.lock.lock();
+        let r = unsafe { &*self.dirty.get() }.serialize(serializer);
+        drop(g);
+        r
+    }
+}
+
+impl<'de, V> serde::Deserialize<'de> for SyncVecDeque<V>
+    where
+        V: serde::Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        let m = VecDeque::deserialize(deserializer)?;
+        Ok(Self::from(m))
+    }
+}
+
+impl<V> Debug for SyncVecDeque<V>
+    where
+        V: Debug,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let g = self.lock.lock();
+        let r = unsafe { &*self.dirty.get() }.fmt(f);
+        drop(g);
+        r
+    }
+}
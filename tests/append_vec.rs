@@ -0,0 +1,116 @@
+use dark_std::sync::SyncAppendVec;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+pub fn test_push_get() {
+    let v = SyncAppendVec::new();
+    assert_eq!(true, v.is_empty());
+    assert_eq!(None, v.get(0));
+    assert_eq!(0, v.push(0));
+    let first = v.get(0).unwrap();
+    for i in 1..10_000 {
+        assert_eq!(i, v.push(i));
+    }
+    // the first value did not move while the vec grew
+    assert_eq!(0, *first);
+    assert_eq!(10_000, v.len());
+    assert_eq!(Some(&9_999), v.last());
+    assert_eq!(Some(&0), v.first());
+    assert_eq!(5_000, v[5_000]);
+    assert_eq!(None, v.get(10_000));
+    assert_eq!(10_000, v.extend(vec![1, 2, 3]));
+    assert_eq!(10_003, v.len());
+    assert_eq!((0..10_000).collect::<Vec<_>>(), v.iter().take(10_000).cloned().collect::<Vec<_>>());
+}
+
+#[test]
+#[should_panic]
+pub fn test_index_out_of_bounds() {
+    let v = SyncAppendVec::<i32>::new();
+    v.push(1);
+    let _ = v[1];
+}
+
+#[test]
+pub fn test_concurrent() {
+    let v = Arc::new(SyncAppendVec::new());
+    let mut handles = vec![];
+    for t in 0..4 {
+        let v = v.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..5_000 {
+                let index = v.push((t, i));
+                assert_eq!(Some(&(t, i)), v.get(index));
+            }
+        }));
+    }
+    let reader = {
+        let v = v.clone();
+        std::thread::spawn(move || {
+            while v.len() < 20_000 {
+                // every value of the prefix is there, and each thread's values are in order
+                let mut last = [-1; 4];
+                for (t, i) in v.iter() {
+                    assert!(*i > last[*t as usize]);
+                    last[*t as usize] = *i;
+                }
+            }
+        })
+    };
+    for h in handles {
+        h.join().unwrap();
+    }
+    reader.join().unwrap();
+    assert_eq!(20_000, v.iter().count());
+}
+
+#[test]
+pub fn test_drop() {
+    struct Counted(Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let v = SyncAppendVec::new();
+    for _ in 0..100 {
+        v.push(Counted(dropped.clone()));
+    }
+    drop(v);
+    assert_eq!(100, dropped.load(Ordering::SeqCst));
+
+    let v = SyncAppendVec::new();
+    for _ in 0..100 {
+        v.push(Counted(dropped.clone()));
+    }
+    let inner = v.into_inner();
+    assert_eq!(100, dropped.load(Ordering::SeqCst));
+    assert_eq!(100, inner.len());
+    drop(inner);
+    assert_eq!(200, dropped.load(Ordering::SeqCst));
+}
+
+#[test]
+pub fn test_debug_serde() {
+    let v: SyncAppendVec<i32> = vec![1, 2, 3].into();
+    assert_eq!("[1, 2, 3]", format!("{:?}", v));
+    fn is_serialize<T: serde::Serialize + serde::de::DeserializeOwned + Send + Sync>(_: &T) {}
+    is_serialize(&v);
+    assert_eq!(vec![1, 2, 3], v.into_iter().collect::<Vec<_>>());
+}
+
+#[tokio::test]
+pub async fn test_push_async() {
+    let v = SyncAppendVec::new_arc();
+    let mut tasks = vec![];
+    for i in 0..10 {
+        let v = v.clone();
+        tasks.push(tokio::spawn(async move { v.push_async(i).await }));
+    }
+    for t in tasks {
+        t.await.unwrap();
+    }
+    assert_eq!(10, v.len());
+}
@@ -1,4 +1,4 @@
-use dark_std::sync::SyncBtreeMap;
+use dark_std::sync::{BtreeMapEntry, MapEvent, SyncBtreeMap};
 use std::ops::Deref;
 use std::sync::Arc;
 
@@ -87,3 +87,532 @@ pub fn test_iter_mut() {
         assert_eq!(*v, 2);
     }
 }
+
+#[test]
+pub fn test_entry() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    *m.entry(1).or_insert(0) += 1;
+    *m.entry(1).or_insert(0) += 1;
+    m.entry(2).and_modify(|v| *v += 1).or_insert(5);
+    m.entry(2).and_modify(|v| *v += 1).or_insert(5);
+    assert_eq!(2, *m.get(&1).unwrap());
+    assert_eq!(6, *m.get(&2).unwrap());
+    assert_eq!(0, *m.entry(3).or_default());
+}
+
+#[test]
+pub fn test_entry_match() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    match m.entry(1) {
+        BtreeMapEntry::Occupied(_) => panic!("must be vacant"),
+        BtreeMapEntry::Vacant(e) => {
+            assert_eq!(&1, e.key());
+            e.insert(1);
+        }
+    }
+    match m.entry(1) {
+        BtreeMapEntry::Occupied(mut e) => {
+            assert_eq!(1, e.insert(2));
+            assert_eq!(&2, e.get());
+            assert_eq!((1, 2), e.remove_entry());
+        }
+        BtreeMapEntry::Vacant(_) => panic!("must be occupied"),
+    }
+    assert_eq!(true, m.get(&1).is_none());
+    assert_eq!(0, m.len());
+}
+
+#[test]
+pub fn test_entry_concurrent() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    let mut handles = vec![];
+    for _ in 0..8 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                *m.entry(i % 10).or_insert(0) += 1;
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    for i in 0..10 {
+        assert_eq!(800, *m.get(&i).unwrap());
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_async() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    m.insert_async(0, 0).await;
+    let mut tasks = vec![];
+    for _ in 0..8 {
+        let m = m.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..100 {
+                let mut v = m.get_mut_async(&0).await.unwrap();
+                tokio::task::yield_now().await;
+                *v += 1;
+            }
+        }));
+    }
+    for t in tasks {
+        t.await.unwrap();
+    }
+    assert_eq!(800, *m.get(&0).unwrap());
+    *m.entry_async(1).await.or_insert(0) += 1;
+    for (_, v) in m.iter_mut_async().await {
+        *v += 1;
+    }
+    assert_eq!(801, *m.get(&0).unwrap());
+    assert_eq!(Some(2), m.remove_async(&1).await);
+    m.clear_async().await;
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_subscribe() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    let events = m.subscribe(16);
+    m.insert(1, 1);
+    m.insert(1, 2);
+    *m.entry(2).or_insert(0) += 1;
+    m.remove(&1);
+    m.clear();
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Inserted { key: 1, old: None, new: 1 },
+            MapEvent::Inserted { key: 1, old: Some(1), new: 2 },
+            MapEvent::Inserted { key: 2, old: None, new: 0 },
+            MapEvent::Removed { key: 1, value: 2 },
+            MapEvent::Cleared,
+        ]
+    );
+    drop(events);
+    m.insert(3, 3);
+}
+
+#[test]
+pub fn test_subscribe_insert_visible() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    let events = m.subscribe(100000);
+    let w = m.clone();
+    let h = std::thread::spawn(move || {
+        for i in 0..1000 {
+            if i % 2 == 0 {
+                w.insert(i, i);
+            } else {
+                w.entry(i).or_insert(i);
+            }
+        }
+    });
+    // the event is only sent once readers see the new key
+    for _ in 0..1000 {
+        match events.recv().unwrap() {
+            MapEvent::Inserted { key, new, .. } => assert_eq!(Some(&new), m.get(&key)),
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    h.join().unwrap();
+}
+
+#[test]
+pub fn test_subscribe_lagged() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    let events = m.subscribe(2);
+    for i in 0..5 {
+        m.insert(i, i);
+    }
+    assert_eq!(2, events.drain().count());
+    m.insert(5, 5);
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Lagged(3),
+            MapEvent::Inserted { key: 5, old: None, new: 5 },
+        ]
+    );
+}
+
+#[test]
+pub fn test_subscribe_order() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    m.insert(0, 0);
+    let events = m.subscribe(100000);
+    let mut handles = vec![];
+    for t in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                m.insert(0, t * 1000 + i);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    // every event replaces the value of the one before
+    let mut last = 0;
+    for e in events.drain() {
+        match e {
+            MapEvent::Inserted { old, new, .. } => {
+                assert_eq!(Some(last), old);
+                last = new;
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert_eq!(last, *m.get(&0).unwrap());
+}
+
+#[test]
+pub fn test_range() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    for i in 0..10 {
+        m.insert(i * 10, i);
+    }
+    let keys: Vec<i32> = m.range(20..50).map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec![20, 30, 40]);
+    let keys: Vec<i32> = m.range(..=20).rev().map(|(k, _)| *k).collect();
+    assert_eq!(keys, vec![20, 10, 0]);
+
+    for (_, v) in m.range_mut(50..) {
+        *v = -*v;
+    }
+    assert_eq!(m.get(&40), Some(&4));
+    assert_eq!(m.get(&50), Some(&-5));
+    assert_eq!(m.range_mut(..30).next_back().map(|(k, _)| *k), Some(20));
+}
+
+#[test]
+pub fn test_range_concurrent() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    let w = m.clone();
+    let h = std::thread::spawn(move || {
+        for _ in 0..1000 {
+            let (k, v) = w.pop_first().unwrap();
+            w.insert(k + 100, v);
+        }
+    });
+    // the writer waits for each scan, which always sees 100 keys in order
+    for _ in 0..1000 {
+        let keys: Vec<i32> = m.range(..).map(|(k, _)| *k).collect();
+        assert_eq!(keys.len(), 100);
+        assert_eq!(true, keys.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(true, m.floor(&i32::MAX).is_some());
+    }
+    h.join().unwrap();
+}
+
+#[test]
+pub fn test_range_write_inside() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    for i in 0..10 {
+        m.insert(i, i);
+    }
+    // the iterator borrows the tree, a write on the same thread must not restructure it
+    let mut r = m.range(2..);
+    assert_eq!(r.next(), Some((&2, &2)));
+    let w = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        for i in 100..200 {
+            m.insert(i, i);
+        }
+    }));
+    assert_eq!(w.is_err(), true);
+    assert_eq!(r.map(|(k, _)| *k).collect::<Vec<_>>(), (3..10).collect::<Vec<_>>());
+
+    let mut r = m.range_mut(..5);
+    let w = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| m.remove(&0)));
+    assert_eq!(w.is_err(), true);
+    for (_, v) in &mut r {
+        *v += 1;
+    }
+    drop(r);
+    assert_eq!(m.len(), 10);
+    assert_eq!(m.get(&0), Some(&1));
+    // the lock was released by the iterators
+    m.insert(10, 10);
+    assert_eq!(m.len(), 11);
+}
+
+#[test]
+pub fn test_navigation() {
+    let m = SyncBtreeMap::<i32, &str>::new();
+    assert_eq!(m.first_key_value(), None);
+    assert_eq!(m.floor(&1), None);
+    m.insert(10, "a");
+    m.insert(20, "b");
+    m.insert(30, "c");
+    assert_eq!(m.first_key_value(), Some((10, "a")));
+    assert_eq!(m.last_key_value(), Some((30, "c")));
+    assert_eq!(m.floor(&20), Some((20, "b")));
+    assert_eq!(m.floor(&25), Some((20, "b")));
+    assert_eq!(m.floor(&5), None);
+    assert_eq!(m.ceiling(&20), Some((20, "b")));
+    assert_eq!(m.ceiling(&25), Some((30, "c")));
+    assert_eq!(m.ceiling(&35), None);
+
+    assert_eq!(m.pop_first(), Some((10, "a")));
+    assert_eq!(m.pop_last(), Some((30, "c")));
+    assert_eq!(m.len(), 1);
+}
+
+#[test]
+pub fn test_split_off_append() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    for i in 0..6 {
+        m.insert(i, i);
+    }
+    let events = m.subscribe(16);
+    let mut tail = m.split_off(&3);
+    assert_eq!(tail.keys().copied().collect::<Vec<_>>(), vec![3, 4, 5]);
+    assert_eq!(m.len(), 3);
+    assert_eq!(events.drain().count(), 3);
+
+    tail.insert(0, 100);
+    m.append(&mut tail);
+    assert_eq!(tail.is_empty(), true);
+    assert_eq!(m.len(), 6);
+    assert_eq!(m.get(&0), Some(&100));
+    assert_eq!(
+        events.try_recv().unwrap(),
+        MapEvent::Inserted { key: 0, old: Some(0), new: 100 }
+    );
+    assert_eq!(events.drain().count(), 3);
+
+    // no subscriber left takes the merge path
+    drop(events);
+    m.insert(9, 9);
+    let mut other = m.split_off(&4);
+    m.append(&mut other);
+    assert_eq!(m.len(), 7);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_range_async() {
+    let m = Arc::new(SyncBtreeMap::<i32, i32>::new());
+    for i in 0..4 {
+        m.insert_async(i, i).await;
+    }
+    let mut r = m.range_mut_async(1..3).await;
+    tokio::task::yield_now().await;
+    for (_, v) in &mut r {
+        *v += 10;
+    }
+    drop(r);
+    assert_eq!(m.range(..).map(|(_, v)| *v).collect::<Vec<_>>(), vec![0, 11, 12, 3]);
+    assert_eq!(m.pop_first_async().await, Some((0, 0)));
+    assert_eq!(m.pop_last_async().await, Some((3, 3)));
+}
+
+/// ordered but not hashable, like a price level keyed by `(Decimal, u64)`
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+struct Price(f64);
+
+impl Eq for Price {}
+
+impl Ord for Price {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[test]
+pub fn test_ord_key() {
+    let m = SyncBtreeMap::<(Price, u64), &str>::new();
+    m.insert((Price(1.5), 2), "b");
+    m.insert((Price(1.5), 1), "a");
+    m.insert((Price(0.5), 7), "c");
+    assert_eq!(m.get(&(Price(1.5), 1)), Some(&"a"));
+    assert_eq!(m.contains_key(&(Price(0.5), 7)), true);
+    assert_eq!(m[&(Price(1.5), 2)], "b");
+    *m.get_mut(&(Price(1.5), 2)).unwrap() = "bb";
+    *m.entry((Price(2.0), 0)).or_insert("d") = "dd";
+    assert_eq!(
+        m.iter().map(|(_, v)| *v).collect::<Vec<_>>(),
+        vec!["c", "a", "bb", "dd"]
+    );
+    assert_eq!(m.floor(&(Price(1.0), 0)).map(|(_, v)| v), Some("c"));
+    assert_eq!(m.remove(&(Price(0.5), 7)), Some("c"));
+    assert_eq!(format!("{:?}", m.clone()), format!("{:?}", m));
+}
+
+#[test]
+pub fn test_borrowed_key() {
+    let m = SyncBtreeMap::<String, i32>::new();
+    m.insert("a".to_string(), 1);
+    m.insert("b".to_string(), 2);
+    assert_eq!(m.get("a"), Some(&1));
+    assert_eq!(m.contains_key("b"), true);
+    assert_eq!(m["b"], 2);
+    *m.get_mut("a").unwrap() += 1;
+    assert_eq!(m.remove("a"), Some(2));
+    let from_b = (std::ops::Bound::Included("b"), std::ops::Bound::Unbounded);
+    assert_eq!(m.range::<str, _>(from_b).count(), 1);
+}
+
+#[test]
+pub fn test_prefix_string() {
+    let m = SyncBtreeMap::<String, i32>::new();
+    for (i, k) in ["t1/eu/a", "t1/eu/b", "t1/us/a", "t2/eu/a", "t1"].iter().enumerate() {
+        m.insert(k.to_string(), i as i32);
+    }
+    let keys: Vec<&str> = m.scan_prefix("t1/").map(|(k, _)| k.as_str()).collect();
+    assert_eq!(keys, vec!["t1/eu/a", "t1/eu/b", "t1/us/a"]);
+    assert_eq!(m.count_prefix("t1/eu/"), 2);
+    assert_eq!(m.count_prefix("t3/"), 0);
+    assert_eq!(m.count_prefix(""), 5);
+
+    let events = m.subscribe(16);
+    assert_eq!(m.remove_prefix("t1/"), 3);
+    assert_eq!(events.drain().count(), 3);
+    assert_eq!(m.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>(), vec!["t1", "t2/eu/a"]);
+}
+
+#[test]
+pub fn test_prefix_bytes() {
+    let m = SyncBtreeMap::<Vec<u8>, i32>::new();
+    m.insert(vec![1, 2, 3], 0);
+    m.insert(vec![1, 2, 255], 1);
+    m.insert(vec![1, 3], 2);
+    m.insert(vec![0, 255], 3);
+    assert_eq!(m.count_prefix(&[1, 2][..]), 2);
+    assert_eq!(m.remove_prefix(&[1][..]), 3);
+    assert_eq!(m.len(), 1);
+
+    let m = SyncBtreeMap::<&[u8], i32>::new();
+    m.insert(b"ab", 0);
+    m.insert(b"abc", 1);
+    m.insert(b"b", 2);
+    let values: Vec<i32> = m.scan_prefix(&b"ab"[..]).map(|(_, v)| *v).collect();
+    assert_eq!(values, vec![0, 1]);
+}
+
+#[test]
+pub fn test_prefix_write_inside() {
+    let m = SyncBtreeMap::<String, i32>::new();
+    for i in 0..10 {
+        m.insert(format!("a/{}", i), i);
+    }
+    let mut scan = m.scan_prefix("a/");
+    assert_eq!(scan.next().map(|(_, v)| *v), Some(0));
+    // the scan borrows the tree, a write on the same thread must not restructure it
+    let w = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        for i in 0..100 {
+            m.insert(format!("a/0/{}", i), i);
+        }
+    }));
+    assert_eq!(w.is_err(), true);
+    let w = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| m.remove_prefix("a/")));
+    assert_eq!(w.is_err(), true);
+    assert_eq!(scan.map(|(_, v)| *v).collect::<Vec<_>>(), (1..10).collect::<Vec<_>>());
+    assert_eq!(m.count_prefix("a/"), 10);
+    assert_eq!(m.remove_prefix("a/"), 10);
+}
+
+#[tokio::test]
+pub async fn test_remove_prefix_async() {
+    let m = SyncBtreeMap::<String, i32>::new();
+    m.insert("a/1".to_string(), 1);
+    m.insert("a/2".to_string(), 2);
+    m.insert("b/1".to_string(), 3);
+    assert_eq!(m.remove_prefix_async("a/").await, 2);
+    assert_eq!(m.len(), 1);
+}
+
+#[test]
+pub fn test_bulk_read_inside() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    m.insert_many((0..10).map(|i| (i, i)));
+    // the predicates may read the map they filter
+    m.retain(|k, _| m.get(&(k + 1)).is_some());
+    assert_eq!(m.len(), 9);
+    let taken = m.extract_if(|k, _| m.contains_key(&(k + 2)));
+    assert_eq!(taken.keys().copied().collect::<Vec<_>>(), (0..7).collect::<Vec<_>>());
+    assert_eq!(m.iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![7, 8]);
+}
+
+#[test]
+pub fn test_snapshot() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    for i in 0..10 {
+        m.insert(i, i);
+    }
+    let s = m.snapshot();
+    assert_eq!(s.ptr_eq(&m.snapshot()), true);
+    m.pop_first();
+    for (_, v) in m.iter_mut() {
+        *v += 1;
+    }
+    assert_eq!(s.keys().copied().collect::<Vec<_>>(), (0..10).collect::<Vec<_>>());
+    assert_eq!(s.get(&9), Some(&9));
+    let s2 = m.snapshot();
+    assert_eq!(s2.ptr_eq(&s), false);
+    assert_eq!(s2.first_key_value(), Some((&1, &2)));
+    assert_eq!(format!("{:?}", s2), format!("{:?}", m));
+}
+
+#[tokio::test]
+pub async fn test_snapshot_send() {
+    let m = SyncBtreeMap::<i32, String>::new();
+    m.insert(1, "a".to_string());
+    let s = m.snapshot();
+    let h = tokio::spawn(async move { s.values().cloned().collect::<Vec<_>>() });
+    m.insert(2, "b".to_string());
+    assert_eq!(h.await.unwrap(), vec!["a".to_string()]);
+}
+
+#[test]
+pub fn test_bulk() {
+    let m = SyncBtreeMap::<i32, i32>::new();
+    m.insert(0, -1);
+    assert_eq!(m.insert_many((0..10).map(|i| (i, i))), 9);
+    m.extend(vec![(10, 10), (11, 11)]);
+    assert_eq!(m.remove_many(&[10, 11, 12]), 2);
+    assert_eq!(m.len(), 10);
+
+    m.retain(|k, v| {
+        *v *= 10;
+        *k < 8
+    });
+    assert_eq!(m.iter().map(|(_, v)| *v).collect::<Vec<_>>(), vec![0, 10, 20, 30, 40, 50, 60, 70]);
+
+    let events = m.subscribe(16);
+    m.retain(|k, _| *k != 7);
+    let odd = m.extract_if(|k, _| k % 2 == 1);
+    assert_eq!(odd.into_iter().collect::<Vec<_>>(), vec![(1, 10), (3, 30), (5, 50)]);
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Removed { key: 7, value: 70 },
+            MapEvent::Removed { key: 1, value: 10 },
+            MapEvent::Removed { key: 3, value: 30 },
+            MapEvent::Removed { key: 5, value: 50 },
+        ]
+    );
+    assert_eq!(m.drain().into_keys().collect::<Vec<_>>(), vec![0, 2, 4, 6]);
+    assert_eq!(events.try_recv().unwrap(), MapEvent::Cleared);
+    assert_eq!(m.is_empty(), true);
+}
+
+#[test]
+pub fn test_save_load() {
+    let path = std::env::temp_dir().join(format!("dark_std_btree_{}.bin", std::process::id()));
+    let m = SyncBtreeMap::new();
+    for i in 0..10 {
+        m.insert((i, i.to_string()), vec![i; 3]);
+    }
+    m.save_to(&path).unwrap();
+    let loaded = SyncBtreeMap::<(i32, String), Vec<i32>>::load_from(&path).unwrap();
+    assert_eq!(loaded.dirty_ref(), m.dirty_ref());
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..10]).unwrap();
+    let e = SyncBtreeMap::<(i32, String), Vec<i32>>::load_from(&path).unwrap_err();
+    assert!(e.to_string().contains("truncated"), "{}", e);
+    std::fs::remove_file(&path).unwrap();
+}
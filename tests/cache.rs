@@ -0,0 +1,162 @@
+use dark_std::sync::{CachePolicy, SyncLruCache};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[test]
+pub fn test_lru() {
+    let evicted = Arc::new(Mutex::new(vec![]));
+    let e = evicted.clone();
+    let c = SyncLruCache::<i32, i32>::new(3).on_evict(move |k, v| {
+        e.lock().unwrap().push((*k, *v));
+    });
+    for i in 0..3 {
+        c.insert(i, i);
+    }
+    c.get(&0);
+    c.insert(3, 3);
+    c.insert(4, 4);
+    assert_eq!(vec![(1, 1), (2, 2)], *evicted.lock().unwrap());
+    assert_eq!(3, c.len());
+    assert_eq!(true, c.contains_key(&0));
+    assert_eq!(0, *c.insert(0, 10).unwrap());
+    assert_eq!(10, *c.remove(&0).unwrap());
+    assert_eq!(2, c.len());
+}
+
+#[test]
+pub fn test_lfu() {
+    let evicted = Arc::new(Mutex::new(vec![]));
+    let e = evicted.clone();
+    let c = SyncLruCache::<i32, i32>::with_policy(2, CachePolicy::Lfu).on_evict(move |k, _| {
+        e.lock().unwrap().push(*k);
+    });
+    c.insert(1, 1);
+    c.insert(2, 2);
+    c.get(&1);
+    c.get(&1);
+    c.get(&2);
+    c.insert(3, 3);
+    // the least used of the entries already cached makes room
+    assert_eq!(true, c.peek(&3).is_some());
+    assert_eq!(true, c.peek(&2).is_none());
+    c.get(&3);
+    c.get(&3);
+    c.get(&3);
+    c.insert(4, 4);
+    assert_eq!(true, c.peek(&1).is_none());
+    assert_eq!(true, c.peek(&3).is_some());
+    assert_eq!(true, c.peek(&4).is_some());
+    for i in 5..10 {
+        c.insert(i, i);
+        assert_eq!(true, c.peek(&i).is_some());
+    }
+    assert_eq!(10, *c.get_or_insert_with(10, || 10));
+    assert_eq!(true, c.peek(&10).is_some());
+    assert_eq!(true, c.peek(&3).is_some());
+    assert_eq!(vec![2, 1, 4, 5, 6, 7, 8, 9], *evicted.lock().unwrap());
+}
+
+#[test]
+pub fn test_tiny_lfu() {
+    let c = SyncLruCache::<i32, i32>::with_policy(100, CachePolicy::TinyLfu);
+    for _ in 0..5 {
+        for i in 0..50 {
+            c.get_or_insert_with(i, || i);
+        }
+    }
+    // a scan of keys seen once must not flush the frequent ones
+    for i in 1000..2000 {
+        c.get_or_insert_with(i, || i);
+    }
+    assert_eq!(true, c.len() <= 100);
+    // the sketch is approximate, a plain LRU would keep none of them
+    let kept = (0..50).filter(|i| c.peek(i).is_some()).count();
+    assert_eq!(true, kept >= 45);
+}
+
+#[test]
+pub fn test_tiny_lfu_update() {
+    let c = SyncLruCache::<i32, i32>::with_policy(10, CachePolicy::TinyLfu);
+    for i in 0..10 {
+        c.insert(i, i);
+    }
+    for i in 1..10 {
+        for _ in 0..6 {
+            c.get(&i);
+        }
+    }
+    for _ in 0..3 {
+        c.get(&0);
+    }
+    // a rewritten key of the main space stays there instead of competing from the window again
+    c.insert(0, 100);
+    for i in 1000..1020 {
+        c.insert(i, i);
+    }
+    assert_eq!(100, *c.peek(&0).unwrap());
+    assert_eq!(true, c.len() <= 10);
+}
+
+#[test]
+pub fn test_get_or_insert_with() {
+    let c = Arc::new(SyncLruCache::<i32, i32>::new(10));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let mut handles = vec![];
+    for _ in 0..8 {
+        let c = c.clone();
+        let calls = calls.clone();
+        handles.push(std::thread::spawn(move || {
+            let v = c.get_or_insert_with(1, || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                1
+            });
+            assert_eq!(1, *v);
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(1, calls.load(Ordering::SeqCst));
+    assert_eq!(8, c.hits() + c.misses());
+}
+
+#[test]
+pub fn test_concurrent() {
+    let c = Arc::new(SyncLruCache::<i32, i32>::with_policy(64, CachePolicy::TinyLfu));
+    let mut handles = vec![];
+    for t in 0..4 {
+        let c = c.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..10000 {
+                let k = (i * (t + 1)) % 500;
+                if c.get(&k).is_none() {
+                    c.insert(k, k);
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(true, c.len() <= 64);
+    for (k, v) in c.iter() {
+        assert_eq!(*k, *v);
+    }
+    c.clear();
+    assert_eq!(true, c.is_empty());
+}
+
+#[test]
+pub fn test_serde() {
+    let c = SyncLruCache::<i32, String>::new(8);
+    for i in 0..10 {
+        c.insert(i, i.to_string());
+    }
+    // bincode needs the exact length of the map up front
+    let bytes = bincode::serialize(&c).unwrap();
+    let c2: SyncLruCache<i32, String> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(8, c2.len());
+    for i in 2..10 {
+        assert_eq!(i.to_string(), *c2.get(&i).unwrap());
+    }
+}
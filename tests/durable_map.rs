@@ -0,0 +1,204 @@
+use dark_std::sync::{FsyncPolicy, SyncDurableHashMap};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dark_std_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+pub fn test_replay() {
+    let dir = temp_dir("durable_replay");
+    let m = SyncDurableHashMap::<i32, String>::open(&dir).unwrap();
+    for i in 0..10 {
+        assert!(m.insert(i, i.to_string()).unwrap().is_none());
+    }
+    assert_eq!("1", *m.insert(1, "one".to_string()).unwrap().unwrap());
+    assert_eq!("2", *m.remove(&2).unwrap().unwrap());
+    assert!(m.remove(&2).unwrap().is_none());
+    assert_eq!(12, m.log_len());
+    drop(m);
+
+    let m = SyncDurableHashMap::<i32, String>::open(&dir).unwrap();
+    assert_eq!(9, m.len());
+    assert_eq!("one", *m.get(&1).unwrap());
+    assert!(m.get(&2).is_none());
+    assert_eq!(12, m.log_len());
+    m.clear().unwrap();
+    m.insert(100, "a".to_string()).unwrap();
+    drop(m);
+
+    let m = SyncDurableHashMap::<i32, String>::open(&dir).unwrap();
+    assert_eq!(1, m.len());
+    assert_eq!("a", *m.get(&100).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_compact() {
+    let dir = temp_dir("durable_compact");
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    for i in 0..100 {
+        m.insert(i % 10, i).unwrap();
+    }
+    assert_eq!(100, m.log_len());
+    let before = m.log_bytes();
+    m.compact().unwrap();
+    assert_eq!(0, m.log_len());
+    assert_eq!(0, m.log_bytes());
+    assert!(before > 0);
+    m.insert(0, -1).unwrap();
+    drop(m);
+
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(10, m.len());
+    assert_eq!(-1, *m.get(&0).unwrap());
+    assert_eq!(99, *m.get(&9).unwrap());
+    assert_eq!(1, m.log_len());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_torn_tail() {
+    let dir = temp_dir("durable_torn");
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    m.insert(1, 1).unwrap();
+    m.insert(2, 2).unwrap();
+    let len = m.log_bytes();
+    drop(m);
+
+    // a crash in the middle of the third record
+    let log = dir.join("wal.log");
+    let mut f = OpenOptions::new().append(true).open(&log).unwrap();
+    f.write_all(&[9, 0, 0, 0, 1, 2]).unwrap();
+    drop(f);
+
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(2, m.len());
+    assert_eq!(len, m.log_bytes());
+    assert_eq!(len, std::fs::metadata(&log).unwrap().len());
+    m.insert(3, 3).unwrap();
+    drop(m);
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(3, m.len());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_corrupted() {
+    let dir = temp_dir("durable_corrupted");
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    m.insert(1, 1).unwrap();
+    m.insert(2, 2).unwrap();
+    drop(m);
+
+    let log = dir.join("wal.log");
+    let mut bytes = std::fs::read(&log).unwrap();
+    bytes[8] ^= 0xff;
+    std::fs::write(&log, &bytes).unwrap();
+    let e = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap_err();
+    assert!(e.to_string().contains("corrupted record at offset 0"), "{}", e);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_corrupted_len() {
+    let dir = temp_dir("durable_corrupted_len");
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    m.insert(1, 1).unwrap();
+    m.insert(2, 2).unwrap();
+    m.insert(3, 3).unwrap();
+    let len = m.log_bytes() as usize;
+    drop(m);
+
+    // the length of the second record now runs past the end of the log,
+    // like a torn record: the log ends before it
+    let log = dir.join("wal.log");
+    let mut bytes = std::fs::read(&log).unwrap();
+    let second = len / 3;
+    bytes[second + 1] = 0xff;
+    std::fs::write(&log, &bytes).unwrap();
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(1, m.len());
+    assert_eq!(1, m.log_len());
+    assert_eq!(second as u64, std::fs::metadata(&log).unwrap().len());
+    drop(m);
+
+    // so does a zeroed tail
+    let mut f = OpenOptions::new().append(true).open(&log).unwrap();
+    f.write_all(&[0; 64]).unwrap();
+    drop(f);
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(1, m.len());
+    assert_eq!(second as u64, std::fs::metadata(&log).unwrap().len());
+    drop(m);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_open_locked() {
+    let dir = temp_dir("durable_locked");
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    m.insert(1, 1).unwrap();
+    let e = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap_err();
+    assert!(e.to_string().contains("already opened"), "{}", e);
+    drop(m);
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(1, *m.get(&1).unwrap());
+    drop(m);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn test_policy() {
+    for policy in [
+        FsyncPolicy::Always,
+        FsyncPolicy::Batch(16),
+        FsyncPolicy::Interval(Duration::from_millis(10)),
+    ] {
+        let dir = temp_dir("durable_policy");
+        let m = Arc::new(SyncDurableHashMap::<i32, i32>::open_with(&dir, policy).unwrap());
+        let mut handles = vec![];
+        for t in 0..4 {
+            let m = m.clone();
+            handles.push(std::thread::spawn(move || {
+                for i in 0..50 {
+                    m.insert(t * 100 + i, i).unwrap();
+                }
+            }));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+        m.sync().unwrap();
+        assert_eq!(200, m.log_len());
+        drop(m);
+        let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+        assert_eq!(200, m.len());
+        assert_eq!(49, *m.get(&349).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+pub async fn test_spawn_sync() {
+    let dir = temp_dir("durable_spawn_sync");
+    let m = Arc::new(
+        SyncDurableHashMap::<i32, i32>::open_with(&dir, FsyncPolicy::Interval(Duration::from_secs(60)))
+            .unwrap(),
+    );
+    let task = m.spawn_sync(Duration::from_millis(5));
+    m.insert(1, 1).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    drop(m);
+    task.await.unwrap();
+    let m = SyncDurableHashMap::<i32, i32>::open(&dir).unwrap();
+    assert_eq!(1, *m.get(&1).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
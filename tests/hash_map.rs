@@ -1,4 +1,4 @@
-use dark_std::sync::SyncHashMap;
+use dark_std::sync::{HashMapEntry, MapEvent, SyncHashMap};
 
 use std::sync::Arc;
 use std::thread::sleep;
@@ -32,9 +32,9 @@ pub fn test_insert2() {
     m.insert("/js".to_string(), "2".to_string());
     m.insert("/fn".to_string(), "3".to_string());
 
-    assert_eq!(&"1".to_string(), m.get("/").unwrap());
-    assert_eq!(&"2".to_string(), m.get("/js").unwrap());
-    assert_eq!(&"3".to_string(), m.get("/fn").unwrap());
+    assert_eq!(&"1".to_string(), &*m.get("/").unwrap());
+    assert_eq!(&"2".to_string(), &*m.get("/js").unwrap());
+    assert_eq!(&"3".to_string(), &*m.get("/fn").unwrap());
 }
 
 // #[test]
@@ -92,7 +92,7 @@ pub fn test_get() {
     let m = SyncHashMap::<i32, i32>::new();
     m.insert(1, 2);
     let g = m.get(&1).unwrap();
-    assert_eq!(&2, g);
+    assert_eq!(&2, &*g);
 }
 
 #[test]
@@ -101,8 +101,11 @@ pub fn test_get_mut() {
     m.insert(1, 2);
     let mut r = m.get_mut(&1).unwrap();
     *r = 0;
+    // readers see the new value once the guard is dropped
+    assert_eq!(&2, &*m.get(&1).unwrap());
+    drop(r);
     let g = m.get(&1).unwrap();
-    assert_eq!(&0, g);
+    assert_eq!(&0, &*g);
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -117,6 +120,7 @@ impl Drop for A {
 }
 
 #[test]
+#[allow(deprecated)]
 pub fn test_remove() {
     let a = A { inner: 0 };
     let m = SyncHashMap::<i32, A>::new();
@@ -126,9 +130,10 @@ pub fn test_remove() {
     println!("rm:{:?}", rm);
     drop(rm);
     assert_eq!(true, m.is_empty());
+    assert_eq!(true, m.iter().next().is_none());
     assert_eq!(true, m.dirty_ref().is_empty());
-    assert_eq!(None, m.get(&1));
-    assert_eq!(&A { inner: 0 }, g);
+    assert_eq!(true, m.get(&1).is_none());
+    assert_eq!(&A { inner: 0 }, &*g);
 }
 
 #[test]
@@ -154,6 +159,99 @@ pub fn test_remove2() {
     sleep(Duration::from_secs(5));
 }
 
+#[test]
+pub fn test_promote() {
+    let m = SyncHashMap::<i32, i32>::new();
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    // every miss on the read map counts, until dirty is promoted
+    for _ in 0..2 {
+        for i in 0..100 {
+            assert_eq!(&i, &*m.get(&i).unwrap());
+        }
+    }
+    m.remove(&1);
+    m.insert(1, 2);
+    m.insert(100, 100);
+    assert_eq!(&2, &*m.get(&1).unwrap());
+    assert_eq!(&100, &*m.get(&100).unwrap());
+    assert_eq!(101, m.len());
+    assert_eq!(101, m.iter().count());
+}
+
+#[test]
+pub fn test_read_while_write() {
+    let m = Arc::new(SyncHashMap::<i32, String>::new());
+    let mut handles = vec![];
+    for t in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..10000 {
+                if t % 2 == 0 {
+                    m.insert(i % 512, i.to_string());
+                    if i % 3 == 0 {
+                        m.remove(&(i % 512));
+                    }
+                } else if let Some(v) = m.get(&(i % 512)) {
+                    assert!(!v.is_empty());
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(m.len(), m.iter().count());
+}
+
+#[test]
+pub fn test_get_after_remove() {
+    let m = SyncHashMap::<i32, String>::new();
+    m.insert(1, "a".repeat(64));
+    let g = m.get(&1).unwrap();
+    let old = m.insert(1, "b".repeat(64)).unwrap();
+    assert_eq!("a".repeat(64), *old);
+    drop(old);
+    m.remove(&1);
+    m.clear();
+    m.insert(2, "c".repeat(64));
+    assert_eq!("a".repeat(64), *g);
+}
+
+#[test]
+pub fn test_pin() {
+    let m = Arc::new(SyncHashMap::<i32, String>::new());
+    for i in 0..100 {
+        m.insert(i, i.to_string());
+    }
+    let guard = m.pin();
+    let values: Vec<&String> = (0..100).map(|i| &guard[&i]).collect();
+    let m2 = m.clone();
+    std::thread::spawn(move || {
+        for i in 0..100 {
+            m2.remove(&i);
+        }
+        m2.clear();
+    })
+    .join()
+    .unwrap();
+    assert_eq!(None, guard.get(&1));
+    for (i, v) in values.into_iter().enumerate() {
+        assert_eq!(&i.to_string(), v);
+    }
+    assert_eq!(0, guard.iter().count());
+}
+
+#[test]
+pub fn test_mut() {
+    let mut m = SyncHashMap::<i32, String>::new();
+    assert_eq!(None, m.insert_mut(1, "a".to_string()));
+    assert_eq!(Some("a".to_string()), m.insert_mut(1, "b".to_string()));
+    assert_eq!(Some("b".to_string()), m.remove_mut(&1));
+    assert_eq!(true, m.is_empty());
+}
+
 #[test]
 pub fn test_iter() {
     let m = SyncHashMap::<i32, i32>::new();
@@ -174,6 +272,60 @@ pub fn test_iter_mut() {
     }
 }
 
+#[test]
+pub fn test_entry() {
+    let m = SyncHashMap::<i32, i32>::new();
+    *m.entry(1).or_insert(0) += 1;
+    *m.entry(1).or_insert(0) += 1;
+    m.entry(2).and_modify(|v| *v += 1).or_insert(5);
+    m.entry(2).and_modify(|v| *v += 1).or_insert(5);
+    assert_eq!(2, *m.get(&1).unwrap());
+    assert_eq!(6, *m.get(&2).unwrap());
+    assert_eq!(0, *m.entry(3).or_default());
+}
+
+#[test]
+pub fn test_entry_match() {
+    let m = SyncHashMap::<i32, i32>::new();
+    match m.entry(1) {
+        HashMapEntry::Occupied(_) => panic!("must be vacant"),
+        HashMapEntry::Vacant(e) => {
+            assert_eq!(&1, e.key());
+            e.insert(1);
+        }
+    }
+    match m.entry(1) {
+        HashMapEntry::Occupied(mut e) => {
+            assert_eq!(1, e.insert(2));
+            assert_eq!(&2, e.get());
+            assert_eq!((1, 2), e.remove_entry());
+        }
+        HashMapEntry::Vacant(_) => panic!("must be occupied"),
+    }
+    assert_eq!(true, m.get(&1).is_none());
+    assert_eq!(0, m.len());
+}
+
+#[test]
+pub fn test_entry_concurrent() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    let mut handles = vec![];
+    for _ in 0..8 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                *m.entry(i % 10).or_insert(0) += 1;
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    for i in 0..10 {
+        assert_eq!(800, *m.get(&i).unwrap());
+    }
+}
+
 // #[test]
 // pub fn test_smoke2() {
 //     let wait1 = WaitGroup::new();
@@ -224,3 +376,718 @@ pub fn test_iter_mut() {
 //     }
 //     wait1.wait();
 // }
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_async() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    m.insert_async(0, 0).await;
+    let mut tasks = vec![];
+    for _ in 0..8 {
+        let m = m.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..100 {
+                let mut v = m.get_mut_async(&0).await.unwrap();
+                tokio::task::yield_now().await;
+                *v += 1;
+            }
+        }));
+    }
+    let blocking = {
+        let m = m.clone();
+        std::thread::spawn(move || {
+            for _ in 0..100 {
+                *m.get_mut(&0).unwrap() += 1;
+            }
+        })
+    };
+    for t in tasks {
+        t.await.unwrap();
+    }
+    blocking.join().unwrap();
+    assert_eq!(900, *m.get(&0).unwrap());
+    *m.entry_async(1).await.or_insert(0) += 1;
+    for (_, mut v) in m.iter_mut_async().await {
+        *v += 1;
+    }
+    assert_eq!(901, *m.get(&0).unwrap());
+    assert_eq!(2, *m.get(&1).unwrap());
+    assert_eq!(2, *m.remove_async(&1).await.unwrap());
+    m.clear_async().await;
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_read_under_async_guard() {
+    // a hang here never returns to the runtime, so watch it from another thread
+    let (send, recv) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let m = Arc::new(SyncHashMap::<i32, i32>::new());
+            m.insert(1, 1);
+            m.insert(2, 2);
+            let mut g = m.get_mut_async(&1).await.unwrap();
+            // 2 is only in dirty, the task holding the lock reads it
+            assert_eq!(2, *m.get(&2).unwrap());
+            assert_eq!(2, m.iter().count());
+            assert_eq!(2, m.pin().iter().count());
+            // so does another task while the guard is held across `.await`
+            let m2 = m.clone();
+            let v = tokio::spawn(async move {
+                let v = *m2.get(&2).unwrap();
+                assert_eq!(2, m2.pin().iter().count());
+                v
+            })
+            .await
+            .unwrap();
+            assert_eq!(2, v);
+            *g = 10;
+            drop(g);
+            assert_eq!(10, *m.get(&1).unwrap());
+        });
+        send.send(()).unwrap();
+    });
+    recv.recv_timeout(Duration::from_secs(10)).unwrap();
+}
+
+#[test]
+pub fn test_subscribe() {
+    let m = SyncHashMap::<i32, i32>::new();
+    let events = m.subscribe(16);
+    m.insert(1, 1);
+    m.insert(1, 2);
+    *m.entry(2).or_insert(0) += 1;
+    m.remove(&1);
+    m.clear();
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Inserted { key: 1, old: None, new: 1 },
+            MapEvent::Inserted { key: 1, old: Some(1), new: 2 },
+            MapEvent::Inserted { key: 2, old: None, new: 0 },
+            MapEvent::Removed { key: 1, value: 2 },
+            MapEvent::Cleared,
+        ]
+    );
+    drop(events);
+    m.insert(3, 3);
+}
+
+#[test]
+pub fn test_subscribe_entry_insert() {
+    let m = SyncHashMap::<i32, i32>::new();
+    m.insert(1, 1);
+    let events = m.subscribe(16);
+    match m.entry(1) {
+        HashMapEntry::Occupied(mut e) => {
+            assert_eq!(1, e.insert(2));
+            // the event is only sent once readers see the new value
+            assert_eq!(
+                MapEvent::Inserted { key: 1, old: Some(1), new: 2 },
+                events.try_recv().unwrap()
+            );
+            assert_eq!(2, *m.get(&1).unwrap());
+            *e.get_mut() += 1;
+        }
+        HashMapEntry::Vacant(_) => panic!("must be occupied"),
+    }
+    assert_eq!(3, *m.get(&1).unwrap());
+    assert_eq!(true, events.try_recv().is_err());
+}
+
+#[test]
+pub fn test_subscribe_lagged() {
+    let m = SyncHashMap::<i32, i32>::new();
+    let events = m.subscribe(2);
+    for i in 0..5 {
+        m.insert(i, i);
+    }
+    assert_eq!(2, events.drain().count());
+    m.insert(5, 5);
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Lagged(3),
+            MapEvent::Inserted { key: 5, old: None, new: 5 },
+        ]
+    );
+}
+
+#[test]
+pub fn test_subscribe_order() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    m.insert(0, 0);
+    let events = m.subscribe(100000);
+    let mut handles = vec![];
+    for t in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                m.insert(0, t * 1000 + i);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    // every event replaces the value of the one before
+    let mut last = 0;
+    for e in events.drain() {
+        match e {
+            MapEvent::Inserted { old, new, .. } => {
+                assert_eq!(Some(last), old);
+                last = new;
+            }
+            e => panic!("unexpected {:?}", e),
+        }
+    }
+    assert_eq!(last, *m.get(&0).unwrap());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_wait_for() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    m.insert(1, 1);
+    assert_eq!(*m.wait_for(&1).await, 1);
+
+    let m2 = m.clone();
+    let h = tokio::spawn(async move { *m2.wait_for(&2).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    m.insert_async(2, 2).await;
+    assert_eq!(h.await.unwrap(), 2);
+    assert_eq!(m.contains_key(&2), true);
+}
+
+#[test]
+pub fn test_wait_for_timeout() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    assert_eq!(m.wait_for_timeout(&1, Duration::from_millis(20)).is_none(), true);
+
+    let m2 = m.clone();
+    let h = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        m2.insert(1, 1);
+    });
+    assert_eq!(*m.wait_for_timeout(&1, Duration::from_secs(10)).unwrap(), 1);
+    h.join().unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_wait_take() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    let mut handles = vec![];
+    for _ in 0..4 {
+        let m = m.clone();
+        handles.push(tokio::spawn(async move { *m.wait_take(&1).await }));
+    }
+    for i in 0..4 {
+        m.insert_async(1, i).await;
+        // one value at a time, an insert over a value not taken yet would replace it
+        while m.contains_key(&1) {
+            tokio::task::yield_now().await;
+        }
+    }
+    let mut taken = vec![];
+    for h in handles {
+        taken.push(h.await.unwrap());
+    }
+    taken.sort();
+    assert_eq!(taken, vec![0, 1, 2, 3]);
+    assert_eq!(m.is_empty(), true);
+    assert_eq!(m.wait_take_timeout(&1, Duration::from_millis(10)).is_none(), true);
+}
+
+#[test]
+pub fn test_snapshot() {
+    let mut m = SyncHashMap::<i32, i32>::new();
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    let s = m.snapshot();
+    assert_eq!(s.ptr_eq(&m.snapshot()), true);
+    m.insert(100, 100);
+    m.remove(&0);
+    *m.get_mut(&1).unwrap() = -1;
+    assert_eq!(s.len(), 100);
+    assert_eq!(s.get(&0), Some(&0));
+    assert_eq!(s.get(&1), Some(&1));
+
+    let s2 = m.snapshot();
+    assert_eq!(s2.ptr_eq(&s), false);
+    assert_eq!(s2.len(), 100);
+    assert_eq!(s2.get(&1), Some(&-1));
+    m.insert_mut(101, 101);
+    assert_eq!(m.snapshot().len(), 101);
+
+    fn is_serialize<T: serde::Serialize + Send + Sync>(_: &T) {}
+    is_serialize(&s2);
+    let copy = m.snapshot().into_inner();
+    assert_eq!(copy.len(), 101);
+}
+
+#[test]
+pub fn test_snapshot_not_kept() {
+    let value = Arc::new(0);
+    let m = SyncHashMap::<i32, Arc<i32>>::new();
+    m.insert(1, value.clone());
+    let s = m.snapshot();
+    assert_eq!(Arc::strong_count(&value), 3);
+    assert_eq!(s.ptr_eq(&m.snapshot()), true);
+    // the map holds no copy once the callers dropped theirs
+    drop(s);
+    assert_eq!(Arc::strong_count(&value), 2);
+    assert_eq!(m.snapshot().len(), 1);
+    assert_eq!(Arc::strong_count(&value), 2);
+}
+
+#[test]
+pub fn test_snapshot_inside_guard() {
+    let m = SyncHashMap::<i32, i32>::new();
+    m.insert(1, 1);
+    let mut g = m.get_mut(&1).unwrap();
+    // the lock is not reentrant, the guard may be writing
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| m.snapshot()));
+    assert_eq!(r.is_err(), true);
+    *g = 100;
+    drop(g);
+    assert_eq!(100, *m.get(&1).unwrap());
+    assert_eq!(m.snapshot().get(&1), Some(&100));
+}
+
+#[test]
+pub fn test_snapshot_concurrent() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    m.insert(0, 0);
+    m.insert(1, 0);
+    let writer = {
+        let m = m.clone();
+        std::thread::spawn(move || {
+            for i in 1..2000 {
+                // both keys always move together
+                let _ = m.transaction(|tx| {
+                    tx.insert(0, i);
+                    tx.insert(1, i);
+                    Ok(())
+                });
+            }
+        })
+    };
+    for _ in 0..200 {
+        let s = m.snapshot();
+        assert_eq!(s[&0], s[&1]);
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+pub fn test_transaction() {
+    let m = SyncHashMap::<i32, i32>::new();
+    m.insert(1, 10);
+    m.insert(2, 0);
+    let r = m.transaction(|tx| {
+        tx.insert(1, 5);
+        assert_eq!(tx.get(&1), Some(&5));
+        assert_eq!(tx.remove(&2), true);
+        assert_eq!(tx.contains_key(&2), false);
+        tx.insert(3, 3);
+        // nothing is applied before the commit
+        assert_eq!(*m.get(&1).unwrap(), 10);
+        Ok(1)
+    });
+    assert_eq!(r, Ok(1));
+    assert_eq!(*m.get(&1).unwrap(), 5);
+    assert_eq!(m.get(&2).is_none(), true);
+    assert_eq!(*m.get(&3).unwrap(), 3);
+    assert_eq!(m.len(), 2);
+
+    let r: dark_std::errors::Result<()> = m.transaction(|tx| {
+        tx.insert(1, 0);
+        tx.abort()
+    });
+    assert_eq!(r.is_err(), true);
+    let r: dark_std::errors::Result<()> = m.transaction(|tx| {
+        tx.remove(&1);
+        Err("insufficient".into())
+    });
+    assert_eq!(r.unwrap_err().to_string(), "insufficient");
+    assert_eq!(*m.get(&1).unwrap(), 5);
+
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        m.transaction(|tx| -> dark_std::errors::Result<()> {
+            tx.insert(1, 0);
+            panic!("rolled back");
+        })
+    }));
+    assert_eq!(r.is_err(), true);
+    assert_eq!(*m.get(&1).unwrap(), 5);
+    // the lock was released by the panic
+    m.insert(4, 4);
+}
+
+#[test]
+pub fn test_transaction_concurrent() {
+    let m = Arc::new(SyncHashMap::<i32, i64>::new());
+    for i in 0..10 {
+        m.insert(i, 100);
+    }
+    let mut handles = vec![];
+    for t in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..500 {
+                let (from, to) = ((t + i) % 10, (t * 3 + i * 7 + 1) % 10);
+                let _ = m.transaction(|tx| {
+                    let a = *tx.get(&from).unwrap();
+                    if a < 7 || from == to {
+                        return tx.abort();
+                    }
+                    let b = *tx.get(&to).unwrap();
+                    tx.insert(from, a - 7);
+                    tx.insert(to, b + 7);
+                    Ok(())
+                });
+            }
+        }));
+    }
+    for _ in 0..100 {
+        assert_eq!(m.snapshot().values().sum::<i64>(), 1000);
+    }
+    // a lock-free reader sees every transaction applied whole or not at all
+    while !handles.iter().all(|h| h.is_finished()) {
+        assert_eq!(m.pin().iter().map(|(_, v)| *v).sum::<i64>(), 1000);
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(m.iter().map(|(_, v)| *v).sum::<i64>(), 1000);
+}
+
+#[test]
+pub fn test_transaction_moved() {
+    let m = SyncHashMap::<i32, i32>::new();
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    let pinned = m.pin();
+    let before = pinned.iter();
+    m.transaction(|tx| {
+        tx.insert(1, -1);
+        tx.remove(&2);
+        tx.insert(200, 200);
+        Ok(())
+    })
+    .unwrap();
+    // an iteration begun before the commit sees none of it
+    assert_eq!(before.map(|(_, v)| *v).sum::<i32>(), 4950);
+    assert_eq!(*m.get(&1).unwrap(), -1);
+    assert_eq!(m.get(&2).is_none(), true);
+    assert_eq!(*m.get(&200).unwrap(), 200);
+    assert_eq!(m.len(), 100);
+
+    // the keys written by the transaction stay writable
+    m.insert(1, 10);
+    *m.get_mut(&1).unwrap() += 1;
+    m.insert(2, 2);
+    assert_eq!(*m.remove(&200).unwrap(), 200);
+    assert_eq!(*m.get(&1).unwrap(), 11);
+    assert_eq!(m.len(), 100);
+    assert_eq!(m.iter().map(|(_, v)| *v).sum::<i32>(), 4960);
+    drop(pinned);
+}
+
+#[tokio::test]
+pub async fn test_transaction_async() {
+    let m = SyncHashMap::<i32, i32>::new();
+    let r = m
+        .transaction_async(|tx| {
+            tx.insert(1, 1);
+            Ok(())
+        })
+        .await;
+    assert_eq!(r, Ok(()));
+    assert_eq!(*m.get(&1).unwrap(), 1);
+}
+
+#[test]
+pub fn test_conditional_write_inside() {
+    let m = SyncHashMap::<String, i32>::new();
+    m.insert("a".to_string(), 1);
+    // the closures run under the writer lock, a write to the map from them panics
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        m.update("a", |v| {
+            for i in 0..100 {
+                m.insert(i.to_string(), i);
+            }
+            v + 1
+        })
+    }));
+    assert_eq!(r.is_err(), true);
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        m.replace_if("a", |_| m.remove(&"a".to_string()).is_some(), 3)
+    }));
+    assert_eq!(r.is_err(), true);
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        m.remove_if("a", |_| m.insert("b".to_string(), 2).is_none())
+    }));
+    assert_eq!(r.is_err(), true);
+    assert_eq!(m.len(), 1);
+    assert_eq!(*m.update("a", |v| v + 1).unwrap(), 1);
+    assert_eq!(*m.get("a").unwrap(), 2);
+}
+
+#[test]
+pub fn test_conditional() {
+    let m = SyncHashMap::<i32, i32>::new();
+    assert_eq!(m.insert_if_absent(1, 1).is_none(), true);
+    assert_eq!(*m.insert_if_absent(1, 2).unwrap(), 1);
+    assert_eq!(*m.get(&1).unwrap(), 1);
+
+    assert_eq!(*m.compare_and_swap(&1, &1, 2).unwrap(), 1);
+    assert_eq!(m.compare_and_swap(&1, &1, 3).unwrap_err(), 3);
+    assert_eq!(m.compare_and_swap(&9, &1, 3).unwrap_err(), 3);
+
+    assert_eq!(m.replace_if(&1, |v| *v > 5, 6).unwrap_err(), 6);
+    assert_eq!(*m.replace_if(&1, |v| *v == 2, 6).unwrap(), 2);
+
+    assert_eq!(*m.update(&1, |v| v * 10).unwrap(), 6);
+    assert_eq!(m.update(&9, |v| v * 10).is_none(), true);
+    assert_eq!(*m.get(&1).unwrap(), 60);
+
+    assert_eq!(m.remove_if(&1, |v| *v < 0).is_none(), true);
+    assert_eq!(*m.remove_if(&1, |v| *v == 60).unwrap(), 60);
+    assert_eq!(m.is_empty(), true);
+}
+
+#[test]
+pub fn test_conditional_borrowed_key() {
+    let m = SyncHashMap::<String, i32>::new();
+    let events = m.subscribe(16);
+    m.insert("a".to_string(), 1);
+    assert_eq!(*m.compare_and_swap("a", &1, 2).unwrap(), 1);
+    assert_eq!(*m.replace_if("a", |v| *v == 2, 3).unwrap(), 2);
+    assert_eq!(*m.update("a", |v| v + 1).unwrap(), 3);
+    assert_eq!(m.update("b", |v| v + 1).is_none(), true);
+    assert_eq!(*m.remove_if("a", |v| *v == 4).unwrap(), 4);
+    assert_eq!(m.is_empty(), true);
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Inserted { key: "a".to_string(), old: None, new: 1 },
+            MapEvent::Inserted { key: "a".to_string(), old: Some(1), new: 2 },
+            MapEvent::Inserted { key: "a".to_string(), old: Some(2), new: 3 },
+            MapEvent::Inserted { key: "a".to_string(), old: Some(3), new: 4 },
+            MapEvent::Removed { key: "a".to_string(), value: 4 },
+        ]
+    );
+}
+
+#[test]
+pub fn test_conditional_events() {
+    let m = SyncHashMap::<i32, i32>::new();
+    let events = m.subscribe(16);
+    m.insert_if_absent(1, 1);
+    m.insert_if_absent(1, 2);
+    m.compare_and_swap(&1, &1, 2).unwrap();
+    m.update(&1, |v| v + 1);
+    assert_eq!(
+        events.drain().collect::<Vec<_>>(),
+        vec![
+            MapEvent::Inserted { key: 1, old: None, new: 1 },
+            MapEvent::Inserted { key: 1, old: Some(1), new: 2 },
+            MapEvent::Inserted { key: 1, old: Some(2), new: 3 },
+        ]
+    );
+}
+
+#[test]
+pub fn test_compare_and_swap_concurrent() {
+    let m = Arc::new(SyncHashMap::<i32, i32>::new());
+    m.insert(0, 0);
+    let mut handles = vec![];
+    for _ in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for _ in 0..1000 {
+                loop {
+                    let v = *m.get(&0).unwrap();
+                    if m.compare_and_swap(&0, &v, v + 1).is_ok() {
+                        break;
+                    }
+                }
+                m.update(&0, |v| v + 1);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(*m.get(&0).unwrap(), 8000);
+}
+
+#[test]
+pub fn test_bulk() {
+    let m = SyncHashMap::<i32, i32>::new();
+    m.insert(0, -1);
+    assert_eq!(m.insert_many((0..10).map(|i| (i, i))), 9);
+    assert_eq!(*m.get(&0).unwrap(), 0);
+    m.extend(vec![(10, 10), (11, 11)]);
+    assert_eq!(m.len(), 12);
+    assert_eq!(m.remove_many(&[10, 11, 12]), 2);
+    assert_eq!(m.len(), 10);
+
+    m.retain(|k, _| *k < 8);
+    assert_eq!(m.len(), 8);
+    let mut odd: Vec<(i32, i32)> = m
+        .extract_if(|_, v| v % 2 == 1)
+        .into_iter()
+        .map(|(k, v)| (k, *v))
+        .collect();
+    odd.sort();
+    assert_eq!(odd, vec![(1, 1), (3, 3), (5, 5), (7, 7)]);
+    assert_eq!(m.len(), 4);
+
+    let events = m.subscribe(16);
+    let mut all: Vec<i32> = m.drain().into_iter().map(|(_, v)| *v).collect();
+    all.sort();
+    assert_eq!(all, vec![0, 2, 4, 6]);
+    assert_eq!(m.is_empty(), true);
+    assert_eq!(m.get(&0).is_none(), true);
+    assert_eq!(events.drain().collect::<Vec<_>>(), vec![MapEvent::Cleared]);
+    m.insert(1, 1);
+    assert_eq!(*m.get(&1).unwrap(), 1);
+}
+
+#[test]
+pub fn test_bulk_concurrent_readers() {
+    let m = Arc::new(SyncHashMap::<i32, String>::new());
+    let reader = {
+        let m = m.clone();
+        std::thread::spawn(move || {
+            for i in 0..20000 {
+                if let Some(v) = m.get(&(i % 100)) {
+                    assert_eq!(v.len(), 3);
+                }
+            }
+        })
+    };
+    for _ in 0..50 {
+        m.insert_many((0..100).map(|i| (i, "abc".to_string())));
+        m.retain(|k, _| k % 2 == 0);
+        let taken = m.extract_if(|k, _| k % 4 == 0);
+        assert_eq!(taken.len(), 25);
+        drop(taken);
+        m.drain();
+    }
+    reader.join().unwrap();
+}
+
+#[test]
+pub fn test_bulk_read_inside() {
+    let m = SyncHashMap::<i32, i32>::new();
+    m.insert_many((0..10).map(|i| (i, i)));
+    // the predicates may read the map they filter
+    m.retain(|k, _| m.get(&(k + 1)).is_some());
+    assert_eq!(m.len(), 9);
+    let taken = m.extract_if(|k, v| m.contains_key(&(k + 2)) && *m.get(k).unwrap() == *v);
+    assert_eq!(taken.len(), 7);
+    assert_eq!(m.len(), 2);
+}
+
+/// counts the hashers built, every map made from it shares the count
+#[derive(Clone, Default)]
+struct CountingState {
+    built: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+impl std::hash::BuildHasher for CountingState {
+    type Hasher = std::collections::hash_map::DefaultHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.built.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Default::default()
+    }
+}
+
+#[test]
+pub fn test_with_hasher() {
+    use serde::Deserialize;
+    use std::collections::HashMap;
+    use std::sync::atomic::Ordering;
+
+    let state = CountingState::default();
+    let m = SyncHashMap::with_capacity_and_hasher(16, state.clone());
+    for i in 0..10 {
+        m.insert(i, i);
+    }
+    assert_eq!(*m.get(&3).unwrap(), 3);
+    assert!(state.built.load(Ordering::Relaxed) > 0);
+
+    let before = state.built.load(Ordering::Relaxed);
+    let c = m.clone();
+    assert_eq!(c.len(), 10);
+    assert!(c.hasher().built.load(Ordering::Relaxed) > before);
+    let s = m.snapshot();
+    assert_eq!(s.get(&9), Some(&9));
+
+    let mut hm = HashMap::with_hasher(state.clone());
+    hm.insert("a", 1);
+    let m = SyncHashMap::from(hm);
+    m.insert("b", 2);
+    let inner: HashMap<&str, i32, CountingState> = m.into_inner();
+    assert_eq!(inner.len(), 2);
+
+    let d = serde::de::value::MapDeserializer::<_, serde::de::value::Error>::new(
+        vec![(1, 10), (2, 20)].into_iter(),
+    );
+    let m = SyncHashMap::<i32, i32, CountingState>::deserialize(d).unwrap();
+    assert_eq!(*m.get(&2).unwrap(), 20);
+    assert!(m.hasher().built.load(Ordering::Relaxed) > 0);
+    let m: SyncHashMap<i32, i32, CountingState> = Default::default();
+    assert!(m.is_empty());
+}
+
+#[test]
+pub fn test_save_load() {
+    let path = std::env::temp_dir().join(format!("dark_std_hash_map_{}.bin", std::process::id()));
+    let m = SyncHashMap::new();
+    for i in 0..100 {
+        m.insert(i, format!("v{}", i));
+    }
+    m.save_to(&path).unwrap();
+    m.insert(100, "later".to_string());
+    let loaded = SyncHashMap::<i32, String>::load_from(&path).unwrap();
+    assert_eq!(loaded.len(), 100);
+    assert_eq!(*loaded.get(&42).unwrap(), "v42");
+    assert!(loaded.get(&100).is_none());
+
+    // saving again replaces the file
+    m.save_to(&path).unwrap();
+    let loaded = SyncHashMap::<i32, String>::load_from(&path).unwrap();
+    assert_eq!(loaded.len(), 101);
+
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::write(&path, &bytes[..bytes.len() - 3]).unwrap();
+    let e = SyncHashMap::<i32, String>::load_from(&path).unwrap_err();
+    assert!(e.to_string().contains("truncated"), "{}", e);
+
+    let mut corrupted = bytes.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 0xff;
+    std::fs::write(&path, &corrupted).unwrap();
+    let e = SyncHashMap::<i32, String>::load_from(&path).unwrap_err();
+    assert!(e.to_string().contains("checksum"), "{}", e);
+
+    std::fs::write(&path, b"{\"1\": 1}").unwrap();
+    let e = SyncHashMap::<i32, String>::load_from(&path).unwrap_err();
+    assert!(e.to_string().contains("not a dark-std snapshot"), "{}", e);
+
+    std::fs::write(&path, &bytes).unwrap();
+    let e = dark_std::sync::SyncVec::<String>::load_from(&path).unwrap_err();
+    assert!(e.to_string().contains("saved from a SyncHashMap"), "{}", e);
+
+    std::fs::remove_file(&path).unwrap();
+    assert!(SyncHashMap::<i32, String>::load_from(&path).is_err());
+}
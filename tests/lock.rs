@@ -0,0 +1,81 @@
+use dark_std::sync::WriteLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[test]
+pub fn test_not_reentrant() {
+    let l = WriteLock::new();
+    let g = l.lock();
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _g = l.lock();
+    }));
+    assert_eq!(true, r.is_err());
+    assert_eq!(true, l.is_locked());
+    drop(g);
+    assert_eq!(false, l.is_locked());
+}
+
+#[tokio::test]
+pub async fn test_try_lock() {
+    let l = WriteLock::new();
+    let g1 = l.try_lock().unwrap();
+    // not reentrant on the same thread either
+    assert_eq!(true, l.try_lock().is_none());
+    drop(g1);
+    let g = l.lock_async().await;
+    assert_eq!(true, l.try_lock().is_none());
+    drop(g);
+    assert_eq!(true, l.try_lock().is_some());
+    assert_eq!(false, l.is_locked());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+pub async fn test_async_excludes_thread() {
+    let l = Arc::new(WriteLock::new());
+    let done = Arc::new(AtomicBool::new(false));
+    let g = l.lock_async().await;
+    let t = {
+        let l = l.clone();
+        let done = done.clone();
+        std::thread::spawn(move || {
+            let _g = l.lock();
+            done.store(true, Ordering::SeqCst);
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(false, done.load(Ordering::SeqCst));
+    drop(g);
+    t.join().unwrap();
+    assert_eq!(true, done.load(Ordering::SeqCst));
+}
+
+#[tokio::test]
+pub async fn test_async_cancel() {
+    let l = WriteLock::new();
+    let g = l.lock_async().await;
+    let r = tokio::time::timeout(Duration::from_millis(10), l.lock_async()).await;
+    assert_eq!(true, r.is_err());
+    drop(g);
+    // the cancelled waiter must not keep its place in the queue
+    let r = tokio::time::timeout(Duration::from_secs(1), l.lock_async()).await;
+    assert_eq!(true, r.is_ok());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_async_guard_is_send() {
+    let l = Arc::new(WriteLock::new());
+    let mut tasks = vec![];
+    for _ in 0..16 {
+        let l = l.clone();
+        tasks.push(tokio::spawn(async move {
+            let g = l.lock_async().await;
+            tokio::task::yield_now().await;
+            drop(g);
+        }));
+    }
+    for t in tasks {
+        t.await.unwrap();
+    }
+    assert_eq!(false, l.is_locked());
+}
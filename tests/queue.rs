@@ -0,0 +1,102 @@
+use dark_std::sync::SyncBoundedQueue;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[test]
+pub fn test_try() {
+    let q = SyncBoundedQueue::new(2);
+    assert_eq!(2, q.capacity());
+    assert_eq!(true, q.is_empty());
+    assert_eq!(Ok(()), q.try_push(1));
+    assert_eq!(Ok(()), q.try_push(2));
+    assert_eq!(true, q.is_full());
+    assert_eq!(Err(3), q.try_push(3));
+    assert_eq!(Err(3), q.push_timeout(3, Duration::from_millis(10)));
+    assert_eq!(Some(1), q.try_pop());
+    assert_eq!(1, q.len());
+    assert_eq!(vec![2], q.drain());
+    assert_eq!(None, q.try_pop());
+    assert_eq!(None, q.pop_timeout(Duration::from_millis(10)));
+}
+
+#[test]
+pub fn test_blocking() {
+    let q = Arc::new(SyncBoundedQueue::new(1));
+    let consumer = {
+        let q = q.clone();
+        std::thread::spawn(move || {
+            let mut got = vec![];
+            for _ in 0..100 {
+                got.push(q.pop());
+            }
+            got
+        })
+    };
+    for i in 0..100 {
+        q.push(i);
+        assert!(q.len() <= 1);
+    }
+    assert_eq!((0..100).collect::<Vec<_>>(), consumer.join().unwrap());
+}
+
+#[test]
+pub fn test_backpressure() {
+    let q = Arc::new(SyncBoundedQueue::new(1));
+    q.push(1);
+    let pushed = Arc::new(AtomicUsize::new(0));
+    let producer = {
+        let q = q.clone();
+        let pushed = pushed.clone();
+        std::thread::spawn(move || {
+            q.push(2);
+            pushed.store(1, Ordering::SeqCst);
+        })
+    };
+    std::thread::sleep(Duration::from_millis(50));
+    // full, the producer waits
+    assert_eq!(0, pushed.load(Ordering::SeqCst));
+    assert_eq!(1, q.pop());
+    producer.join().unwrap();
+    assert_eq!(1, pushed.load(Ordering::SeqCst));
+    assert_eq!(2, q.pop());
+}
+
+#[tokio::test]
+pub async fn test_async() {
+    let q = SyncBoundedQueue::new_arc(4);
+    let start = Instant::now();
+    let consumer = {
+        let q = q.clone();
+        tokio::spawn(async move {
+            let mut sum = 0;
+            for _ in 0..100 {
+                sum += q.pop_async().await;
+            }
+            sum
+        })
+    };
+    let mut producers = vec![];
+    for t in 0..4 {
+        let q = q.clone();
+        producers.push(tokio::spawn(async move {
+            for i in 0..25 {
+                q.push_async(t * 25 + i).await;
+            }
+        }));
+    }
+    for p in producers {
+        p.await.unwrap();
+    }
+    assert_eq!((0..100).sum::<i32>(), consumer.await.unwrap());
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // a thread pushing to an async consumer
+    let consumer = {
+        let q = q.clone();
+        tokio::spawn(async move { q.pop_async().await })
+    };
+    let q2 = q.clone();
+    std::thread::spawn(move || q2.push(7)).join().unwrap();
+    assert_eq!(7, consumer.await.unwrap());
+}
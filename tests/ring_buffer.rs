@@ -0,0 +1,166 @@
+use dark_std::sync::SyncRingBuffer;
+use dark_std::sync_ring;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+pub fn test_overwrite() {
+    let r = SyncRingBuffer::new(3);
+    assert_eq!(true, r.is_empty());
+    assert_eq!(3, r.capacity());
+    r.push(1);
+    r.push(2);
+    assert_eq!(vec![1, 2], r.to_vec());
+    r.extend(3..=5);
+    assert_eq!(3, r.len());
+    assert_eq!(5, r.pushed());
+    assert_eq!(vec![3, 4, 5], r.to_vec());
+    assert_eq!(Some(&5), r.pin().last());
+    assert_eq!(vec![3, 4, 5], r.into_inner());
+
+    let r = SyncRingBuffer::new(0);
+    assert_eq!(1, r.capacity());
+    r.push(1);
+    r.push(2);
+    assert_eq!(vec![2], r.to_vec());
+}
+
+#[test]
+pub fn test_latest() {
+    let r = SyncRingBuffer::new(4);
+    assert_eq!(Vec::<i32>::new(), r.latest(2));
+    r.extend(0..10);
+    assert_eq!(vec![8, 9], r.latest(2));
+    assert_eq!(vec![6, 7, 8, 9], r.latest(10));
+    assert_eq!(Vec::<i32>::new(), r.latest(0));
+}
+
+#[test]
+pub fn test_guard_outlives_overwrite() {
+    let r = SyncRingBuffer::new(2);
+    r.push("a".to_string());
+    r.push("b".to_string());
+    let guard = r.pin();
+    let a = guard.iter().next().unwrap();
+    r.push("c".to_string());
+    r.push("d".to_string());
+    assert_eq!("a", a);
+    // an iteration begun later starts at the oldest value held now
+    assert_eq!(vec!["c", "d"], guard.iter().collect::<Vec<_>>());
+}
+
+#[test]
+pub fn test_concurrent() {
+    let r = Arc::new(SyncRingBuffer::new(64));
+    let mut handles = vec![];
+    for t in 0..4 {
+        let r = r.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..10_000 {
+                r.push((t, i));
+            }
+        }));
+    }
+    for _ in 0..2 {
+        let r = r.clone();
+        handles.push(std::thread::spawn(move || {
+            for _ in 0..1_000 {
+                let guard = r.pin();
+                let mut last = [None; 4];
+                let mut n = 0;
+                for (t, i) in &guard {
+                    // each producer's values come oldest first
+                    assert!(last[*t].map_or(true, |l| l < *i));
+                    last[*t] = Some(*i);
+                    n += 1;
+                }
+                assert!(n <= 64);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(40_000, r.pushed());
+    assert_eq!(64, r.to_vec().len());
+}
+
+#[test]
+pub fn test_single_producer() {
+    let r = Arc::new(SyncRingBuffer::new(16));
+    let producer = {
+        let r = r.clone();
+        std::thread::spawn(move || {
+            for i in 0..100_000u64 {
+                r.push(i);
+            }
+        })
+    };
+    while !producer.is_finished() {
+        let v = r.to_vec();
+        // a single producer's values are consecutive
+        for w in v.windows(2) {
+            assert_eq!(w[0] + 1, w[1]);
+        }
+    }
+    producer.join().unwrap();
+    assert_eq!((99_984..100_000).collect::<Vec<_>>(), r.to_vec());
+}
+
+#[test]
+pub fn test_macro_serde() {
+    let r = sync_ring![1, 2, 3];
+    assert_eq!(3, r.capacity());
+    assert_eq!(vec![1, 2, 3], r.to_vec());
+    let r = sync_ring![0; 4];
+    assert_eq!(vec![0, 0, 0, 0], r.to_vec());
+    let r: SyncRingBuffer<i32> = sync_ring![5 =>];
+    assert_eq!(5, r.capacity());
+    assert!(r.is_empty());
+    let r = sync_ring![2 => 1, 2, 3];
+    assert_eq!(vec![2, 3], r.to_vec());
+    assert_eq!("[2, 3]", format!("{:?}", r));
+
+    // the same encoding as a vec, oldest first
+    let bytes = bincode::serialize(&r).unwrap();
+    assert_eq!(bincode::serialize(&vec![2, 3]).unwrap(), bytes);
+    let r: SyncRingBuffer<i32> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(2, r.capacity());
+    assert_eq!(vec![2, 3], r.into_iter().collect::<Vec<_>>());
+    let bytes = bincode::serialize(&Vec::<i32>::new()).unwrap();
+    let r: SyncRingBuffer<i32> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(1, r.capacity());
+}
+
+#[test]
+pub fn test_drop() {
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+    struct D;
+    impl Drop for D {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let r = SyncRingBuffer::new(8);
+    for _ in 0..20 {
+        r.push(D);
+    }
+    drop(r);
+    // overwritten values are released by the epoch collector, eventually
+    for _ in 0..1_000 {
+        if DROPS.load(Ordering::SeqCst) == 20 {
+            break;
+        }
+        crossbeam_epoch::pin().flush();
+        std::thread::yield_now();
+    }
+    assert_eq!(20, DROPS.load(Ordering::SeqCst));
+    let r = SyncRingBuffer::new(8);
+    for _ in 0..3 {
+        r.push(D);
+    }
+    let v = r.into_inner();
+    assert_eq!(20, DROPS.load(Ordering::SeqCst));
+    drop(v);
+    assert_eq!(23, DROPS.load(Ordering::SeqCst));
+}
@@ -0,0 +1,147 @@
+use dark_std::sync::ShardedSyncHashMap;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+pub fn test_empty() {
+    let m: ShardedSyncHashMap<i32, i32> = ShardedSyncHashMap::new();
+    assert_eq!(0, m.len());
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_insert_get() {
+    let m = ShardedSyncHashMap::<String, String>::with_shards(8);
+    assert_eq!(8, m.shards());
+    m.insert("/".to_string(), "1".to_string());
+    m.insert("/js".to_string(), "2".to_string());
+    assert_eq!("1", m.get("/").unwrap().as_str());
+    assert_eq!("2", m.get("/js").unwrap().as_str());
+    assert_eq!(true, m.get("/fn").is_none());
+    assert_eq!("1", m.insert("/".to_string(), "3".to_string()).unwrap().as_str());
+    assert_eq!(2, m.len());
+}
+
+#[test]
+pub fn test_get_mut() {
+    let m = ShardedSyncHashMap::<i32, i32>::new();
+    m.insert(1, 2);
+    *m.get_mut(&1).unwrap() = 0;
+    assert_eq!(0, *m.get(&1).unwrap());
+}
+
+#[test]
+pub fn test_remove() {
+    let m = ShardedSyncHashMap::<i32, i32>::with_shards(4);
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    assert_eq!(100, m.len());
+    assert_eq!(5, *m.remove(&5).unwrap());
+    assert_eq!(true, m.remove(&5).is_none());
+    assert_eq!(99, m.iter().count());
+    m.clear();
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_iter_mut() {
+    let m = ShardedSyncHashMap::<i32, i32>::with_shards(4);
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    for (_, mut v) in m.iter_mut() {
+        *v += 1;
+    }
+    let sum: i32 = m.iter().map(|(_, v)| *v).sum();
+    assert_eq!((1..=100).sum::<i32>(), sum);
+}
+
+#[test]
+pub fn test_concurrent_insert() {
+    let m = Arc::new(ShardedSyncHashMap::<i32, i32>::with_shards(16));
+    let mut handles = vec![];
+    for t in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                m.insert(t * 1000 + i, i);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(4000, m.len());
+}
+
+#[test]
+pub fn test_from_clone() {
+    let m = ShardedSyncHashMap::<i32, i32, RandomState>::with_shards(4);
+    m.insert(1, 1);
+    assert_eq!(format!("{:?}", m), "{1: 1}");
+    let mut map = HashMap::new();
+    map.insert(1, 1);
+    map.insert(2, 2);
+    let m = ShardedSyncHashMap::from(map.clone());
+    assert_eq!(map, m.clone().into_inner());
+}
+
+#[test]
+pub fn test_serde() {
+    let m = ShardedSyncHashMap::<i32, String, RandomState>::with_shards(4);
+    for i in 0..100 {
+        m.insert(i, i.to_string());
+    }
+    m.remove(&0);
+    // bincode needs the exact length of the map up front
+    let bytes = bincode::serialize(&m).unwrap();
+    let m2: ShardedSyncHashMap<i32, String> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(99, m2.len());
+    assert_eq!(m.clone().into_inner(), m2.into_inner());
+}
+
+/// counts the hashers built, every map made from it shares the count
+#[derive(Clone, Default)]
+struct CountingState {
+    built: Arc<AtomicUsize>,
+}
+
+impl BuildHasher for CountingState {
+    type Hasher = std::collections::hash_map::DefaultHasher;
+
+    fn build_hasher(&self) -> Self::Hasher {
+        self.built.fetch_add(1, Ordering::Relaxed);
+        Default::default()
+    }
+}
+
+#[test]
+pub fn test_with_hasher() {
+    let state = CountingState::default();
+    let m = ShardedSyncHashMap::with_shards_and_hasher(4, state.clone());
+    for i in 0..100 {
+        m.insert(i, i);
+    }
+    let before = state.built.load(Ordering::Relaxed);
+    for i in 0..100 {
+        assert_eq!(i, *m.get(&i).unwrap());
+    }
+    // both picking the shard and the lookup inside it hash with `S`
+    assert!(state.built.load(Ordering::Relaxed) - before >= 200);
+
+    let c = m.clone();
+    let before = state.built.load(Ordering::Relaxed);
+    assert_eq!(5, *c.get(&5).unwrap());
+    assert!(state.built.load(Ordering::Relaxed) - before >= 2);
+    let inner: HashMap<i32, i32, CountingState> = c.into_inner();
+    assert_eq!(100, inner.len());
+
+    let bytes = bincode::serialize(&m).unwrap();
+    let d: ShardedSyncHashMap<i32, i32, CountingState> = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(100, d.len());
+    assert_eq!(7, *d.get(&7).unwrap());
+}
@@ -0,0 +1,96 @@
+use dark_std::sync::SyncTtlHashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::Duration;
+
+#[test]
+pub fn test_insert_get() {
+    let m = SyncTtlHashMap::<i32, i32>::new(Duration::from_secs(60));
+    assert_eq!(true, m.insert(1, 1).is_none());
+    assert_eq!(1, *m.insert(1, 2).unwrap());
+    assert_eq!(2, *m.get(&1).unwrap());
+    assert_eq!(true, m.contains_key(&1));
+    assert_eq!(2, *m.remove(&1).unwrap());
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_expire_on_get() {
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let e = evicted.clone();
+    let m = SyncTtlHashMap::<i32, i32>::new(Duration::from_millis(10)).on_evict(move |k, v| {
+        assert_eq!((1, 1), (*k, *v));
+        e.fetch_add(1, Ordering::SeqCst);
+    });
+    m.insert(1, 1);
+    m.insert_with_ttl(2, 2, Duration::from_secs(60));
+    sleep(Duration::from_millis(30));
+    assert_eq!(2, m.len());
+    assert_eq!(true, m.get(&1).is_none());
+    assert_eq!(2, *m.get(&2).unwrap());
+    assert_eq!(1, m.len());
+    assert_eq!(1, evicted.load(Ordering::SeqCst));
+}
+
+#[test]
+pub fn test_expire_on_insert() {
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let e = evicted.clone();
+    let m = SyncTtlHashMap::<i32, i32>::new(Duration::from_millis(10)).on_evict(move |k, v| {
+        assert_eq!((3, 3), (*k, *v));
+        e.fetch_add(1, Ordering::SeqCst);
+    });
+    m.insert_with_ttl(3, 3, Duration::from_millis(1));
+    sleep(Duration::from_millis(5));
+    // an expired value is not handed out as the replaced one, it is evicted
+    assert_eq!(true, m.insert(3, 4).is_none());
+    assert_eq!(1, evicted.load(Ordering::SeqCst));
+    assert_eq!(4, *m.insert(3, 5).unwrap());
+    assert_eq!(1, evicted.load(Ordering::SeqCst));
+}
+
+#[test]
+pub fn test_touch() {
+    let m = SyncTtlHashMap::<i32, i32>::new(Duration::from_millis(20));
+    m.insert(1, 1);
+    assert_eq!(true, m.touch(&1, Duration::from_secs(60)));
+    sleep(Duration::from_millis(40));
+    assert_eq!(1, *m.get(&1).unwrap());
+    assert_eq!(true, m.expires_in(&1).unwrap() > Duration::from_secs(50));
+    assert_eq!(false, m.touch(&2, Duration::from_secs(60)));
+}
+
+#[test]
+pub fn test_purge() {
+    let m = SyncTtlHashMap::<i32, i32>::new(Duration::from_millis(10));
+    for i in 0..10 {
+        m.insert(i, i);
+    }
+    m.insert_with_ttl(10, 10, Duration::from_secs(60));
+    sleep(Duration::from_millis(30));
+    assert_eq!(1, m.iter().count());
+    assert_eq!(10, m.purge());
+    assert_eq!(1, m.len());
+    assert_eq!(format!("{:?}", m), "{10: 10}");
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test]
+pub async fn test_spawn_purge() {
+    let evicted = Arc::new(AtomicUsize::new(0));
+    let e = evicted.clone();
+    let m = Arc::new(
+        SyncTtlHashMap::<i32, i32>::new(Duration::from_millis(10)).on_evict(move |_, _| {
+            e.fetch_add(1, Ordering::SeqCst);
+        }),
+    );
+    let task = m.spawn_purge(Duration::from_millis(5));
+    m.insert(1, 1);
+    m.insert(2, 2);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(0, m.len());
+    assert_eq!(2, evicted.load(Ordering::SeqCst));
+    drop(m);
+    task.await.unwrap();
+}
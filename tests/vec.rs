@@ -32,9 +32,9 @@ pub fn test_push2() {
     m.push("2".to_string());
     m.push("3".to_string());
 
-    assert_eq!(&"1".to_string(), m.get(0).unwrap());
-    assert_eq!(&"2".to_string(), m.get(1).unwrap());
-    assert_eq!(&"3".to_string(), m.get(2).unwrap());
+    assert_eq!("1", *m.get(0).unwrap());
+    assert_eq!("2", *m.get(1).unwrap());
+    assert_eq!("3", *m.get(2).unwrap());
 }
 
 #[test]
@@ -42,7 +42,7 @@ pub fn test_get() {
     let m = SyncVec::<i32>::new();
     m.push(2);
     let g = m.get(0).unwrap();
-    assert_eq!(&2, g);
+    assert_eq!(2, *g);
 }
 
 #[test]
@@ -52,8 +52,9 @@ pub fn test_get_mut() {
     let mut m0 = m.get_mut(0).unwrap();
     *m0 = 1;
     println!("{}", *m0);
-    let g = m.get(0).unwrap();
-    assert_eq!(&1, g);
+    assert_eq!(2, *m.get(0).unwrap());
+    drop(m0);
+    assert_eq!(1, *m.get(0).unwrap());
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
@@ -73,13 +74,14 @@ pub fn test_remove() {
     let m = SyncVec::<A>::new();
     m.push(a);
     let g = m.get(0).unwrap();
+    assert_eq!(A { inner: 0 }, *g);
+    drop(g);
     let rm = m.remove(0).unwrap();
     println!("rm:{:?}", rm);
     drop(rm);
     assert_eq!(true, m.is_empty());
-    assert_eq!(true, m.dirty_ref().is_empty());
-    assert_eq!(None, m.get(0));
-    assert_eq!(&A { inner: 0 }, g);
+    assert_eq!(true, m.read().is_empty());
+    assert_eq!(true, m.get(0).is_none());
 }
 
 #[test]
@@ -118,9 +120,11 @@ pub fn test_iter() {
 pub fn test_iter_mut() {
     let m = SyncVec::<i32>::new();
     m.push(2);
-    for v in m.iter_mut() {
+    for mut v in m.iter_mut() {
         assert_eq!(*v, 2);
+        *v += 1;
     }
+    assert_eq!(*m.read(), [3]);
 }
 
 #[test]
@@ -140,5 +144,237 @@ pub fn test_macro2() {
 #[test]
 pub fn test_macro3() {
     let v = sync_vec![1;2];
-    assert_eq!(v.dirty_ref(), &vec![1; 2]);
+    assert_eq!(*v.read(), [1; 2]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+pub async fn test_async() {
+    let v = Arc::new(SyncVec::<i32>::new());
+    v.push_async(0).await;
+    let mut tasks = vec![];
+    for _ in 0..8 {
+        let v = v.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..100 {
+                let mut iter = v.iter_mut_async().await;
+                tokio::task::yield_now().await;
+                for mut x in &mut iter {
+                    *x += 1;
+                }
+            }
+        }));
+    }
+    for t in tasks {
+        t.await.unwrap();
+    }
+    assert_eq!(Some(&800), v.get(0).as_deref());
+    *v.get_mut_async(0).await.unwrap() += 1;
+    v.insert_async(0, 1).await;
+    v.set_async(0, 2).await;
+    assert_eq!(Some(2), v.remove_async(0).await);
+    assert_eq!(Some(801), v.pop_async().await);
+    v.push_async(1).await;
+    v.clear_async().await;
+    assert_eq!(true, v.is_empty());
+}
+
+#[test]
+pub fn test_snapshot() {
+    let mut v = SyncVec::<i32>::new();
+    v.push(1);
+    v.push(2);
+    let s = v.snapshot();
+    assert_eq!(s.ptr_eq(&v.snapshot()), true);
+    v.push_mut(3);
+    let s2 = v.snapshot();
+    assert_eq!(s2.ptr_eq(&s), false);
+    for mut x in v.iter_mut() {
+        *x *= 10;
+    }
+    assert_eq!(*s, vec![1, 2]);
+    assert_eq!(*s2, vec![1, 2, 3]);
+    assert_eq!(*v.snapshot(), vec![10, 20, 30]);
+    assert_eq!((&s2).into_iter().sum::<i32>(), 6);
+}
+
+#[test]
+pub fn test_snapshot_inside_guard() {
+    let v = SyncVec::<i32>::new();
+    v.push(1);
+    v.push(2);
+    let mut g = v.get_mut(0).unwrap();
+    // the lock is not reentrant, the guard may be writing
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| v.snapshot()));
+    assert_eq!(r.is_err(), true);
+    *g = 100;
+    drop(g);
+    assert_eq!(*v.snapshot(), vec![100, 2]);
+}
+
+#[test]
+pub fn test_bulk() {
+    let v = SyncVec::<i32>::new();
+    v.extend(0..5);
+    v.insert_many(5, vec![5, 6]);
+    v.insert_many(0, [-1]);
+    assert_eq!(*v.read(), [-1, 0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(v.remove_many([0, 3, 3, 100]), vec![-1, 2]);
+    assert_eq!(*v.read(), [0, 1, 3, 4, 5, 6]);
+    v.retain(|x| *x != 1);
+    assert_eq!(v.extract_if(|x| *x % 2 == 1), vec![3, 5]);
+    assert_eq!(v.drain(1..), vec![4, 6]);
+    assert_eq!(v.drain(..), vec![0]);
+    assert_eq!(v.is_empty(), true);
+}
+
+#[test]
+pub fn test_bulk_read_inside() {
+    let v = SyncVec::<i32>::new();
+    v.extend(0..10);
+    // a predicate reading the values may read the vec they filter
+    v.retain(|x| v.contains(&(x + 1)));
+    assert_eq!(v.len(), 9);
+    v.sort_by_key(|x| v.len() as i32 - x);
+    v.dedup();
+    assert_eq!(v.binary_search_by(|x| (*x as usize).cmp(&(v.len() - 1)).reverse()), Ok(0));
+    // one changing them keeps readers waiting, reading inside it would never return
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        v.extract_if(|x| *v.get(0).unwrap() == 8 && *x % 2 == 1)
+    }));
+    assert_eq!(r.is_err(), true);
+    assert_eq!(v.extract_if(|x| *x % 2 == 1), vec![7, 5, 3, 1]);
+    assert_eq!(*v.read(), [8, 6, 4, 2, 0]);
+}
+
+#[test]
+pub fn test_save_load() {
+    let path = std::env::temp_dir().join(format!("dark_std_vec_{}.bin", std::process::id()));
+    let v = SyncVec::new();
+    for i in 0..10 {
+        v.push(i);
+    }
+    v.save_to(&path).unwrap();
+    let loaded = SyncVec::<i32>::load_from(&path).unwrap();
+    assert_eq!(loaded.into_inner(), (0..10).collect::<Vec<_>>());
+    let empty = SyncVec::<i32>::new();
+    empty.save_to(&path).unwrap();
+    assert!(SyncVec::<i32>::load_from(&path).unwrap().is_empty());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn test_ordered() {
+    let v = sync_vec![5, 3, 1, 4, 1, 2];
+    v.sort();
+    assert_eq!(*v.read(), [1, 1, 2, 3, 4, 5]);
+    v.dedup();
+    assert_eq!(Ok(3), v.binary_search(&4));
+    assert_eq!(Err(0), v.binary_search_by(|x| x.cmp(&0)));
+    assert_eq!(2, v.insert_sorted(2));
+    assert_eq!(6, v.insert_sorted(9));
+    assert_eq!(*v.read(), [1, 2, 2, 3, 4, 5, 9]);
+    v.sort_unstable_by(|a, b| b.cmp(a));
+    assert_eq!(*v.read(), [9, 5, 4, 3, 2, 2, 1]);
+    v.sort_by_key(|x| x % 3);
+    assert_eq!(*v.read(), [9, 3, 4, 1, 5, 2, 2]);
+    v.dedup_by_key(|x| *x % 3);
+    assert_eq!(*v.read(), [9, 4, 5]);
+    v.sort_by(|a, b| a.cmp(b));
+    v.swap(0, 2);
+    assert_eq!(*v.read(), [9, 5, 4]);
+    assert_eq!(Some(9), v.swap_remove(0));
+    assert_eq!(None, v.swap_remove(2));
+    assert_eq!(*v.read(), [4, 5]);
+    v.resize_with(4, || 0);
+    assert_eq!(*v.read(), [4, 5, 0, 0]);
+    v.rotate_left(1);
+    assert_eq!(*v.read(), [5, 0, 0, 4]);
+    v.rotate_right(1);
+    v.reverse();
+    assert_eq!(*v.read(), [0, 0, 5, 4]);
+    v.truncate(1);
+    assert_eq!(*v.read(), [0]);
+}
+
+#[test]
+pub fn test_insert_sorted_concurrent() {
+    let v = Arc::new(SyncVec::new());
+    let mut handles = vec![];
+    for t in 0..4 {
+        let v = v.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..250 {
+                v.insert_sorted((i * 7 + t * 13) % 101);
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    let m = v.snapshot();
+    assert_eq!(1000, m.len());
+    assert!(m.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+pub fn test_fallible() {
+    let v = sync_vec![1, 2];
+    assert_eq!(Some(2), v.set(1, 3));
+    assert_eq!(Ok(3), v.replace(1, 4));
+    let e = v.replace(2, 5).unwrap_err();
+    assert_eq!("index out of bounds: the len is 2 but the index is 2", e.to_string());
+    assert!(v.try_set(2, 5).is_err());
+    assert_eq!(Ok(()), v.try_set(0, 0));
+    assert_eq!(Ok(()), v.try_insert(2, 6));
+    let e = v.try_insert(4, 7).unwrap_err();
+    assert_eq!("insertion index (is 4) should be <= len (is 3)", e.to_string());
+    assert_eq!(*v.read(), [0, 4, 6]);
+    assert_eq!(6, v.read()[2]);
+    assert_eq!(6, *v.get_uncheck(2));
+}
+
+#[test]
+#[should_panic(expected = "index out of bounds: the len is 1 but the index is 1")]
+pub fn test_index_out_of_bounds() {
+    let v = sync_vec![1];
+    let _ = v.get_uncheck(1);
+}
+
+#[test]
+pub fn test_read_while_truncate() {
+    let v = Arc::new(SyncVec::<String>::new());
+    let writer = {
+        let v = v.clone();
+        std::thread::spawn(move || {
+            for _ in 0..1000 {
+                v.extend((0..16).map(|i| i.to_string()));
+                v.truncate(4);
+                v.retain(|x| x != "3");
+                v.clear();
+            }
+        })
+    };
+    for _ in 0..10000 {
+        if let Some(x) = v.get(2) {
+            assert_eq!(*x, "2");
+        }
+        for x in v.read().iter() {
+            assert!(x.parse::<i32>().is_ok());
+        }
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+pub fn test_write_inside_read() {
+    let v = sync_vec![1, 2];
+    let x = v.get(0).unwrap();
+    // the write would wait for the borrow of this very thread
+    let r = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| v.push(*x)));
+    assert_eq!(r.is_err(), true);
+    drop(x);
+    // a guard made inside the arguments lives until the end of the statement, copy the value out first
+    let x = *v.get(0).unwrap();
+    v.push(x + 2);
+    assert_eq!(*v.read(), [1, 2, 3]);
 }
@@ -0,0 +1,90 @@
+use dark_std::sync::SyncVecDeque;
+use std::sync::Arc;
+
+#[test]
+pub fn test_push_pop() {
+    let q = SyncVecDeque::new();
+    assert_eq!(true, q.is_empty());
+    assert_eq!(None, q.pop_front());
+    assert_eq!(None, q.pop_back());
+    q.push_back(2);
+    q.push_back(3);
+    q.push_front(1);
+    assert_eq!(3, q.len());
+    assert_eq!(Some(1), q.front());
+    assert_eq!(Some(3), q.back());
+    assert_eq!(vec![1, 2, 3], q.to_vec());
+    assert_eq!(Some(3), q.pop_back());
+    assert_eq!(Some(1), q.pop_front());
+    assert_eq!(1, q.len());
+    q.extend(vec![4, 5, 6]);
+    q.retain(|v| v % 2 == 0);
+    assert_eq!("[2, 4, 6]", format!("{:?}", q));
+    assert_eq!(vec![2, 4, 6], q.drain());
+    assert_eq!(true, q.is_empty());
+    q.push_back(7);
+    q.clear();
+    assert_eq!(0, q.len());
+}
+
+#[test]
+pub fn test_concurrent() {
+    let q = Arc::new(SyncVecDeque::new());
+    let mut handles = vec![];
+    for t in 0..4 {
+        let q = q.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                if t % 2 == 0 {
+                    q.push_back(i);
+                } else {
+                    q.push_front(i);
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(4000, q.len());
+    let mut handles = vec![];
+    for t in 0..4 {
+        let q = q.clone();
+        handles.push(std::thread::spawn(move || {
+            let mut n = 0;
+            while if t % 2 == 0 { q.pop_front() } else { q.pop_back() }.is_some() {
+                n += 1;
+            }
+            n
+        }));
+    }
+    let popped: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(4000, popped);
+    assert_eq!(true, q.is_empty());
+}
+
+#[test]
+pub fn test_from_serde() {
+    let q = SyncVecDeque::from(vec![1, 2, 3]);
+    fn is_serialize<T: serde::Serialize + serde::de::DeserializeOwned + Send + Sync>(_: &T) {}
+    is_serialize(&q);
+    assert_eq!(vec![1, 2, 3], q.into_iter().collect::<Vec<_>>());
+}
+
+#[tokio::test]
+pub async fn test_async() {
+    let q = SyncVecDeque::new_arc();
+    let mut tasks = vec![];
+    for i in 0..10 {
+        let q = q.clone();
+        tasks.push(tokio::spawn(async move { q.push_back_async(i).await }));
+    }
+    for t in tasks {
+        t.await.unwrap();
+    }
+    let mut n = 0;
+    while q.pop_front_async().await.is_some() {
+        n += 1;
+    }
+    assert_eq!(10, n);
+}
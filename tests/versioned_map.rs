@@ -0,0 +1,75 @@
+use dark_std::sync::{SyncVersionedHashMap, Versioned};
+use std::sync::Arc;
+
+#[test]
+pub fn test_revision() {
+    let m = SyncVersionedHashMap::<i32, i32>::new();
+    assert_eq!(0, m.revision());
+    assert_eq!(1, m.insert(1, 1));
+    assert_eq!(2, m.insert(2, 2));
+    assert_eq!(3, m.insert(1, 10));
+    assert_eq!((3, 10), m.get_versioned(&1).map(|(rev, v)| (rev, *v)).unwrap());
+    assert_eq!(10, *m.get(&1).unwrap());
+    assert_eq!(2, *m.remove(&2).unwrap());
+    assert_eq!(4, m.revision());
+    assert_eq!(true, m.remove(&2).is_none());
+    assert_eq!(4, m.revision());
+    m.clear();
+    assert_eq!(5, m.revision());
+    assert_eq!(true, m.is_empty());
+}
+
+#[test]
+pub fn test_insert_if_rev() {
+    let m = SyncVersionedHashMap::<&str, i32>::new();
+    assert_eq!(Err((0, 1)), m.insert_if_rev("a", 1, 7));
+    let rev = m.insert_if_rev("a", 1, 0).unwrap();
+    assert_eq!(Err((rev, 2)), m.insert_if_rev("a", 2, 0));
+    let rev2 = m.insert_if_rev("a", 2, rev).unwrap();
+    assert_eq!(true, rev2 > rev);
+    assert_eq!(Err((rev2, 3)), m.insert_if_rev("a", 3, rev));
+    assert_eq!(2, *m.get(&"a").unwrap());
+}
+
+#[test]
+pub fn test_insert_if_rev_concurrent() {
+    let m = Arc::new(SyncVersionedHashMap::<i32, i32>::new());
+    m.insert(0, 0);
+    let mut handles = vec![];
+    for _ in 0..4 {
+        let m = m.clone();
+        handles.push(std::thread::spawn(move || {
+            for _ in 0..500 {
+                loop {
+                    let (rev, v) = m.get_versioned(&0).map(|(rev, v)| (rev, *v)).unwrap();
+                    if m.insert_if_rev(0, v + 1, rev).is_ok() {
+                        break;
+                    }
+                }
+            }
+        }));
+    }
+    for h in handles {
+        h.join().unwrap();
+    }
+    assert_eq!(2000, *m.get(&0).unwrap());
+    assert_eq!(2001, m.revision());
+}
+
+#[test]
+pub fn test_iter_snapshot() {
+    let m = SyncVersionedHashMap::<i32, i32>::new();
+    m.insert(1, 1);
+    m.insert(2, 2);
+    let iter = m.iter();
+    assert_eq!(2, iter.revision());
+    let mut items: Vec<(i32, u64, i32)> = iter.map(|(k, rev, v)| (*k, rev, *v)).collect();
+    items.sort();
+    assert_eq!(vec![(1, 1, 1), (2, 2, 2)], items);
+
+    let (rev, snapshot) = m.snapshot();
+    m.insert(1, 10);
+    assert_eq!(2, rev);
+    assert_eq!(Some(&Versioned { rev: 1, value: 1 }), snapshot.get(&1));
+    assert_eq!(format!("{:?}", m.snapshot().1.get(&1).unwrap()), "Versioned { rev: 3, value: 10 }");
+}